@@ -0,0 +1,175 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use silentdragonpaper::bulk::stream_wallet;
+use silentdragonpaper::export::{write_entries, CsvWriter, EntryWriter, JsonLinesWriter};
+use silentdragonpaper::paper::generate_seed;
+use silentdragonpaper::pdf::PdfPartsWriter;
+use silentdragonpaper::secure_file::SecretFile;
+use std::io;
+use std::io::prelude::*;
+use std::time::Instant;
+
+use crate::common;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("bulk")
+        .about("Generate a very large batch from one HD seed, writing every address as soon as it is derived")
+        .arg(Arg::with_name("output")
+                .index(1)
+                .required(true)
+                .help("Name of output file. PDFs are written to several files, named like output-0001.pdf, with the pages numbered through the whole batch. If the batch fails, the PDF files already written are removed"))
+        .arg(Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .help("What format to generate the output in: JSON Lines (one wallet entry per line), csv or pdf")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["jsonl", "csv", "pdf"])
+                .default_value("jsonl"))
+        .arg(Arg::with_name("t_addresses")
+                .short("t")
+                .long("taddrs")
+                .help("Number of t-addresses to generate")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|i:String| match i.parse::<u32>() {
+                        Ok(_)   => return Ok(()),
+                        Err(_)  => return Err(format!("Number of addresses '{}' is not a number", i))
+                }))
+        .arg(Arg::with_name("z_addresses")
+                .short("z")
+                .long("zaddrs")
+                .help("Number of z-addresses (Sapling) to generate")
+                .takes_value(true)
+                .default_value("1")
+                .validator(|i:String| match i.parse::<u32>() {
+                        Ok(_)   => return Ok(()),
+                        Err(_)  => return Err(format!("Number of addresses '{}' is not a number", i))
+                }))
+        .arg(Arg::with_name("per_file")
+                .long("per-file")
+                .help("Number of addresses (and pages) in every PDF file")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("500")
+                .validator(|i:String| match i.parse::<usize>() {
+                        Ok(n) if n > 0 => return Ok(()),
+                        _              => return Err(format!("Number of pages '{}' is not a positive number", i))
+                }))
+        .arg(common::threads_arg())
+        .arg(common::seed_length_arg())
+        .arg(common::path_arg())
+        .arg(common::account_arg())
+        .arg(common::start_index_arg())
+        .arg(common::entropy_arg())
+        .arg(common::paper_arg())
+        .arg(common::protect_keys_arg())
+        .arg(common::key_kdf_cost_arg())
+        .arg(common::key_passphrase_file_arg())
+        .arg(common::columns_arg())
+        .arg(common::label_arg())
+        .arg(common::force_arg())
+        .arg(common::refuse_swap_arg())
+        .arg(common::require_airgap_arg())
+}
+
+pub fn run(matches: &ArgMatches) {
+    let filename = matches.value_of("output").unwrap();
+    let format   = matches.value_of("format").unwrap();
+    let force    = matches.is_present("force");
+
+    let t_addresses = matches.value_of("t_addresses").unwrap().parse::<u32>().unwrap();
+    let z_addresses = matches.value_of("z_addresses").unwrap().parse::<u32>().unwrap();
+    let threads     = common::threads(matches);
+    let per_file    = matches.value_of("per_file").unwrap().parse::<usize>().unwrap();
+    let seed_length = common::seed_length(matches);
+
+    let path = match common::derivation_path(matches) {
+        Ok(p)  => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let options = match common::pdf_options(matches) {
+        Ok(o)  => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let export = match common::export_options(matches) {
+        Ok(e)  => e,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    // The keys are only encrypted on paper
+    if format == "pdf" {
+        common::report_key_encryption(&options, z_addresses as u64);
+    }
+
+    if common::check_airgap(matches.is_present("require_airgap")).is_none() {
+        return;
+    }
+
+    let _session = match common::start_session(matches.is_present("refuse_swap")) {
+        Some(s) => s,
+        None    => return
+    };
+
+    let entropy = common::user_entropy(matches);
+
+    let entries = match generate_seed(seed_length, &entropy).and_then(|(seed, _)| stream_wallet(&seed, z_addresses, t_addresses, &path, threads)) {
+        Ok(s)  => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    print!("Generating {} z-addresses and {} t-addresses into {:?}...", z_addresses, t_addresses, filename);
+    io::stdout().flush().ok();
+    let started = Instant::now();
+
+    // Every entry is written as soon as it is derived, so the batch is never in memory as a whole
+    let mut removed = 0;
+    let result = if format == "pdf" {
+        PdfPartsWriter::new(filename, per_file, z_addresses as u64 + t_addresses as u64, &options, force).and_then(|mut writer| {
+            if let Err(e) = write_entries(entries, &mut writer) {
+                removed = writer.abort();
+                return Err(e);
+            }
+            Ok(format!("{} PDF files", writer.parts()))
+        })
+    } else {
+        SecretFile::create(filename, force).and_then(|mut file| {
+            let count = {
+                let mut writer: Box<dyn EntryWriter> = match format {
+                    "csv" => Box::new(CsvWriter::new(&mut file, filename, &export)),
+                    _     => Box::new(JsonLinesWriter::new(&mut file, filename, &export))
+                };
+                write_entries(entries, writer.as_mut())?
+            };
+            file.commit()?;
+            Ok(format!("{} entries", count))
+        })
+    };
+
+    match result {
+        Ok(written) => {
+            println!("[OK]\nWrote {}", written);
+            common::report_throughput(z_addresses as u64 + t_addresses as u64, started.elapsed(), threads);
+        },
+        Err(e)      => {
+            eprintln!("[ERROR]");
+            eprintln!("{}", e);
+            if removed > 0 {
+                eprintln!("Removed the {} PDF {} written before the error", removed, if removed == 1 { "file" } else { "files" });
+            }
+        }
+    };
+}
@@ -0,0 +1,162 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use silentdragonpaper::paper::wallet_from_seed;
+use silentdragonpaper::{codex32, slip39};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+
+use crate::common;
+use crate::version;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("combine")
+        .about("Recover a wallet from its SLIP-39 or codex32 shares, written with --shares")
+        .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("File with the shares, one per line. If absent, the shares are read from stdin"))
+        .arg(Arg::with_name("output")
+                .index(1)
+                .help("Name of output file."))
+        .arg(Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .help("What format to generate the output in: json, pdf, csv or jsonl (JSON Lines, one wallet entry per line)")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["pdf", "json", "csv", "jsonl"])
+                .default_value("json"))
+        .arg(Arg::with_name("t_addresses")
+                .short("t")
+                .long("taddrs")
+                .help("Number of t-addresses to derive again. This is printed on the shares")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|i:String| match i.parse::<u32>() {
+                        Ok(_)   => return Ok(()),
+                        Err(_)  => return Err(format!("Number of addresses '{}' is not a number", i))
+                }))
+        .arg(Arg::with_name("z_addresses")
+                .short("z")
+                .long("zaddrs")
+                .help("Number of z-addresses to derive again. This is printed on the shares")
+                .takes_value(true)
+                .default_value("1")
+                .validator(|i:String| match i.parse::<u32>() {
+                        Ok(_)   => return Ok(()),
+                        Err(_)  => return Err(format!("Number of addresses '{}' is not a number", i))
+                }))
+        .arg(common::threads_arg())
+        .arg(common::path_arg())
+        .arg(common::account_arg())
+        .arg(common::start_index_arg())
+        .arg(common::paper_arg())
+        .arg(common::protect_keys_arg())
+        .arg(common::key_kdf_cost_arg())
+        .arg(common::key_passphrase_file_arg())
+        .arg(common::encrypt_arg())
+        .arg(common::passphrase_file_arg())
+        .arg(common::recipient_arg())
+        .arg(common::attach_pdf_arg())
+        .arg(common::columns_arg())
+        .arg(common::label_arg())
+        .arg(common::force_arg())
+        .arg(common::refuse_swap_arg())
+}
+
+pub fn run(matches: &ArgMatches) {
+    let filename = matches.value_of("output");
+    let format   = matches.value_of("format").unwrap();
+
+    let t_addresses = matches.value_of("t_addresses").unwrap().parse::<u32>().unwrap();
+    let z_addresses = matches.value_of("z_addresses").unwrap().parse::<u32>().unwrap();
+
+    let path = match common::derivation_path(matches) {
+        Ok(p)  => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let options = match common::pdf_options(matches) {
+        Ok(o)  => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let export = match common::export_options(matches) {
+        Ok(e)  => e,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let encryption = match common::encryption(matches) {
+        Ok(e)  => e,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let _session = match common::start_session(matches.is_present("refuse_swap")) {
+        Some(s) => s,
+        None    => return
+    };
+
+    let text = match matches.value_of("input") {
+        Some(input) => match fs::read_to_string(input) {
+            Ok(t)  => t,
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                return;
+            }
+        },
+        None        => {
+            println!("Type or paste the shares, one per line, and press [CTRL+D] when done.");
+            let mut buffer = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut buffer) {
+                eprintln!("{}", e);
+                return;
+            }
+            buffer
+        }
+    };
+
+    let mnemonics: Vec<&str> = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).collect();
+
+    let combined = if mnemonics.iter().all(|m| codex32::is_codex32(m)) {
+        codex32::combine_shares(&mnemonics).map(|s| (s, "codex32"))
+    } else {
+        slip39::combine_mnemonics(&mnemonics, "").map(|s| (s, "SLIP-39"))
+    };
+
+    let (seed, scheme) = match combined {
+        Ok(s)  => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    println!("Combined {} {} shares", mnemonics.len(), scheme);
+
+    let mut wallet = match wallet_from_seed(&seed, z_addresses, t_addresses, &path, common::threads(matches), format!("{} shares", scheme)) {
+        Ok(w)  => w,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    if let Some(m) = wallet.metadata.as_mut() {
+        m.tool_version = format!("SilentDragonPaper {}", version::version());
+    }
+
+    common::write_wallet(&wallet, format, filename, &options, &export, &encryption, matches.is_present("attach_pdf"), matches.is_present("force"));
+}
@@ -0,0 +1,606 @@
+use clap::{Arg, ArgMatches};
+use silentdragonpaper::airgap;
+use silentdragonpaper::airgap::AirgapStatus;
+use silentdragonpaper::encryption::{encrypt_to_recipients, encrypt_with_passphrase, read_identity_file, read_recipients_file};
+use silentdragonpaper::encryption::{Attachment, DecryptionKey, EncryptionKind, KdfParams, Recipient};
+use silentdragonpaper::error::Error;
+use silentdragonpaper::export::{parse_columns, write_batch, Column, ExportOptions, DEFAULT_COLUMNS};
+use silentdragonpaper::hardened::{HardenedSession, SwapPolicy};
+use silentdragonpaper::paper::{DerivationPath, DEFAULT_SEED_LENGTH, MAX_SEED_LENGTH, MIN_SEED_LENGTH};
+use silentdragonpaper::pdf;
+use silentdragonpaper::pdf::{PaperSize, PdfOptions};
+use silentdragonpaper::secure_file::{write_secret_file, SecretFile};
+use silentdragonpaper::slip39::GroupSpec;
+use silentdragonpaper::wallet::WalletBatch;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::Duration;
+
+/// How to split the HD seed into shares, from --shares
+pub enum ShareSpec {
+    /// Any `group_threshold` of the groups of SLIP-39 shares
+    Slip39 { group_threshold: u8, groups: Vec<GroupSpec> },
+    /// codex32 shares, which have no groups
+    Codex32(GroupSpec),
+}
+
+/// How the JSON wallet file is protected
+pub enum Encryption {
+    Plaintext,
+    /// Encrypted with a key derived from this passphrase
+    Passphrase(String),
+    /// Encrypted to the public keys of these recipients
+    Recipients(Vec<Recipient>),
+}
+
+/// Lock memory and disable core dumps before any secret is created or read, printing any protections
+/// that couldn't be applied. Returns None if the session couldn't be started.
+pub fn start_session(refuse_swap: bool) -> Option<HardenedSession> {
+    let swap_policy = if refuse_swap { SwapPolicy::Refuse } else { SwapPolicy::Warn };
+    match HardenedSession::begin(swap_policy) {
+        Ok(s)  => {
+            for w in s.warnings() {
+                eprintln!("WARNING: {}", w);
+            }
+            Some(s)
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+/// Check that we're offline, printing a warning if we aren't. With `require`, it is an error instead, and
+/// None is returned.
+pub fn check_airgap(require: bool) -> Option<AirgapStatus> {
+    let status = airgap::check_airgap();
+    if !status.is_airgapped() {
+        if require {
+            eprintln!("{}", status.summary());
+            eprintln!("Disconnect from all networks (or run with 'unshare -n') and try again.");
+            return None;
+        }
+        eprintln!("WARNING: {}", status.summary());
+    }
+    Some(status)
+}
+
+/// The user's entropy from --entropy, or typed in if it wasn't given
+pub fn user_entropy(matches: &ArgMatches) -> Vec<u8> {
+    match matches.value_of("entropy") {
+        Some(e) => e.as_bytes().to_vec(),
+        None    => {
+            println!("Provide additional entropy for generating random numbers.
+Type in a string of random characters, press [ENTER] when done.");
+            let mut buffer = String::new();
+            io::stdin().lock().read_line(&mut buffer).unwrap();
+
+            buffer.into_bytes()
+        }
+    }
+}
+
+pub fn entropy_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("entropy")
+        .short("e")
+        .long("entropy")
+        .takes_value(true)
+        .help("Provide additional entropy to the random number generator. Any random string, containing 32-64 characters")
+}
+
+pub fn require_airgap_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("require_airgap")
+        .long("require-airgap")
+        .help("Refuse to generate keys if this computer has any active network interfaces. By default, this is only a warning")
+}
+
+pub fn refuse_swap_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("refuse_swap")
+        .long("refuse-swap")
+        .help("Refuse to run if swap is active, since key material could be written to disk. By default, this is only a warning")
+}
+
+pub fn columns_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("columns")
+        .long("columns")
+        .takes_value(true)
+        .value_name("COLUMNS")
+        .help("The columns of the csv or jsonl output, in order, separated by commas. From num, type, address, private_key, viewing_key, path and label. The default is all of them but the label, which is added with --label")
+}
+
+pub fn label_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("label")
+        .long("label")
+        .takes_value(true)
+        .value_name("LABEL")
+        .help("Write this label in the label column of every csv or jsonl row, to tell batches apart in an inventory")
+}
+
+pub fn force_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("force")
+        .long("force")
+        .help("Overwrite the output file if it already exists")
+}
+
+pub fn paper_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("paper")
+        .long("paper")
+        .help("Paper size of the PDF")
+        .takes_value(true)
+        .possible_values(&["a4", "letter"])
+        .default_value("a4")
+}
+
+pub fn encrypt_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("encrypt")
+        .long("encrypt")
+        .help("Encrypt the JSON wallet file with a passphrase. You will be asked for the passphrase, unless --passphrase-file is given")
+}
+
+pub fn passphrase_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("passphrase_file")
+        .long("passphrase-file")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("Read the passphrase of the encrypted wallet file or key from the first line of this file, instead of asking for it")
+}
+
+pub fn recipient_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("recipient")
+        .long("recipient")
+        .takes_value(true)
+        .value_name("FILE")
+        .multiple(true)
+        .number_of_values(1)
+        .conflicts_with("encrypt")
+        .help("Encrypt the JSON wallet file to the public keys in this recipients file, written by the keygen subcommand. Can be given more than once")
+}
+
+pub fn attach_pdf_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("attach_pdf")
+        .long("attach-pdf")
+        .help("Also render the PDF, and encrypt it into the JSON wallet file along with the keys. Needs --encrypt or --recipient")
+}
+
+pub fn protect_keys_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("protect_keys")
+        .long("protect-keys")
+        .help("Print the private keys in the PDF encrypted with a passphrase (BIP38 for t-addresses), and leave out the HD seed, so the paper alone can't spend the funds. You will be asked for the passphrase, unless --key-passphrase-file is given")
+}
+
+pub fn key_kdf_cost_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("key_kdf_cost")
+        .long("key-kdf-cost")
+        .takes_value(true)
+        .value_name("LOG_N")
+        .requires("protect_keys")
+        .validator(|i:String| match i.parse::<u8>() {
+                Ok(n) if n >= 10 && n <= 20 => return Ok(()),
+                _                           => return Err(format!("The scrypt cost '{}' is not a number from 10 to 20", i))
+        })
+        .help("log2 of the scrypt cost of every spending key encrypted with --protect-keys. Every z-address is encrypted on its own, so this is paid once per address. The default of 17 needs 128 MiB and about a second per key on a laptop. Every step down halves the memory and the time, and makes guessing the passphrase twice as cheap: 14 needs 16 MiB and about 0.1 seconds")
+}
+
+pub fn key_passphrase_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("key_passphrase_file")
+        .long("key-passphrase-file")
+        .takes_value(true)
+        .value_name("FILE")
+        .requires("protect_keys")
+        .help("Read the passphrase for --protect-keys from the first line of this file, instead of asking for it")
+}
+
+pub fn shares_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("shares")
+        .long("shares")
+        .takes_value(true)
+        .value_name("KofN")
+        .multiple(true)
+        .number_of_values(1)
+        .conflicts_with_all(&["nohd", "vanity_prefix", "encrypt", "recipient", "attach_pdf", "protect_keys"])
+        .help("Split the HD seed into SLIP-39 shares, any K of N of which recover the wallet, like 2of3. The PDF has the addresses and one page per share, but no private keys. Give it more than once for groups of shares")
+}
+
+pub fn group_threshold_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("group_threshold")
+        .long("group-threshold")
+        .takes_value(true)
+        .value_name("N")
+        .requires("shares")
+        .help("How many of the groups of --shares are needed to recover the wallet. Needed when there is more than one group")
+}
+
+pub fn mnemonic_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("mnemonic")
+        .long("mnemonic")
+        .conflicts_with_all(&["nohd", "vanity_prefix", "shares"])
+        .help("Derive the HD seed from a new 24 word BIP39 mnemonic, which the PDF prints on a page of its own")
+}
+
+pub fn seed_passphrase_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("seed_passphrase")
+        .long("seed-passphrase")
+        .help("Add a BIP39 passphrase (a \"25th word\") to the mnemonic. The PDF says that it's needed, but doesn't print it. You will be asked for the passphrase, unless --seed-passphrase-file is given")
+}
+
+pub fn seed_passphrase_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("seed_passphrase_file")
+        .long("seed-passphrase-file")
+        .takes_value(true)
+        .value_name("FILE")
+        .requires("seed_passphrase")
+        .help("Read the passphrase for --seed-passphrase from the first line of this file, instead of asking for it")
+}
+
+pub fn share_format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("share_format")
+        .long("share-format")
+        .takes_value(true)
+        .value_name("FORMAT")
+        .possible_values(&["slip39", "codex32"])
+        .requires("shares")
+        .help("Write the --shares as SLIP-39 mnemonics (the default), or as codex32 (BIP93) strings, which can be checked and combined by hand with the codex32 worksheets")
+}
+
+pub fn seed_length_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("seed_length")
+        .long("seed-length")
+        .takes_value(true)
+        .value_name("BYTES")
+        .help("Length of the HD seed, from 32 to 252 bytes. The default is 32")
+        .validator(|i:String| match i.parse::<usize>() {
+                Ok(n) if n >= MIN_SEED_LENGTH && n <= MAX_SEED_LENGTH => return Ok(()),
+                _                                                     => return Err(format!("Seed length '{}' is not a number from {} to {}", i, MIN_SEED_LENGTH, MAX_SEED_LENGTH))
+        })
+}
+
+pub fn threads_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("threads")
+        .long("threads")
+        .takes_value(true)
+        .value_name("N")
+        .help("Number of threads to derive the addresses on. Set this to the number of CPUs you have. The addresses are the same on any number of threads. The default is 1")
+        .validator(|i:String| match i.parse::<u32>() {
+                Ok(n) if n > 0 => return Ok(()),
+                _              => return Err(format!("Number of threads '{}' is not a positive number", i))
+        })
+}
+
+pub fn path_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("path")
+        .long("path")
+        .takes_value(true)
+        .value_name("PATH")
+        .conflicts_with("account")
+        .help("Derive the z-addresses below this path, at PATH/index', instead of at m/32'/133'/index'. Every level must be hardened, like \"m/32'/133'/7'\"")
+}
+
+pub fn account_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("account")
+        .long("account")
+        .takes_value(true)
+        .value_name("N")
+        .help("Derive the z-addresses of this ZIP-32 account, at m/32'/133'/N'/index'")
+}
+
+pub fn start_index_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("start_index")
+        .long("start-index")
+        .takes_value(true)
+        .value_name("N")
+        .help("The index of the first z-address, so that batches from the same seed don't collide. The default is 0")
+}
+
+pub fn identity_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("identity")
+        .long("identity")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("The identity file to decrypt a wallet file that was encrypted to its public key")
+}
+
+/// The passphrase of the wallet file, from --passphrase-file or typed on the terminal without echoing it.
+/// A new passphrase has to be typed twice.
+pub fn passphrase(matches: &ArgMatches, confirm: bool) -> Result<String, String> {
+    read_passphrase(matches.value_of("passphrase_file"), "Passphrase", confirm)
+}
+
+/// The passphrase that protects the private keys printed on paper, from --key-passphrase-file or typed on
+/// the terminal
+pub fn key_passphrase(matches: &ArgMatches, confirm: bool) -> Result<String, String> {
+    read_passphrase(matches.value_of("key_passphrase_file"), "Private key passphrase", confirm)
+}
+
+/// The BIP39 passphrase of the mnemonic, which is empty without --seed-passphrase
+pub fn seed_passphrase(matches: &ArgMatches, confirm: bool) -> Result<String, String> {
+    if !matches.is_present("seed_passphrase") {
+        return Ok(String::new());
+    }
+    read_passphrase(matches.value_of("seed_passphrase_file"), "BIP39 passphrase", confirm)
+}
+
+/// Read the first line of `filename`, or ask for the passphrase on the terminal with `prompt`
+fn read_passphrase(filename: Option<&str>, prompt: &str, confirm: bool) -> Result<String, String> {
+    if let Some(filename) = filename {
+        let contents = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        return Ok(contents.lines().next().unwrap_or("").to_string());
+    }
+
+    let passphrase = rpassword::read_password_from_tty(Some(&format!("{}: ", prompt))).map_err(|e| format!("Couldn't read the passphrase: {}", e))?;
+    if confirm {
+        if passphrase.is_empty() {
+            return Err("The passphrase can't be empty".to_string());
+        }
+
+        let again = rpassword::read_password_from_tty(Some(&format!("{} again: ", prompt))).map_err(|e| format!("Couldn't read the passphrase: {}", e))?;
+        if again != passphrase {
+            return Err("The passphrases don't match".to_string());
+        }
+    }
+
+    Ok(passphrase)
+}
+
+/// How to protect the output, from the command line. This asks for the passphrase, so call it before
+/// generating any keys.
+pub fn encryption(matches: &ArgMatches) -> Result<Encryption, String> {
+    let encrypted = matches.is_present("encrypt") || matches.is_present("recipient");
+
+    if !encrypted {
+        if matches.is_present("attach_pdf") {
+            return Err("--attach-pdf needs --encrypt or --recipient".to_string());
+        }
+        return Ok(Encryption::Plaintext);
+    }
+
+    if matches.value_of("format") != Some("json") {
+        return Err("Only JSON output can be encrypted. Use --attach-pdf to encrypt the PDF along with it".to_string());
+    }
+
+    if let Some(filenames) = matches.values_of("recipient") {
+        let mut recipients = vec![];
+        for filename in filenames {
+            recipients.extend(read_recipients_file(filename).map_err(|e| e.to_string())?);
+        }
+        return Ok(Encryption::Recipients(recipients));
+    }
+
+    Ok(Encryption::Passphrase(passphrase(matches, true)?))
+}
+
+/// The groups of SLIP-39 shares and the group threshold from the command line, if the seed is to be split
+pub fn share_spec(matches: &ArgMatches) -> Result<Option<ShareSpec>, String> {
+    let groups = match matches.values_of("shares") {
+        Some(g) => g.map(|g| g.parse::<GroupSpec>()).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?,
+        None    => return Ok(None)
+    };
+
+    if matches.value_of("format") != Some("pdf") {
+        return Err("Shares are only written to PDFs. Use --format pdf".to_string());
+    }
+
+    if matches.value_of("share_format") == Some("codex32") {
+        if groups.len() > 1 || matches.is_present("group_threshold") {
+            return Err("codex32 shares don't have groups. Give --shares once".to_string());
+        }
+        return Ok(Some(ShareSpec::Codex32(groups[0])));
+    }
+
+    let group_threshold = match matches.value_of("group_threshold") {
+        Some(t)                   => t.parse::<u8>().map_err(|_| format!("The group threshold '{}' is not a number", t))?,
+        None if groups.len() == 1 => 1,
+        None                      => return Err("Use --group-threshold to say how many of the groups of shares are needed".to_string())
+    };
+
+    Ok(Some(ShareSpec::Slip39 { group_threshold, groups }))
+}
+
+/// The length of the HD seed from --seed-length, which the validator already checked
+pub fn seed_length(matches: &ArgMatches) -> usize {
+    matches.value_of("seed_length").map(|l| l.parse::<usize>().unwrap()).unwrap_or(DEFAULT_SEED_LENGTH)
+}
+
+pub fn threads(matches: &ArgMatches) -> u32 {
+    matches.value_of("threads").map(|n| n.parse::<u32>().unwrap()).unwrap_or(1)
+}
+
+/// Print how fast `count` addresses were derived, so that the hardware for large batches can be sized
+pub fn report_throughput(count: u64, elapsed: Duration, threads: u32) {
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    let rate = if secs > 0.0 { count as f64 / secs } else { 0.0 };
+    let plural = if threads == 1 { "" } else { "s" };
+    println!("Derived {} addresses in {:.1} seconds on {} thread{} ({:.0} addresses/sec)", count, secs, threads, plural, rate);
+}
+
+/// With --protect-keys, print how long encrypting `zcount` spending keys will take, from the time of one key
+/// derivation at the chosen scrypt cost
+pub fn report_key_encryption(options: &PdfOptions, zcount: u64) {
+    if options.key_passphrase.is_none() || zcount == 0 {
+        return;
+    }
+
+    match options.key_kdf.benchmark() {
+        Ok(elapsed) => {
+            let secs  = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+            let total = secs * zcount as f64;
+            let estimate = if total < 120.0 {
+                format!("{:.0} seconds", total)
+            } else if total < 7200.0 {
+                format!("{:.0} minutes", total / 60.0)
+            } else {
+                format!("{:.1} hours", total / 3600.0)
+            };
+            println!("Encrypting {} spending keys with scrypt cost {} ({} MiB) takes {:.2} seconds per key, about {} in all. --key-kdf-cost changes this",
+                     zcount, options.key_kdf.log_n, options.key_kdf.memory() >> 20, secs, estimate);
+        },
+        Err(e)      => eprintln!("{}", e)
+    };
+}
+
+/// Where to derive the z-addresses, from --path or --account and --start-index
+pub fn derivation_path(matches: &ArgMatches) -> Result<DerivationPath, String> {
+    let path = match (matches.value_of("path"), matches.value_of("account")) {
+        (Some(p), _)    => DerivationPath::parse(p).map_err(|e| e.to_string())?,
+        (None, Some(a)) => DerivationPath::account(a.parse::<u32>().map_err(|_| format!("The account '{}' is not a number", a))?).map_err(|e| e.to_string())?,
+        (None, None)    => DerivationPath::default()
+    };
+
+    match matches.value_of("start_index") {
+        Some(i) => path.with_start(i.parse::<u32>().map_err(|_| format!("The start index '{}' is not a number", i))?).map_err(|e| e.to_string()),
+        None    => Ok(path)
+    }
+}
+
+/// The passphrase or identity to decrypt a file with, from the command line
+pub fn decryption_key(kind: EncryptionKind, matches: &ArgMatches) -> silentdragonpaper::error::Result<DecryptionKey> {
+    match kind {
+        EncryptionKind::Passphrase => passphrase(matches, false).map(DecryptionKey::Passphrase).map_err(Error::Decryption),
+        EncryptionKind::Recipients => match matches.value_of("identity") {
+            Some(filename) => read_identity_file(filename).map(DecryptionKey::Identity),
+            None           => Err(Error::Decryption("The file is encrypted to a public key. Use --identity to give the identity file to decrypt it with".to_string()))
+        }
+    }
+}
+
+/// Read a wallet file, decrypting it if it is encrypted
+pub fn read_wallet(filename: &str, matches: &ArgMatches) -> Result<WalletBatch, String> {
+    WalletBatch::from_file_with_key(filename, |kind| decryption_key(kind, matches)).map_err(|e| e.to_string())
+}
+
+/// The columns and the label of the CSV or JSON Lines output, from the command line
+pub fn export_options(matches: &ArgMatches) -> Result<ExportOptions, String> {
+    if !matches.is_present("columns") && !matches.is_present("label") {
+        return Ok(ExportOptions::default());
+    }
+
+    if let Some(format) = matches.value_of("format") {
+        if format != "csv" && format != "jsonl" {
+            return Err("--columns and --label only apply to the csv and jsonl formats".to_string());
+        }
+    }
+
+    let label = matches.value_of("label").map(|l| l.to_string());
+    let columns = match matches.value_of("columns") {
+        Some(c) => parse_columns(c).map_err(|e| e.to_string())?,
+        None    => DEFAULT_COLUMNS.iter().cloned().chain(Some(Column::Label)).collect()
+    };
+
+    if columns.contains(&Column::Label) && label.is_none() {
+        return Err("The label column needs a --label".to_string());
+    }
+
+    Ok(ExportOptions { columns, label })
+}
+
+/// The PDF layout options from the command line. With --protect-keys, this asks for the passphrase of the
+/// private keys, so call it before generating any keys.
+pub fn pdf_options(matches: &ArgMatches) -> Result<PdfOptions, String> {
+    let paper_size = matches.value_of("paper").unwrap_or("a4").parse::<PaperSize>().map_err(|e| e.to_string())?;
+
+    let pages = match matches.value_of("pages") {
+        None    => None,
+        Some(p) => Some(p.split(',')
+                        .map(|n| n.trim().parse::<usize>().map_err(|_| format!("Page '{}' is not a number", n)))
+                        .collect::<Result<Vec<_>, _>>()?)
+    };
+
+    let key_passphrase = if matches.is_present("protect_keys") {
+        if matches.value_of("format").map(|f| f != "pdf").unwrap_or(false) && !matches.is_present("attach_pdf") {
+            return Err("--protect-keys only applies to the PDF. Use --format pdf or --attach-pdf".to_string());
+        }
+        Some(key_passphrase(matches, true)?)
+    } else {
+        None
+    };
+
+    let mut key_kdf = KdfParams::default();
+    if let Some(n) = matches.value_of("key_kdf_cost") {
+        key_kdf.log_n = n.parse::<u8>().map_err(|_| format!("The scrypt cost '{}' is not a number", n))?;
+    }
+
+    Ok(PdfOptions { paper_size, pages, key_passphrase, key_kdf })
+}
+
+/// Write the wallet in the given format. JSON, CSV and JSON Lines are printed to the console if there is no
+/// file name, but PDFs always need one. With `attach_pdf`, the PDF is rendered in memory and encrypted into the JSON file.
+pub fn write_wallet(wallet: &WalletBatch, format: &str, filename: Option<&str>, options: &PdfOptions, export: &ExportOptions, encryption: &Encryption, attach_pdf: bool, force: bool) {
+    if format == "json" {
+        let attachment = if attach_pdf {
+            // Name the PDF after the JSON file it is in
+            let name = filename.and_then(|f| Path::new(f).with_extension("pdf").file_name().map(|n| n.to_string_lossy().to_string()))
+                        .unwrap_or_else(|| "wallet.pdf".to_string());
+
+            match pdf::render_pdf(wallet, options) {
+                Ok(data) => Some(Attachment { name, data }),
+                Err(e)   => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        let plaintext = wallet.to_json_string();
+        let result = match encryption {
+            Encryption::Plaintext              => Ok((plaintext, "a plaintext")),
+            Encryption::Passphrase(passphrase) => encrypt_with_passphrase(plaintext.as_bytes(), passphrase, &KdfParams::default(), attachment.as_ref())
+                                                    .map(|c| (c, "an encrypted")),
+            Encryption::Recipients(recipients) => encrypt_to_recipients(plaintext.as_bytes(), recipients, attachment.as_ref())
+                                                    .map(|c| (c, "an encrypted"))
+        };
+
+        let (contents, description) = match result {
+            Ok(r)  => r,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+
+        match filename {
+            None           => println!("{}", contents),
+            Some(filename) => match write_secret_file(filename, contents.as_bytes(), force) {
+                Ok(_)  => println!("Wrote {:?} as {} file", filename, description),
+                Err(e) => eprintln!("{}", e)
+            }
+        };
+    } else if format == "pdf" {
+        let filename = match filename {
+            Some(f) => f,
+            None    => {
+                eprintln!("Need an output file name when writing to PDF");
+                return;
+            }
+        };
+
+        print!("Writing {:?} as a PDF file...", filename);
+        io::stdout().flush().ok();
+        match pdf::save_to_pdf(wallet, filename, options, force) {
+            Ok(_)   => { println!("[OK]");},
+            Err(e)  => {
+                eprintln!("[ERROR]");
+                eprintln!("{}", e);
+            }
+        };
+    } else {
+        let csv = format == "csv";
+        let description = if csv { "a CSV" } else { "a JSON Lines" };
+
+        match filename {
+            None           => if let Err(e) = write_batch(wallet, io::stdout(), "stdout", csv, export) {
+                eprintln!("{}", e);
+            },
+            Some(filename) => match SecretFile::create(filename, force).and_then(|mut file| {
+                write_batch(wallet, &mut file, filename, csv, export)?;
+                file.commit()
+            }) {
+                Ok(_)  => println!("Wrote {:?} as {} file", filename, description),
+                Err(e) => eprintln!("{}", e)
+            }
+        };
+    }
+}
@@ -0,0 +1,81 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use silentdragonpaper::encryption::{decrypt, encryption_kind};
+use silentdragonpaper::secure_file::write_secret_file;
+use std::fs;
+
+use crate::common;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("decrypt")
+        .about("Decrypt a wallet file that was written with --encrypt or --recipient")
+        .arg(Arg::with_name("input")
+                .index(1)
+                .required(true)
+                .help("The encrypted wallet file"))
+        .arg(Arg::with_name("output")
+                .index(2)
+                .required(true)
+                .help("Name of the plaintext JSON wallet file to write"))
+        .arg(Arg::with_name("pdf")
+                .long("pdf")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write the attached PDF, if there is one, to this file"))
+        .arg(common::passphrase_file_arg())
+        .arg(common::identity_arg())
+        .arg(common::force_arg())
+        .arg(common::refuse_swap_arg())
+}
+
+pub fn run(matches: &ArgMatches) {
+    let input  = matches.value_of("input").unwrap();
+    let output = matches.value_of("output").unwrap();
+    let force  = matches.is_present("force");
+
+    let _session = match common::start_session(matches.is_present("refuse_swap")) {
+        Some(s) => s,
+        None    => return
+    };
+
+    let contents = match fs::read_to_string(input) {
+        Ok(c)  => c,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return;
+        }
+    };
+
+    let kind = match encryption_kind(&contents) {
+        Some(k) => k,
+        None    => {
+            eprintln!("{} isn't an encrypted wallet file", input);
+            return;
+        }
+    };
+
+    let decrypted = match common::decryption_key(kind, matches).and_then(|key| decrypt(&contents, &key)) {
+        Ok(d)  => d,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    match write_secret_file(output, &decrypted.plaintext, force) {
+        Ok(_)  => println!("Wrote {:?} as a plaintext file", output),
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    match (&decrypted.attachment, matches.value_of("pdf")) {
+        (Some(a), Some(pdf)) => match write_secret_file(pdf, &a.data, force) {
+            Ok(_)  => println!("Wrote the attached {:?} to {:?}", a.name, pdf),
+            Err(e) => eprintln!("{}", e)
+        },
+        (Some(a), None)      => println!("The file has an attached PDF {:?}. Use --pdf to save it.", a.name),
+        (None, Some(_))      => eprintln!("The file doesn't have an attached PDF"),
+        (None, None)         => ()
+    };
+}
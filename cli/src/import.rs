@@ -0,0 +1,110 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use silentdragonpaper::import::import_keys;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+
+use crate::common;
+use crate::version;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("import")
+        .about("Create a paper wallet for existing keys, from the output of z_exportkey, dumpprivkey or z_exportwallet")
+        .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("File with the keys to import. If absent, the keys are read from stdin"))
+        .arg(Arg::with_name("output")
+                .index(1)
+                .help("Name of output file."))
+        .arg(Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .help("What format to generate the output in: json, pdf, csv or jsonl (JSON Lines, one wallet entry per line)")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["pdf", "json", "csv", "jsonl"])
+                .default_value("json"))
+        .arg(common::paper_arg())
+        .arg(common::protect_keys_arg())
+        .arg(common::key_kdf_cost_arg())
+        .arg(common::key_passphrase_file_arg())
+        .arg(common::encrypt_arg())
+        .arg(common::passphrase_file_arg())
+        .arg(common::recipient_arg())
+        .arg(common::attach_pdf_arg())
+        .arg(common::columns_arg())
+        .arg(common::label_arg())
+        .arg(common::force_arg())
+        .arg(common::refuse_swap_arg())
+}
+
+pub fn run(matches: &ArgMatches) {
+    let filename = matches.value_of("output");
+    let format   = matches.value_of("format").unwrap();
+
+    let options = match common::pdf_options(matches) {
+        Ok(o)  => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let export = match common::export_options(matches) {
+        Ok(e)  => e,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let encryption = match common::encryption(matches) {
+        Ok(e)  => e,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let _session = match common::start_session(matches.is_present("refuse_swap")) {
+        Some(s) => s,
+        None    => return
+    };
+
+    let text = match matches.value_of("input") {
+        Some(input) => match fs::read_to_string(input) {
+            Ok(t)  => t,
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                return;
+            }
+        },
+        None        => {
+            println!("Paste the keys to import, one per line, and press [CTRL+D] when done.");
+            let mut buffer = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut buffer) {
+                eprintln!("{}", e);
+                return;
+            }
+            buffer
+        }
+    };
+
+    let mut wallet = match import_keys(&text) {
+        Ok(w)  => w,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    println!("Imported {} keys", wallet.entries.len());
+
+    if let Some(m) = wallet.metadata.as_mut() {
+        m.tool_version = format!("SilentDragonPaper {}", version::version());
+    }
+
+    common::write_wallet(&wallet, format, filename, &options, &export, &encryption, matches.is_present("attach_pdf"), matches.is_present("force"));
+}
@@ -0,0 +1,42 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use silentdragonpaper::encryption::Identity;
+use silentdragonpaper::secure_file::write_secret_file;
+
+use crate::common;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("keygen")
+        .about("Create an identity (a key pair) that wallet files can be encrypted to with --recipient")
+        .arg(Arg::with_name("identity")
+                .index(1)
+                .required(true)
+                .help("Name of the identity file to write. Keep it secret: it decrypts every file encrypted to it. The public key is written to the same name with .pub added"))
+        .arg(common::force_arg())
+}
+
+pub fn run(matches: &ArgMatches) {
+    let filename = matches.value_of("identity").unwrap();
+    let recipient_filename = format!("{}.pub", filename);
+    let force = matches.is_present("force");
+
+    let identity = match Identity::generate() {
+        Ok(i)  => i,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    // Write the public key first, so a failure never leaves an identity behind that nobody knows the public key of
+    let recipient = identity.recipient();
+    let result = write_secret_file(&recipient_filename, format!("{}\n", recipient).as_bytes(), force)
+        .and_then(|_| write_secret_file(filename, identity.to_file_contents().as_bytes(), force));
+
+    match result {
+        Ok(_)  => {
+            println!("Wrote the identity to {:?} and the public key to {:?}", filename, recipient_filename);
+            println!("Public key: {}", recipient);
+        },
+        Err(e) => eprintln!("{}", e)
+    };
+}
@@ -2,12 +2,25 @@ extern crate clap;
 extern crate silentdragonpaper;
 
 mod version;
+mod common;
+mod render;
+mod import;
+mod verify;
+mod repair;
+mod keygen;
+mod decrypt;
+mod combine;
+mod restore;
+mod bulk;
 
 use clap::{Arg, App};
 use silentdragonpaper::paper::*;
-use silentdragonpaper::pdf;
+use silentdragonpaper::{codex32, pdf, slip39};
+use silentdragonpaper::pdf::SeedShares;
+use silentdragonpaper::selftest;
 use std::io;
 use std::io::prelude::*;
+use std::time::Instant;
 
 fn main() { 
     let matches = App::new("SilentDragonPaper")
@@ -16,10 +29,10 @@ fn main() {
         .arg(Arg::with_name("format")
                 .short("f")
                 .long("format")
-                .help("What format to generate the output in: json or pdf")
+                .help("What format to generate the output in: json, pdf, csv or jsonl (JSON Lines, one wallet entry per line)")
                 .takes_value(true)
                 .value_name("FORMAT")
-                .possible_values(&["pdf", "json"])
+                .possible_values(&["pdf", "json", "csv", "jsonl"])
                 .default_value("json"))
         .arg(Arg::with_name("nohd")
                 .short("n")
@@ -30,20 +43,35 @@ fn main() {
                 .long("output")
                 .index(1)
                 .help("Name of output file."))
-        .arg(Arg::with_name("entropy")
-                .short("e")
-                .long("entropy")
-                .takes_value(true)
-                .help("Provide additional entropy to the random number generator. Any random string, containing 32-64 characters"))
+        .arg(common::columns_arg())
+        .arg(common::label_arg())
+        .arg(common::force_arg())
+        .arg(common::paper_arg())
+        .arg(common::encrypt_arg())
+        .arg(common::passphrase_file_arg())
+        .arg(common::recipient_arg())
+        .arg(common::attach_pdf_arg())
+        .arg(common::protect_keys_arg())
+        .arg(common::key_kdf_cost_arg())
+        .arg(common::key_passphrase_file_arg())
+        .arg(common::shares_arg())
+        .arg(common::group_threshold_arg())
+        .arg(common::share_format_arg())
+        .arg(common::mnemonic_arg())
+        .arg(common::seed_passphrase_arg().requires("mnemonic"))
+        .arg(common::seed_passphrase_file_arg())
+        .arg(common::path_arg().conflicts_with("vanity_prefix"))
+        .arg(common::account_arg().conflicts_with("vanity_prefix"))
+        .arg(common::start_index_arg().conflicts_with("vanity_prefix"))
+        .arg(common::seed_length_arg().conflicts_with_all(&["vanity_prefix", "mnemonic"]))
+        .arg(common::entropy_arg())
         .arg(Arg::with_name("vanity_prefix")
                 .long("vanity")
                 .help("Generate a vanity address with the given prefix. Note that ['b', 'i', 'o', '1'] are not allowed in addresses.")
                 .takes_value(true))
-        .arg(Arg::with_name("threads")
-                .long("threads")
-                .help("Number of threads to use for the vanity address generator. Set this to the number of CPUs you have")
-                .takes_value(true)
-                .default_value("1"))
+        .arg(common::threads_arg())
+        .arg(common::refuse_swap_arg())
+        .arg(common::require_airgap_arg())
         .arg(Arg::with_name("t_addresses")
                 .short("t")
                 .long("taddrs")
@@ -64,9 +92,73 @@ fn main() {
                         Ok(_)   => return Ok(()),
                         Err(_)  => return Err(format!("Number of addresses '{}' is not a number", i))
                 }))
+        .subcommand(render::subcommand())
+        .subcommand(import::subcommand())
+        .subcommand(verify::subcommand())
+        .subcommand(repair::subcommand())
+        .subcommand(keygen::subcommand())
+        .subcommand(decrypt::subcommand())
+        .subcommand(combine::subcommand())
+        .subcommand(restore::subcommand())
+        .subcommand(bulk::subcommand())
        .get_matches();  
 
+    // Make sure the hashes and encoders work on this machine before touching any keys
+    if let Err(e) = selftest::known_answer_tests() {
+        eprintln!("{}", e);
+        return;
+    }
+
+    if let Some(render_matches) = matches.subcommand_matches("render") {
+        render::run(render_matches);
+        return;
+    }
+
+    if let Some(import_matches) = matches.subcommand_matches("import") {
+        import::run(import_matches);
+        return;
+    }
+
+    if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        verify::run(verify_matches);
+        return;
+    }
+
+    if let Some(repair_matches) = matches.subcommand_matches("repair") {
+        repair::run(repair_matches);
+        return;
+    }
+
+    if let Some(keygen_matches) = matches.subcommand_matches("keygen") {
+        keygen::run(keygen_matches);
+        return;
+    }
+
+    if let Some(decrypt_matches) = matches.subcommand_matches("decrypt") {
+        decrypt::run(decrypt_matches);
+        return;
+    }
+
+    if let Some(combine_matches) = matches.subcommand_matches("combine") {
+        combine::run(combine_matches);
+        return;
+    }
+
+    if let Some(restore_matches) = matches.subcommand_matches("restore") {
+        restore::run(restore_matches);
+        return;
+    }
+
+    if let Some(bulk_matches) = matches.subcommand_matches("bulk") {
+        bulk::run(bulk_matches);
+        return;
+    }
+
     let nohd: bool    = matches.is_present("nohd");
+    let force: bool   = matches.is_present("force");
+
+    let seed_length = common::seed_length(&matches);
+    let threads     = common::threads(&matches);
 
     // Get the filename and output format
     let filename = matches.value_of("output");
@@ -78,13 +170,75 @@ fn main() {
         return;
     }
 
+    let pdf_options = match common::pdf_options(&matches) {
+        Ok(o)  => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let export = match common::export_options(&matches) {
+        Ok(e)  => e,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let path = match common::derivation_path(&matches) {
+        Ok(p)  => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let share_spec = match common::share_spec(&matches) {
+        Ok(g)  => g,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    // Ask for the passphrase before generating anything, so a typo doesn't waste the keys
+    let encryption = match common::encryption(&matches) {
+        Ok(e)  => e,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let seed_passphrase = match common::seed_passphrase(&matches, true) {
+        Ok(p)  => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
     // Number of t addresses to generate
     let t_addresses = matches.value_of("t_addresses").unwrap().parse::<u32>().unwrap();    
 
     // Number of z addresses to generate
     let z_addresses = matches.value_of("z_addresses").unwrap().parse::<u32>().unwrap();    
 
-    let addresses = if !matches.value_of("vanity_prefix").is_none() {
+    // Check that we're offline before any secret is created
+    let airgap_status = match common::check_airgap(matches.is_present("require_airgap")) {
+        Some(s) => s,
+        None    => return
+    };
+
+    // Lock memory and disable core dumps before any secret is created. The session is kept alive
+    // till the end of main, so it covers writing the output too.
+    let _session = match common::start_session(matches.is_present("refuse_swap")) {
+        Some(s) => s,
+        None    => return
+    };
+
+    let (mut addresses, shares) = if !matches.value_of("vanity_prefix").is_none() {
         if z_addresses != 1 {
             eprintln!("Can only generate 1 z-address in vanity mode. You specified {}", z_addresses);
             return;
@@ -95,11 +249,9 @@ fn main() {
             return;
         }
 
-        let num_threads = matches.value_of("threads").unwrap().parse::<u32>().unwrap();
-
         let prefix = matches.value_of("vanity_prefix").unwrap().to_string();
         println!("Generating z-address starting with \"{}\"...", prefix);
-        let addresses = match generate_vanity_wallet(num_threads, prefix) {
+        let addresses = match generate_vanity_wallet(threads, prefix) {
             Ok(w) => w,
             Err(e) => {
                 eprintln!("{}", e);
@@ -108,51 +260,64 @@ fn main() {
         };
 
         // return
-        addresses
+        (addresses, None)
     } else {
-        // Get user entropy. 
-        let mut entropy: Vec<u8> = Vec::new();
-        // If the user hasn't specified any, read from the stdin
-        if matches.value_of("entropy").is_none() {
-            // Read from stdin
-            println!("Provide additional entropy for generating random numbers.
-Type in a string of random characters, press [ENTER] when done.");
-            let mut buffer = String::new();
-            let stdin = io::stdin();
-            stdin.lock().read_line(&mut buffer).unwrap();
-
-            entropy.extend_from_slice(buffer.as_bytes());
-        } else {
-            // Use provided entropy. 
-            entropy.extend(matches.value_of("entropy").unwrap().as_bytes());
-        }
+        let entropy = common::user_entropy(&matches);
 
         print!("Generating {} z-addresses and {} t-addresses...", z_addresses, t_addresses);
         io::stdout().flush().ok();
-        let addresses = generate_wallet(nohd, z_addresses, t_addresses, &entropy); 
+        let started = Instant::now();
+        let result = match &share_spec {
+            // Split the seed of the batch, so that no single share can spend
+            Some(spec)                             => generate_seed(seed_length, &entropy).and_then(|(seed, entropy_source)| {
+                let wallet = wallet_from_seed(&seed, z_addresses, t_addresses, &path, threads, entropy_source)?;
+                let shares = match spec {
+                    common::ShareSpec::Slip39 { group_threshold, groups } => SeedShares::Slip39(slip39::generate_shares(&seed, "", *group_threshold, groups, slip39::DEFAULT_ITERATION_EXPONENT)?),
+                    common::ShareSpec::Codex32(g)                         => SeedShares::Codex32(codex32::generate_shares(&seed, g.threshold, g.count)?)
+                };
+                Ok((wallet, Some(shares)))
+            }),
+            None if matches.is_present("mnemonic") => generate_mnemonic(&entropy).and_then(|(mnemonic, entropy_source)| {
+                wallet_from_mnemonic(&mnemonic, &seed_passphrase, z_addresses, t_addresses, &path, threads, entropy_source)
+            }).map(|w| (w, None)),
+            None                                   => generate_wallet(nohd, z_addresses, t_addresses, &path, seed_length, threads, &entropy).map(|w| (w, None))
+        };
+
+        let generated = match result {
+            Ok(g)  => g,
+            Err(e) => {
+                eprintln!("[ERROR]");
+                eprintln!("{}", e);
+                return;
+            }
+        };
         println!("[OK]");
+        common::report_throughput(z_addresses as u64 + t_addresses as u64, started.elapsed(), threads);
 
-        addresses
+        generated
     };
 
-    // If the default format is present, write to the console if the filename is absent
-    if format == "json" {
-        if filename.is_none() {
-            println!("{}", addresses);
-        } else {
-            std::fs::write(filename.unwrap(), addresses).expect("Couldn't write to file!");
-            println!("Wrote {:?} as a plaintext file", filename);
-        }
-    } else if format == "pdf" {
-        // We already know the output file name was specified
-        print!("Writing {:?} as a PDF file...", filename.unwrap());
+    // Record how the batch was made
+    if let Some(m) = addresses.metadata.as_mut() {
+        m.tool_version = format!("SilentDragonPaper {}", version::version());
+        m.airgap       = Some(airgap_status);
+    }
+
+    if let Some(shares) = shares {
+        // share_spec() made sure this is a PDF, which has a file name
+        let filename = filename.unwrap();
+        print!("Writing {:?} as a PDF file with {} shares...", filename, shares.count());
         io::stdout().flush().ok();
-        match pdf::save_to_pdf(&addresses, filename.unwrap()) {
-            Ok(_)   => { println!("[OK]");},
-            Err(e)  => {
+        match pdf::save_shares_to_pdf(&addresses, &shares, filename, &pdf_options, force) {
+            Ok(_)  => println!("[OK]"),
+            Err(e) => {
                 eprintln!("[ERROR]");
                 eprintln!("{}", e);
             }
-        };       
-    }    
+        };
+        return;
+    }
+
+    // If the default format is present, write to the console if the filename is absent
+    common::write_wallet(&addresses, format, filename, &pdf_options, &export, &encryption, matches.is_present("attach_pdf"), force);
 }
@@ -0,0 +1,69 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use silentdragonpaper::pdf;
+use std::io;
+use std::io::prelude::*;
+
+use crate::common;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("render")
+        .about("Create a PDF from a previously saved JSON wallet file, or a CSV or JSON Lines export, without generating new keys")
+        .arg(Arg::with_name("input")
+                .index(1)
+                .required(true)
+                .help("The JSON wallet file to read. If it is encrypted with a passphrase, you will be asked for it. Files named *.csv or *.jsonl are read as CSV or JSON Lines exports"))
+        .arg(Arg::with_name("output")
+                .index(2)
+                .required(true)
+                .help("Name of the PDF file to write"))
+        .arg(Arg::with_name("pages")
+                .long("pages")
+                .takes_value(true)
+                .value_name("PAGES")
+                .help("Only print these pages, separated by commas. Useful to reprint a damaged page"))
+        .arg(common::paper_arg())
+        .arg(common::protect_keys_arg())
+        .arg(common::key_kdf_cost_arg())
+        .arg(common::key_passphrase_file_arg())
+        .arg(common::passphrase_file_arg())
+        .arg(common::identity_arg())
+        .arg(common::force_arg())
+        .arg(common::refuse_swap_arg())
+}
+
+pub fn run(matches: &ArgMatches) {
+    let input  = matches.value_of("input").unwrap();
+    let output = matches.value_of("output").unwrap();
+
+    let options = match common::pdf_options(matches) {
+        Ok(o)  => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    // The wallet file has private keys, so read it in a hardened session too
+    let _session = match common::start_session(matches.is_present("refuse_swap")) {
+        Some(s) => s,
+        None    => return
+    };
+
+    let wallet = match common::read_wallet(input, matches) {
+        Ok(w)  => w,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    print!("Writing {:?} as a PDF file...", output);
+    io::stdout().flush().ok();
+    match pdf::save_to_pdf(&wallet, output, &options, matches.is_present("force")) {
+        Ok(_)   => { println!("[OK]");},
+        Err(e)  => {
+            eprintln!("[ERROR]");
+            eprintln!("{}", e);
+        }
+    };
+}
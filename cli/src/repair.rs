@@ -0,0 +1,55 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use silentdragonpaper::paper::params;
+use silentdragonpaper::repair;
+use std::io;
+use std::io::prelude::*;
+use std::process;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("repair")
+        .about("Find likely corrections for a mistyped address or key")
+        .arg(Arg::with_name("key")
+                .index(1)
+                .help("The mistyped address or key. If absent, it is read from stdin, so private keys don't end up in the shell history"))
+}
+
+pub fn run(matches: &ArgMatches) {
+    let key = match matches.value_of("key") {
+        Some(k) => k.to_string(),
+        None    => {
+            println!("Type or paste the address or key to repair, and press [ENTER].");
+            let mut buffer = String::new();
+            io::stdin().lock().read_line(&mut buffer).unwrap();
+            buffer.trim().to_string()
+        }
+    };
+
+    let corrections = repair::repair(&key, &params());
+
+    if corrections.is_empty() {
+        eprintln!("Couldn't find a correction. Please check the whole key or address again, including for missing or doubled characters.");
+        process::exit(1);
+    }
+
+    if corrections.len() == 1 && corrections[0].positions.is_empty() {
+        println!("Nothing to repair. This is a valid {}", corrections[0].decoded.kind.description());
+        return;
+    }
+
+    if corrections.len() == 1 {
+        println!("Found a likely correction. The changed characters are marked with '^':");
+    } else {
+        println!("Found {} possible corrections, most likely first. The changed characters are marked with '^':", corrections.len());
+    }
+
+    for c in corrections {
+        println!("");
+        println!("  {}", key);
+        println!("  {}", repair::highlight(&c.positions));
+        println!("  {}", c.corrected);
+        println!("  A {} for {}", c.decoded.kind.description(), c.decoded.address);
+    }
+
+    println!("");
+    println!("Check the marked characters against your paper wallet before using the correction.");
+}
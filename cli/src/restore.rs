@@ -0,0 +1,199 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use silentdragonpaper::paper::{decode_seed, wallet_from_mnemonic, wallet_from_seed};
+use silentdragonpaper::wallet::AddressKind;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::process;
+
+use crate::common;
+use crate::version;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("restore")
+        .about("Recover a wallet from its BIP39 mnemonic (and passphrase), written with --mnemonic, or from its hex HD seed")
+        .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("File with the mnemonic or the hex seed. If absent, it is read from stdin"))
+        .arg(Arg::with_name("output")
+                .index(1)
+                .help("Name of output file."))
+        .arg(Arg::with_name("address")
+                .short("a")
+                .long("address")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .multiple(true)
+                .number_of_values(1)
+                .required(true)
+                .help("An address printed on the paper wallet. The restore fails if it isn't derived again, which catches a mistyped passphrase"))
+        .arg(Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .help("What format to generate the output in: json, pdf, csv or jsonl (JSON Lines, one wallet entry per line)")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["pdf", "json", "csv", "jsonl"])
+                .default_value("json"))
+        .arg(Arg::with_name("t_addresses")
+                .short("t")
+                .long("taddrs")
+                .help("Number of t-addresses to derive again. This is printed on the mnemonic page")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|i:String| match i.parse::<u32>() {
+                        Ok(_)   => return Ok(()),
+                        Err(_)  => return Err(format!("Number of addresses '{}' is not a number", i))
+                }))
+        .arg(Arg::with_name("z_addresses")
+                .short("z")
+                .long("zaddrs")
+                .help("Number of z-addresses to derive again. This is printed on the mnemonic page")
+                .takes_value(true)
+                .default_value("1")
+                .validator(|i:String| match i.parse::<u32>() {
+                        Ok(_)   => return Ok(()),
+                        Err(_)  => return Err(format!("Number of addresses '{}' is not a number", i))
+                }))
+        .arg(common::seed_passphrase_arg())
+        .arg(common::seed_passphrase_file_arg())
+        .arg(common::threads_arg())
+        .arg(common::path_arg())
+        .arg(common::account_arg())
+        .arg(common::start_index_arg())
+        .arg(common::paper_arg())
+        .arg(common::protect_keys_arg())
+        .arg(common::key_kdf_cost_arg())
+        .arg(common::key_passphrase_file_arg())
+        .arg(common::encrypt_arg())
+        .arg(common::passphrase_file_arg())
+        .arg(common::recipient_arg())
+        .arg(common::attach_pdf_arg())
+        .arg(common::columns_arg())
+        .arg(common::label_arg())
+        .arg(common::force_arg())
+        .arg(common::refuse_swap_arg())
+}
+
+pub fn run(matches: &ArgMatches) {
+    let filename = matches.value_of("output");
+    let format   = matches.value_of("format").unwrap();
+
+    let t_addresses = matches.value_of("t_addresses").unwrap().parse::<u32>().unwrap();
+    let z_addresses = matches.value_of("z_addresses").unwrap().parse::<u32>().unwrap();
+    let threads     = common::threads(matches);
+
+    let path = match common::derivation_path(matches) {
+        Ok(p)  => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let options = match common::pdf_options(matches) {
+        Ok(o)  => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let export = match common::export_options(matches) {
+        Ok(e)  => e,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let encryption = match common::encryption(matches) {
+        Ok(e)  => e,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let _session = match common::start_session(matches.is_present("refuse_swap")) {
+        Some(s) => s,
+        None    => return
+    };
+
+    let text = match matches.value_of("input") {
+        Some(input) => match fs::read_to_string(input) {
+            Ok(t)  => t,
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                return;
+            }
+        },
+        None        => {
+            println!("Type or paste the mnemonic or the hex seed, and press [CTRL+D] when done.");
+            let mut buffer = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut buffer) {
+                eprintln!("{}", e);
+                return;
+            }
+            buffer
+        }
+    };
+
+    // Mnemonic words can't be written with hex digits alone, so anything else is a mnemonic
+    let is_hex = text.chars().any(|c| !c.is_whitespace()) && text.chars().all(|c| c.is_ascii_hexdigit() || c.is_whitespace());
+    if is_hex && matches.is_present("seed_passphrase") {
+        eprintln!("A hex seed has no BIP39 passphrase. Leave out --seed-passphrase");
+        return;
+    }
+
+    let seed_passphrase = match common::seed_passphrase(matches, false) {
+        Ok(p)  => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let restored = if is_hex {
+        decode_seed(&text).and_then(|seed| wallet_from_seed(&seed, z_addresses, t_addresses, &path, threads, "HD seed".to_string()))
+    } else {
+        wallet_from_mnemonic(&text, &seed_passphrase, z_addresses, t_addresses, &path, threads, "BIP39 mnemonic".to_string())
+    };
+    let source = if is_hex {"seed"} else {"mnemonic"};
+
+    let mut wallet = match restored {
+        Ok(w)  => w,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // Any passphrase gives a wallet, so the only way to tell a wrong one is that the addresses are different
+    for address in matches.values_of("address").unwrap() {
+        if !wallet.entries.iter().any(|e| e.address == address.trim()) {
+            let zcount = wallet.entries.iter().filter(|e| e.kind != AddressKind::Transparent).count();
+            eprintln!("{} is not one of the {} z-addresses and {} t-addresses of this {}.", address.trim(), zcount, wallet.entries.len() - zcount, source);
+            if is_hex {
+                eprintln!("Check the seed, the number of addresses and the path.");
+            } else if seed_passphrase.is_empty() {
+                eprintln!("If the paper says that a BIP39 passphrase is needed, use --seed-passphrase. Otherwise check the words, the number of addresses and the path.");
+            } else {
+                eprintln!("The passphrase is probably wrong. Otherwise check the words, the number of addresses and the path.");
+            }
+
+            // Fail without writing the wallet, so a script can tell that the restore didn't work
+            process::exit(1);
+        }
+    }
+    println!("The addresses match the {}", source);
+
+    if let Some(m) = wallet.metadata.as_mut() {
+        m.tool_version = format!("SilentDragonPaper {}", version::version());
+    }
+
+    common::write_wallet(&wallet, format, filename, &options, &export, &encryption, matches.is_present("attach_pdf"), matches.is_present("force"));
+}
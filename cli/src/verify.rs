@@ -0,0 +1,124 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use silentdragonpaper::error::Error;
+use silentdragonpaper::paper::params;
+use silentdragonpaper::{bip38, encrypted_key, selftest, verify};
+use std::io;
+use std::io::prelude::*;
+use std::process;
+
+use crate::common;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("verify")
+        .about("Check an address or key for typos, and that a key belongs to an address")
+        .arg(Arg::with_name("key")
+                .index(1)
+                .help("The address or key to check. If absent, it is read from stdin, so private keys don't end up in the shell history. A key printed with --protect-keys is decrypted with its passphrase first"))
+        .arg(Arg::with_name("address")
+                .short("a")
+                .long("address")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .help("Also check that the key is the key for this address"))
+        .arg(Arg::with_name("show_key")
+                .long("show-key")
+                .conflicts_with("wallet")
+                .help("Print the decrypted private key of an encrypted key, so it can be imported into a wallet"))
+        .arg(Arg::with_name("wallet")
+                .short("w")
+                .long("wallet")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["key", "address"])
+                .help("Check every address and key in this JSON wallet file instead. If it is encrypted with a passphrase, you will be asked for it. Files named *.csv or *.jsonl are read as CSV or JSON Lines exports"))
+        .arg(common::passphrase_file_arg())
+        .arg(common::identity_arg())
+}
+
+pub fn run(matches: &ArgMatches) {
+    if let Some(filename) = matches.value_of("wallet") {
+        verify_wallet(filename, matches);
+        return;
+    }
+
+    let key = match matches.value_of("key") {
+        Some(k) => k.to_string(),
+        None    => {
+            println!("Type or paste the address or key to check, and press [ENTER].");
+            let mut buffer = String::new();
+            io::stdin().lock().read_line(&mut buffer).unwrap();
+            buffer.trim().to_string()
+        }
+    };
+
+    // A key printed with --protect-keys has to be decrypted before it can be checked
+    let encrypted = bip38::is_encrypted_wif(&key) || encrypted_key::is_encrypted_spending_key(&key, &params());
+    let key = if encrypted {
+        match common::passphrase(matches, false).and_then(|p| decrypt_key(&key, &p).map_err(|e| e.to_string())) {
+            Ok(k)  => k,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        key
+    };
+
+    let result = match matches.value_of("address") {
+        Some(address) => verify::verify_key_for_address(&key, address, &params()),
+        None          => verify::decode(&key, &params())
+    };
+
+    match result {
+        Ok(d) => {
+            if d.kind.is_address() {
+                println!("OK: Valid {}", d.kind.description());
+            } else if matches.is_present("address") {
+                println!("OK: Valid {} for {}", d.kind.description(), d.address);
+            } else {
+                println!("OK: Valid {}. Its address is {}", d.kind.description(), d.address);
+            }
+
+            if encrypted && matches.is_present("show_key") {
+                println!("{}", key);
+            }
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+}
+
+/// Decrypt a BIP38 encrypted WIF or an encrypted Sapling spending key
+fn decrypt_key(key: &str, passphrase: &str) -> silentdragonpaper::error::Result<String> {
+    if bip38::is_encrypted_wif(key) {
+        bip38::decrypt_wif(key, passphrase, &params())
+    } else {
+        encrypted_key::decrypt_spending_key(key, passphrase, &params())
+    }
+}
+
+/// Read a wallet file and check that every address matches its keys, and its seed if it has one
+fn verify_wallet(filename: &str, matches: &ArgMatches) {
+    let _session = match common::start_session(false) {
+        Some(s) => s,
+        None    => process::exit(1)
+    };
+
+    let result = common::read_wallet(filename, matches)
+        .and_then(|w| selftest::check_batch(&w).map(|_| w).map_err(|e| match e {
+            // This isn't about the keys we just generated, so leave out the advice about faulty hardware
+            Error::SelfTest(msg) => msg,
+            e                    => e.to_string()
+        }));
+
+    match result {
+        Ok(w)  => println!("OK: All {} addresses in {} match their keys", w.entries.len(), filename),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+}
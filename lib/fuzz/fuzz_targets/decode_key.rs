@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use silentdragonpaper::encoding::{decode_base58check, decode_bech32};
+use silentdragonpaper::paper::params;
+use silentdragonpaper::verify;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = decode_bech32(s);
+        let _ = decode_base58check(s);
+
+        // Whatever decodes has to be one of ours, and a key has to check out against its own address
+        if let Ok(d) = verify::decode(s, &params()) {
+            if !d.kind.is_address() {
+                verify::verify_key_for_address(s, &d.address, &params()).unwrap();
+            }
+        }
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use silentdragonpaper::paper::params;
+use silentdragonpaper::{repair, verify};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        // Every suggested correction has to be valid
+        for c in repair::repair(s, &params()) {
+            verify::decode(&c.corrected, &params()).unwrap();
+        }
+    }
+});
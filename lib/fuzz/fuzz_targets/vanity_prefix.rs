@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use silentdragonpaper::paper::{get_bech32_for_prefix, MAX_VANITY_PREFIX};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        if let Ok(v) = get_bech32_for_prefix(s.to_string()) {
+            assert_eq!(v.len(), s.chars().count());
+            assert!(v.len() <= MAX_VANITY_PREFIX);
+        }
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use silentdragonpaper::wallet::WalletBatch;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        // This is what render and the GUI pass to save_to_pdf. Anything it accepts has to survive a round trip.
+        if let Ok(batch) = WalletBatch::from_json_str(s) {
+            assert_eq!(WalletBatch::from_json_str(&batch.to_json_string()).unwrap(), batch);
+        }
+    }
+});
@@ -0,0 +1,45 @@
+#!/bin/bash
+# Add real SilentDragonPaper output to the seed corpus. Build the cli first, then run this from lib/fuzz:
+#   ./seed_corpus.sh ../../cli/target/release/SilentDragonPaper
+set -e
+
+BIN=${1:-../../cli/target/release/SilentDragonPaper}
+TMP=$(mktemp -d)
+trap "rm -rf $TMP" EXIT
+
+mkdir -p corpus/decode_key corpus/repair_key corpus/wallet_json corpus/import_keys corpus/vanity_prefix
+
+"$BIN" -z 2 -t 2 -e "$(head -c 48 /dev/urandom | base64)" "$TMP/hd.json" > /dev/null
+"$BIN" -n -z 2 -e "$(head -c 48 /dev/urandom | base64)" "$TMP/nohd.json" > /dev/null
+
+for f in "$TMP"/*.json; do
+    cp "$f" corpus/wallet_json/$(sha1sum "$f" | cut -c1-40)
+done
+
+# Every address and key in the wallets, one per file
+cat "$TMP"/*.json | grep -oE '"(zs1|zviews1|secret-extended-key-main1|R|U)[0-9A-Za-z-]+"' | tr -d '"' | while read k; do
+    name=$(echo -n "$k" | sha1sum | cut -c1-40)
+    echo -n "$k" > corpus/decode_key/$name
+    # The same string with one character changed, for the repair helper
+    echo -n "$k" | sed 's/\(.\{20\}\)./\1q/' > corpus/repair_key/$name
+done
+
+# The private keys as they'd be pasted from z_exportkey and dumpprivkey
+cat "$TMP"/*.json | grep -oE '"(secret-extended-key-main1|U)[0-9A-Za-z-]+"' | tr -d '"' > "$TMP/keys.txt"
+cp "$TMP/keys.txt" corpus/import_keys/$(sha1sum "$TMP/keys.txt" | cut -c1-40)
+
+# A z_exportwallet dump of the same kind of keys, with the address of every key in a comment
+"$BIN" -z 2 -t 2 --format csv --columns type,address,private_key -e "$(head -c 48 /dev/urandom | base64)" "$TMP/keys.csv" > /dev/null
+{
+    echo "# Wallet dump created by Hush"
+    echo "# * Created on 2021-01-01T00:00:00Z"
+    echo
+    grep '^taddr,' "$TMP/keys.csv" | awk -F, '{ print $3 " 2021-01-01T00:00:00Z reserve=1 # addr=" $2 }'
+    echo
+    echo "# Zkeys"
+    echo
+    grep '^zaddr,' "$TMP/keys.csv" | awk -F, '{ print $3 " 2021-01-01T00:00:00Z # zaddr=" $2 }'
+    echo
+    echo "# End of dump"
+} > "$TMP/dump.txt"
+cp "$TMP/dump.txt" corpus/import_keys/$(sha1sum "$TMP/dump.txt" | cut -c1-40)
@@ -0,0 +1,253 @@
+use std::fs;
+use std::path::Path;
+use json::object;
+
+/// The result of the air-gap pre-flight check.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AirgapStatus {
+    /// Whether we could inspect the network at all. This is only supported on Linux.
+    pub checked           : bool,
+    /// Non-loopback interfaces that are up
+    pub active_interfaces : Vec<String>,
+    /// Non-loopback interfaces that have a route (IPv4 or IPv6) through them
+    pub routed_interfaces : Vec<String>,
+}
+
+impl AirgapStatus {
+    /// The machine looks air-gapped if we could check, and found no active interfaces or routes
+    pub fn is_airgapped(&self) -> bool {
+        self.checked && self.active_interfaces.is_empty() && self.routed_interfaces.is_empty()
+    }
+
+    /// A one-line human readable description of the result
+    pub fn summary(&self) -> String {
+        if !self.checked {
+            "Couldn't check if this computer is air-gapped (only supported on Linux)".to_string()
+        } else if self.is_airgapped() {
+            "No active network interfaces found".to_string()
+        } else {
+            let mut interfaces = self.active_interfaces.clone();
+            for i in &self.routed_interfaces {
+                if !interfaces.contains(i) {
+                    interfaces.push(i.clone());
+                }
+            }
+            format!("This computer is not air-gapped. Active network interfaces: {}", interfaces.join(", "))
+        }
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "checked"           => self.checked,
+            "airgapped"         => self.is_airgapped(),
+            "active_interfaces" => self.active_interfaces.clone(),
+            "routed_interfaces" => self.routed_interfaces.clone()
+        }
+    }
+
+    pub fn from_json(kv: &json::JsonValue) -> AirgapStatus {
+        let strings = |v: &json::JsonValue| v.members().filter_map(|s| s.as_str()).map(|s| s.to_string()).collect();
+
+        AirgapStatus {
+            checked           : kv["checked"].as_bool().unwrap_or(false),
+            active_interfaces : strings(&kv["active_interfaces"]),
+            routed_interfaces : strings(&kv["routed_interfaces"]),
+        }
+    }
+}
+
+/// Inspect /sys/class/net and the kernel routing tables for active non-loopback interfaces. This should be
+/// run before any secret is created.
+#[cfg(target_os = "linux")]
+pub fn check_airgap() -> AirgapStatus {
+    let mut interfaces = vec![];
+
+    if let Ok(entries) = fs::read_dir("/sys/class/net") {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            interfaces.push(Interface {
+                name      : entry.file_name().to_string_lossy().to_string(),
+                iftype    : read_sys_file(&path.join("type")),
+                operstate : read_sys_file(&path.join("operstate")),
+                carrier   : read_sys_file(&path.join("carrier")),
+            });
+        }
+    }
+
+    let ipv4 = fs::read_to_string("/proc/net/route").unwrap_or_default();
+    let ipv6 = fs::read_to_string("/proc/net/ipv6_route").unwrap_or_default();
+
+    airgap_status(&interfaces, &ipv4, &ipv6)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_airgap() -> AirgapStatus {
+    AirgapStatus { checked: false, active_interfaces: vec![], routed_interfaces: vec![] }
+}
+
+/// A network interface, with the contents of its files in /sys/class/net/<name>
+struct Interface {
+    name      : String,
+    iftype    : String,
+    operstate : String,
+    carrier   : String,
+}
+
+impl Interface {
+    /// ARPHRD_LOOPBACK is 772 in if_arp.h
+    fn is_loopback(&self) -> bool {
+        self.name == "lo" || self.iftype == "772"
+    }
+
+    /// Some interfaces (like tun devices) report their operstate as "unknown" even when they're passing
+    /// traffic, so for those we also look at the carrier.
+    fn is_up(&self) -> bool {
+        match self.operstate.as_str() {
+            "up"      => true,
+            "unknown" => self.carrier == "1",
+            _         => false
+        }
+    }
+}
+
+/// The status for the interfaces in /sys/class/net and the contents of /proc/net/route and /proc/net/ipv6_route
+fn airgap_status(interfaces: &[Interface], ipv4_routes: &str, ipv6_routes: &str) -> AirgapStatus {
+    let mut active_interfaces: Vec<String> = interfaces.iter()
+        .filter(|i| !i.is_loopback() && i.is_up())
+        .map(|i| i.name.clone())
+        .collect();
+    active_interfaces.sort();
+
+    let mut routed_interfaces = vec![];
+    for iface in routes_ipv4(ipv4_routes).into_iter().chain(routes_ipv6(ipv6_routes).into_iter()) {
+        if iface != "lo" && !routed_interfaces.contains(&iface) {
+            routed_interfaces.push(iface);
+        }
+    }
+    routed_interfaces.sort();
+
+    AirgapStatus { checked: true, active_interfaces, routed_interfaces }
+}
+
+fn read_sys_file(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_default().trim().to_string()
+}
+
+/// Interfaces of all the routes in /proc/net/route that have the RTF_UP flag. The columns are
+/// Iface, Destination, Gateway, Flags, ... with a header line.
+fn routes_ipv4(table: &str) -> Vec<String> {
+    table.lines().skip(1).filter_map(|l| {
+        let cols: Vec<&str> = l.split_whitespace().collect();
+        if cols.len() < 4 {
+            return None;
+        }
+
+        let flags = u32::from_str_radix(cols[3], 16).unwrap_or(0);
+        if flags & 0x1 != 0 { Some(cols[0].to_string()) } else { None }
+    }).collect()
+}
+
+/// Interfaces of all the routes in /proc/net/ipv6_route that have the RTF_UP flag. There is no header, and
+/// the flags and interface name are the 9th and 10th columns.
+fn routes_ipv6(table: &str) -> Vec<String> {
+    table.lines().filter_map(|l| {
+        let cols: Vec<&str> = l.split_whitespace().collect();
+        if cols.len() < 10 {
+            return None;
+        }
+
+        let flags = u32::from_str_radix(cols[8], 16).unwrap_or(0);
+        if flags & 0x1 != 0 { Some(cols[9].to_string()) } else { None }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE_HEADER: &str = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n";
+
+    /// The IPv6 routes of the loopback interface, which every Linux machine has
+    const IPV6_LOOPBACK: &str = "\
+00000000000000000000000000000001 80 00000000000000000000000000000000 00 00000000000000000000000000000000 00000000 00000001 00000000 80200001       lo
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+";
+
+    fn interface(name: &str, iftype: &str, operstate: &str, carrier: &str) -> Interface {
+        Interface { name: name.to_string(), iftype: iftype.to_string(), operstate: operstate.to_string(), carrier: carrier.to_string() }
+    }
+
+    fn loopback() -> Interface {
+        interface("lo", "772", "unknown", "1")
+    }
+
+    #[test]
+    fn test_loopback_only() {
+        let status = airgap_status(&[loopback()], ROUTE_HEADER, IPV6_LOOPBACK);
+        assert!(status.is_airgapped());
+        assert_eq!(status.summary(), "No active network interfaces found");
+
+        // A loopback interface by type, whatever its name
+        let status = airgap_status(&[loopback(), interface("lo2", "772", "up", "1")], ROUTE_HEADER, "");
+        assert!(status.is_airgapped());
+    }
+
+    #[test]
+    fn test_interface_down() {
+        let interfaces = [loopback(), interface("eth0", "1", "down", "0"), interface("wlan0", "1", "dormant", "")];
+        let status = airgap_status(&interfaces, ROUTE_HEADER, IPV6_LOOPBACK);
+        assert!(status.is_airgapped());
+
+        // A tun device that says its state is unknown is down without a carrier
+        let status = airgap_status(&[loopback(), interface("tun0", "65534", "unknown", "0")], ROUTE_HEADER, "");
+        assert!(status.is_airgapped());
+    }
+
+    #[test]
+    fn test_interface_up_without_route() {
+        let interfaces = [loopback(), interface("eth0", "1", "up", "1"), interface("tun0", "65534", "unknown", "1")];
+        let status = airgap_status(&interfaces, ROUTE_HEADER, IPV6_LOOPBACK);
+
+        assert!(!status.is_airgapped());
+        assert_eq!(status.active_interfaces, vec!["eth0", "tun0"]);
+        assert!(status.routed_interfaces.is_empty());
+        assert_eq!(status.summary(), "This computer is not air-gapped. Active network interfaces: eth0, tun0");
+    }
+
+    #[test]
+    fn test_default_route() {
+        let ipv4 = format!("{}{}{}", ROUTE_HEADER,
+                           "eth0\t00000000\t0100A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n",
+                           "eth0\t0000A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n");
+        let interfaces = [loopback(), interface("eth0", "1", "up", "1")];
+        let status = airgap_status(&interfaces, &ipv4, IPV6_LOOPBACK);
+
+        assert!(!status.is_airgapped());
+        assert_eq!((status.active_interfaces.clone(), status.routed_interfaces.clone()), (vec!["eth0".to_string()], vec!["eth0".to_string()]));
+        assert_eq!(status.summary(), "This computer is not air-gapped. Active network interfaces: eth0");
+
+        // A route that isn't up doesn't count, but an IPv6 default route on an interface that says it's down does
+        let ipv4 = format!("{}{}", ROUTE_HEADER, "wlan0\t00000000\t0100A8C0\t0002\t0\t0\t600\t00000000\t0\t0\t0\n");
+        let ipv6 = format!("{}{}", IPV6_LOOPBACK,
+                           "00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00450003     eth1\n");
+        let status = airgap_status(&[loopback(), interface("eth1", "1", "down", "0")], &ipv4, &ipv6);
+        assert!(status.active_interfaces.is_empty());
+        assert_eq!(status.routed_interfaces, vec!["eth1"]);
+        assert!(!status.is_airgapped());
+    }
+
+    #[test]
+    fn test_routes() {
+        assert!(routes_ipv4("").is_empty());
+        assert!(routes_ipv4(ROUTE_HEADER).is_empty());
+        assert_eq!(routes_ipv4(&format!("{}eth0 00000000 0100A8C0 0003 0 0 100 00000000 0 0 0\nshort line\n", ROUTE_HEADER)), vec!["eth0"]);
+        assert_eq!(routes_ipv6(IPV6_LOOPBACK), vec!["lo"]);
+    }
+
+    #[test]
+    fn test_json() {
+        let status = AirgapStatus { checked: true, active_interfaces: vec!["eth0".to_string()], routed_interfaces: vec![] };
+        assert_eq!(AirgapStatus::from_json(&status.to_json()), status);
+        assert_eq!(status.to_json()["airgapped"].as_bool(), Some(false));
+    }
+}
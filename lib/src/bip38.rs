@@ -0,0 +1,172 @@
+use aes::{Aes256, BlockCipher, NewBlockCipher};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::encoding::decode_base58check;
+use crate::error::{Error, Result};
+use crate::paper::{double_sha256, pubkey_hash, CoinParams, ToBase58Check};
+use crate::verify::decode_wif;
+
+/// The prefix of a key encrypted without EC multiplication. In base58 these keys start with "6P".
+const NON_EC_PREFIX: [u8; 2] = [0x01, 0x42];
+
+/// The prefix of a key encrypted with EC multiplication, which we can't decrypt
+const EC_PREFIX: [u8; 2] = [0x01, 0x43];
+
+/// Flag bits: no EC multiplication, and whether the public key is compressed
+const FLAG_NON_EC: u8 = 0xc0;
+const FLAG_COMPRESSED: u8 = 0x20;
+
+/// The length of the payload: prefix, flag, address hash and the two encrypted halves
+const PAYLOAD_LENGTH: usize = 2 + 1 + 4 + 32;
+
+/// The scrypt parameters fixed by BIP38: N=16384, r=8, p=8
+const SCRYPT_LOG_N: u8 = 14;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 8;
+
+/// Whether `s` looks like a BIP38 encrypted private key, so it should be decrypted with a passphrase before
+/// it can be used
+pub fn is_encrypted_wif(s: &str) -> bool {
+    match decode_base58check(s.trim()) {
+        Ok(payload) => payload.len() == PAYLOAD_LENGTH && (payload[..2] == NON_EC_PREFIX || payload[..2] == EC_PREFIX),
+        Err(_)      => false
+    }
+}
+
+/// Encrypt a WIF private key (as printed for t-addresses) with a passphrase, as BIP38 does for Bitcoin (without
+/// EC multiplication).
+///
+/// The result starts with "6P" and has a checksum of the t-address of the key, which is used to detect a wrong
+/// passphrase when decrypting. The passphrase is normalized to Unicode NFC first.
+pub fn encrypt_wif(wif: &str, passphrase: &str, coin: &CoinParams) -> Result<String> {
+    let (sk, compressed) = decode_wif(wif.trim(), coin)?;
+    let address = pubkey_hash(&sk, compressed).to_base58check(&coin.taddress_version, &[]);
+    let address_hash = address_hash(&address);
+
+    let (half1, half2) = derive_halves(passphrase, &address_hash)?;
+    let cipher = Aes256::new(&half2.into());
+
+    let mut block = [0u8; 32];
+    for (i, b) in sk.serialize().iter().enumerate() {
+        block[i] = b ^ half1[i];
+    }
+    for chunk in block.chunks_mut(16) {
+        cipher.encrypt_block(chunk.into());
+    }
+
+    let flag = FLAG_NON_EC | if compressed { FLAG_COMPRESSED } else { 0 };
+    let mut payload = vec![flag];
+    payload.extend_from_slice(&address_hash);
+    payload.extend_from_slice(&block);
+
+    return Ok(payload.to_base58check(&NON_EC_PREFIX, &[]));
+}
+
+/// Decrypt a BIP38 encrypted private key with the passphrase, returning the WIF private key.
+///
+/// A wrong passphrase is detected with the address checksum in the encrypted key.
+pub fn decrypt_wif(encrypted: &str, passphrase: &str, coin: &CoinParams) -> Result<String> {
+    let payload = decode_base58check(encrypted.trim())?;
+
+    if payload.len() == PAYLOAD_LENGTH && payload[..2] == EC_PREFIX {
+        return Err(Error::InvalidKey("Keys encrypted with BIP38 EC multiplication aren't supported".to_string()));
+    }
+    if payload.len() != PAYLOAD_LENGTH || payload[..2] != NON_EC_PREFIX {
+        return Err(Error::InvalidKey("Not a BIP38 encrypted private key".to_string()));
+    }
+
+    let flag = payload[2];
+    if flag & !FLAG_COMPRESSED != FLAG_NON_EC {
+        return Err(Error::InvalidKey(format!("Unknown BIP38 flag byte {:02x}", flag)));
+    }
+    let compressed = flag & FLAG_COMPRESSED != 0;
+
+    let mut checksum = [0u8; 4];
+    checksum.copy_from_slice(&payload[3..7]);
+
+    let (half1, half2) = derive_halves(passphrase, &checksum)?;
+    let cipher = Aes256::new(&half2.into());
+
+    let mut block = [0u8; 32];
+    block.copy_from_slice(&payload[7..]);
+    for chunk in block.chunks_mut(16) {
+        cipher.decrypt_block(chunk.into());
+    }
+    for (i, b) in block.iter_mut().enumerate() {
+        *b ^= half1[i];
+    }
+
+    // A wrong passphrase gives a different key (or no valid key at all), whose address doesn't match the checksum
+    let wrong_passphrase = || Error::Decryption("Wrong passphrase".to_string());
+    let sk = secp256k1::SecretKey::parse(&block).map_err(|_| wrong_passphrase())?;
+    let address = pubkey_hash(&sk, compressed).to_base58check(&coin.taddress_version, &[]);
+    if address_hash(&address) != checksum {
+        return Err(wrong_passphrase());
+    }
+
+    let suffix: &[u8] = if compressed { &[0x01] } else { &[] };
+    return Ok(sk.serialize().to_base58check(&coin.tsecret_prefix, suffix));
+}
+
+/// The first 4 bytes of SHA256d of the address string, which salt the key derivation and detect a wrong passphrase
+fn address_hash(address: &str) -> [u8; 4] {
+    let mut hash = [0u8; 4];
+    hash.copy_from_slice(&double_sha256(address.as_bytes())[..4]);
+    return hash;
+}
+
+/// scrypt of the passphrase, salted with the address hash, split into the XOR mask and the AES key
+fn derive_halves(passphrase: &str, address_hash: &[u8; 4]) -> Result<([u8; 32], [u8; 32])> {
+    let params = scrypt::ScryptParams::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P).map_err(|_| Error::Encryption("Invalid scrypt parameters".to_string()))?;
+
+    let normalized: String = passphrase.nfc().collect();
+    let mut derived = [0u8; 64];
+    scrypt::scrypt(normalized.as_bytes(), address_hash, &params, &mut derived).map_err(|e| Error::Encryption(e.to_string()))?;
+
+    let mut half1 = [0u8; 32];
+    let mut half2 = [0u8; 32];
+    half1.copy_from_slice(&derived[..32]);
+    half2.copy_from_slice(&derived[32..]);
+
+    return Ok((half1, half2));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::params;
+
+    fn bitcoin() -> CoinParams {
+        CoinParams { taddress_version: [0x00], tsecret_prefix: [0x80], ..params() }
+    }
+
+    #[test]
+    fn test_bip38_vectors() {
+        // The test vectors without EC multiplication from BIP38
+        let vectors = [
+            ("TestingOneTwoThree", "5KN7MzqK5wt2TP1fQCYyHBtDrXdJuXbUzm4A9rKAteGu3Qi5CVR", "6PRVWUbkzzsbcVac2qwfssoUJAN1Xhrg6bNk8J7Nzm5H7kxEbn2Nh2ZoGg"),
+            ("TestingOneTwoThree", "L44B5gGEpqEDRS9vVPz7QT35jcBG2r3CZwSwQ4fCewXAhAhqGVpP", "6PYNKZ1EAgYgmQfmNVamxyXVWHzK5s6DGhwP4J5o44cvXdoY7sRzhtpUeo"),
+            ("\u{03d2}\u{0301}\u{0000}\u{10400}\u{1f4a9}", "5Jajm8eQ22H3pGWLEVCXyvND8dQZhiQhoLJNKjYXk9roUFTMSZ4", "6PRW5o9FLp4gJDDVqJQKJFTpMvdsSGJxMYHtHaQBF3ooa8mwD69bapcDQn"),
+        ];
+
+        for (passphrase, wif, encrypted) in vectors.iter() {
+            assert_eq!(encrypt_wif(wif, passphrase, &bitcoin()).unwrap(), *encrypted);
+            assert_eq!(decrypt_wif(encrypted, passphrase, &bitcoin()).unwrap(), *wif);
+            assert!(is_encrypted_wif(encrypted));
+        }
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let wif = "Up1YVLk7uuErCHVQyFCtfinZngmdwfyfc47WCQ8oJxgowjVzNeqs";
+        let encrypted = encrypt_wif(wif, "gift", &params()).unwrap();
+        assert!(encrypted.starts_with("6P"));
+        assert!(!is_encrypted_wif(wif));
+
+        assert_eq!(decrypt_wif(&encrypted, "gift", &params()).unwrap(), wif);
+        match decrypt_wif(&encrypted, "gfit", &params()) {
+            Err(Error::Decryption(_)) => (),
+            _                         => panic!("Expected a wrong passphrase")
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use hmac::Hmac;
+use sha2::{Digest, Sha256, Sha512};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::{Error, Result};
+
+/// The English BIP39 wordlist, one word per line. Every word is identified by its first 4 letters.
+const WORDLIST: &str = include_str!("bip39_wordlist.txt");
+
+/// Every word encodes 11 bits
+const RADIX_BITS: usize = 11;
+
+/// 128 to 256 bits of entropy, in steps of 32 bits, which make 12 to 24 words
+const MIN_ENTROPY_LENGTH: usize = 16;
+const MAX_ENTROPY_LENGTH: usize = 32;
+
+/// The seed is PBKDF2-HMAC-SHA512 of the mnemonic, salted with "mnemonic" and the passphrase
+const PBKDF2_ROUNDS: usize = 2048;
+pub const SEED_LENGTH: usize = 64;
+
+/// The mnemonic for `entropy`, which is 16, 20, 24, 28 or 32 bytes. 32 bytes give 24 words.
+pub fn entropy_to_mnemonic(entropy: &[u8]) -> Result<String> {
+    if entropy.len() < MIN_ENTROPY_LENGTH || entropy.len() > MAX_ENTROPY_LENGTH || entropy.len() % 4 != 0 {
+        return Err(Error::InvalidKey(format!("A BIP39 mnemonic encodes 16 to 32 bytes, in steps of 4. Got {}", entropy.len())));
+    }
+
+    // The entropy, followed by 1 bit of SHA256 of the entropy for every 4 bytes
+    let mut bits: Vec<bool> = entropy.iter().flat_map(|b| (0..8).rev().map(move |i| (b >> i) & 1 == 1)).collect();
+    let hash = Sha256::digest(entropy);
+    bits.extend((0..entropy.len() / 4).map(|i| (hash[i / 8] >> (7 - i % 8)) & 1 == 1));
+
+    let wordlist: Vec<&str> = WORDLIST.lines().collect();
+    let words: Vec<&str> = bits.chunks(RADIX_BITS)
+                               .map(|c| c.iter().fold(0usize, |acc, b| acc << 1 | *b as usize))
+                               .map(|i| wordlist[i])
+                               .collect();
+
+    return Ok(words.join(" "));
+}
+
+/// The entropy of a mnemonic, checking its checksum. Words can be abbreviated to their first 4 letters.
+pub fn mnemonic_to_entropy(mnemonic: &str) -> Result<Vec<u8>> {
+    let indices = mnemonic.split_whitespace().map(word_index).collect::<Result<Vec<usize>>>()?;
+    if indices.len() < 12 || indices.len() > 24 || indices.len() % 3 != 0 {
+        return Err(Error::InvalidKey(format!("A BIP39 mnemonic has 12, 15, 18, 21 or 24 words, got {}", indices.len())));
+    }
+
+    let bits: Vec<bool> = indices.iter().flat_map(|i| (0..RADIX_BITS).rev().map(move |b| (i >> b) & 1 == 1)).collect();
+    let checksum_bits = bits.len() / 33;
+    let (entropy_bits, checksum) = bits.split_at(bits.len() - checksum_bits);
+
+    let entropy: Vec<u8> = entropy_bits.chunks(8).map(|c| c.iter().fold(0u8, |acc, b| acc << 1 | *b as u8)).collect();
+    let hash = Sha256::digest(&entropy);
+    if (0..checksum_bits).any(|i| checksum[i] != ((hash[i / 8] >> (7 - i % 8)) & 1 == 1)) {
+        return Err(Error::InvalidKey("Checksum mismatch in the mnemonic. Some words may be mistyped, or in the wrong order".to_string()));
+    }
+
+    return Ok(entropy);
+}
+
+/// The mnemonic with every word written out in full and in lower case, checking its checksum
+pub fn normalize_mnemonic(mnemonic: &str) -> Result<String> {
+    return entropy_to_mnemonic(&mnemonic_to_entropy(mnemonic)?);
+}
+
+/// The 64 byte BIP39 seed of a mnemonic and a passphrase (the "25th word"). Any passphrase gives a valid seed,
+/// so a mistyped passphrase can only be noticed by the addresses being different.
+pub fn mnemonic_to_seed(mnemonic: &str, passphrase: &str) -> Result<[u8; SEED_LENGTH]> {
+    let mnemonic: String = normalize_mnemonic(mnemonic)?.nfkd().collect();
+    let salt: String = format!("mnemonic{}", passphrase).nfkd().collect();
+
+    let mut seed = [0u8; SEED_LENGTH];
+    pbkdf2::pbkdf2::<Hmac<Sha512>>(mnemonic.as_bytes(), salt.as_bytes(), PBKDF2_ROUNDS, &mut seed);
+
+    return Ok(seed);
+}
+
+/// The index of a word in the wordlist. Abbreviations of at least 4 letters are enough.
+fn word_index(word: &str) -> Result<usize> {
+    let word = word.to_lowercase();
+    if word.len() >= 4 {
+        if let Some(i) = WORDLIST.lines().position(|w| w.starts_with(&word)) {
+            return Ok(i);
+        }
+    }
+
+    return match WORDLIST.lines().position(|w| w == word) {
+        Some(i) => Ok(i),
+        None    => Err(Error::InvalidKey(format!("'{}' is not a BIP39 word", word)))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wordlist() {
+        let words: Vec<&str> = WORDLIST.lines().collect();
+        assert_eq!(words.len(), 1 << RADIX_BITS);
+
+        // Sorted, and every word is identified by its first 4 letters
+        for pair in words.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert_ne!(pair[0].chars().take(4).collect::<String>(), pair[1].chars().take(4).collect::<String>());
+        }
+    }
+
+    #[test]
+    fn test_bip39_vectors() {
+        // From the BIP39 test vectors, with the passphrase "TREZOR"
+        let vectors = [
+            ("00000000000000000000000000000000",
+             "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+             "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"),
+            ("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+             "legal winner thank year wave sausage worth useful legal winner thank yellow",
+             "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607"),
+            ("ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+             "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+             "dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e1613912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad"),
+        ];
+
+        for (entropy, mnemonic, seed) in vectors.iter() {
+            assert_eq!(entropy_to_mnemonic(&hex::decode(entropy).unwrap()).unwrap(), *mnemonic);
+            assert_eq!(hex::encode(mnemonic_to_entropy(mnemonic).unwrap()), *entropy);
+            assert_eq!(hex::encode(&mnemonic_to_seed(mnemonic, "TREZOR").unwrap()[..]), *seed);
+        }
+    }
+
+    #[test]
+    fn test_abbreviations_and_typos() {
+        let m = "legal winner thank year wave sausage worth useful legal winner thank yellow";
+        let abbreviated: Vec<String> = m.split(' ').map(|w| w.chars().take(4).collect::<String>().to_uppercase()).collect();
+        assert_eq!(normalize_mnemonic(&abbreviated.join(" ")).unwrap(), m);
+        assert_eq!(mnemonic_to_seed(&abbreviated.join(" "), "").unwrap()[..], mnemonic_to_seed(m, "").unwrap()[..]);
+
+        // Another passphrase is another seed, not an error
+        assert_ne!(mnemonic_to_seed(m, "a").unwrap()[..], mnemonic_to_seed(m, "").unwrap()[..]);
+
+        assert!(mnemonic_to_entropy(&m.replace("yellow", "year")).is_err());
+        assert!(mnemonic_to_entropy(&m.replace("yellow", "zzzz")).is_err());
+        assert!(mnemonic_to_entropy("legal winner thank").is_err());
+    }
+}
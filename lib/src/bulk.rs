@@ -0,0 +1,369 @@
+use rand::{ChaChaRng, Rng, SeedableRng};
+use std::sync::mpsc;
+use std::thread;
+use zcash_primitives::zip32::{ChildIndex, ExtendedSpendingKey};
+
+use crate::error::{Error, Result};
+use crate::paper::{check_seed_length, encode_taddress, params, random_secret_key, zaddr_entry, CoinParams, DerivationPath};
+use crate::selftest;
+use crate::wallet::{AddressKind, Derivation, KeyMaterial, WalletEntry};
+
+/// How many jobs, and how many finished entries, every worker thread can have waiting. This bounds the memory
+/// a stream uses, however large the batch is.
+const QUEUE_DEPTH: usize = 64;
+
+/// Every HD z-address whose index in the batch is a multiple of this is derived again all the way from the seed,
+/// instead of only from the parent key. A bad parent key would make every child wrong, so checking a sample is
+/// enough to catch it.
+const FULL_CHECK_EVERY: u32 = 16;
+
+/// Where the z-addresses of a stream get their seeds
+enum Seeds {
+    /// Every z-address is a child of the key at the base of the path, derived from one HD seed. The key and
+    /// its path are here along with the seed, after they were checked.
+    Hd(Vec<u8>, Vec<ChildIndex>, ExtendedSpendingKey),
+    /// Every z-address has a seed of its own of this length, drawn from the RNG, at the start of the path
+    PerAddress(ChaChaRng, usize),
+}
+
+/// The work for one entry. Anything random is drawn in order by the dispatcher, so the workers only do the
+/// slow part, and the entries are the same however many of them there are.
+enum Job {
+    /// A z-address, with its own seed, or `None` to derive it from the HD seed
+    ZAddress(u32, Option<Vec<u8>>),
+    /// A t-address, with its secret key
+    TAddress(u32, secp256k1::SecretKey),
+}
+
+/// The entries of a batch, one at a time: first the z-addresses, and then the t-addresses. Worker threads
+/// derive them in parallel, but they come out in order, so a batch of any size can be written out while it
+/// is being generated.
+///
+/// When the stream is dropped, even before the last entry, the threads are stopped and waited for, so no key
+/// is being derived any more once it's gone.
+pub struct EntryStream {
+    workers : Vec<mpsc::Receiver<Result<WalletEntry>>>,
+    threads : Vec<thread::JoinHandle<()>>,
+    total   : u64,
+    next    : u64,
+    failed  : bool,
+}
+
+/// Start deriving the batch of an HD seed on `threads` threads. The entries are the same as the ones of
+/// `wallet_from_seed`: the z-addresses are children at `path`, and the t-addresses come from an RNG seeded
+/// with the first 32 bytes of the seed.
+pub fn stream_wallet(seed: &[u8], zcount: u32, tcount: u32, path: &DerivationPath, threads: u32) -> Result<EntryStream> {
+    check_seed_length(seed.len())?;
+
+    // Fail before deriving anything if the last index doesn't fit in the path
+    if zcount > 0 {
+        path.child_indices(zcount - 1)?;
+    }
+
+    // The key at the base of the path is derived once, and checked once against a derivation from the master
+    // key. Every HD z-address is a child of it, and is checked against it.
+    let base: Vec<ChildIndex> = path.base.iter().map(|i| ChildIndex::Hardened(*i)).collect();
+    let parent = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(seed), &base);
+    selftest::check_parent(seed, &base, &parent)?;
+
+    return Ok(start(Seeds::Hd(seed.to_vec(), base, parent), zcount, tcount, path, threads));
+}
+
+/// Start deriving a batch without HD addresses on `threads` threads. Every z-address gets a new seed of
+/// `seed_length` bytes from `rng`, at the start of `path`, and the t-addresses come from an RNG seeded with a
+/// seed drawn before them. The same RNG always gives the same batch.
+pub fn stream_nohd_wallet(rng: ChaChaRng, seed_length: usize, zcount: u32, tcount: u32, path: &DerivationPath, threads: u32) -> Result<EntryStream> {
+    check_seed_length(seed_length)?;
+    path.child_indices(0)?;
+
+    return Ok(start(Seeds::PerAddress(rng, seed_length), zcount, tcount, path, threads));
+}
+
+fn start(seeds: Seeds, zcount: u32, tcount: u32, path: &DerivationPath, threads: u32) -> EntryStream {
+    let total = zcount as u64 + tcount as u64;
+    let threads = (threads.max(1) as u64).min(total.max(1)) as usize;
+
+    let hd = match &seeds {
+        Seeds::Hd(seed, base, parent) => Some((seed.clone(), base.clone(), parent.clone())),
+        Seeds::PerAddress(_, _)       => None
+    };
+
+    let mut jobs = Vec::new();
+    let mut workers = Vec::new();
+    let mut handles = Vec::new();
+    for _ in 0..threads {
+        let (job_tx, job_rx) = mpsc::sync_channel::<Job>(QUEUE_DEPTH);
+        let (tx, rx) = mpsc::sync_channel(QUEUE_DEPTH);
+        let (hd, path) = (hd.clone(), path.clone());
+
+        handles.push(thread::spawn(move || {
+            let coin = params();
+            for job in job_rx {
+                let entry = run_job(job, hd.as_ref(), &path, &coin);
+                let failed = entry.is_err();
+
+                // Stop if the stream was dropped, since nobody needs the rest
+                if tx.send(entry).is_err() || failed {
+                    return;
+                }
+            }
+        }));
+        jobs.push(job_tx);
+        workers.push(rx);
+    }
+
+    // Entry n goes to worker n % threads, which is where the stream takes it from
+    handles.push(thread::spawn(move || {
+        let mut n = 0;
+        let mut send = |job: Job| {
+            let sent = jobs[n % jobs.len()].send(job).is_ok();
+            n += 1;
+            return sent;
+        };
+
+        // The t-addresses of a batch without HD addresses are seeded with a seed drawn before the z-addresses
+        let (mut seeds, t_seed) = match seeds {
+            Seeds::Hd(seed, _, _)              => (None, seed),
+            Seeds::PerAddress(mut rng, length) => {
+                let first = draw_seed(&mut rng, length);
+                (Some((rng, length)), first)
+            }
+        };
+
+        for i in 0..zcount {
+            let seed = seeds.as_mut().map(|(rng, length)| draw_seed(rng, *length));
+            if !send(Job::ZAddress(i, seed)) {
+                return;
+            }
+        }
+
+        let mut rng_seed: [u8; 32] = [0; 32];
+        rng_seed.copy_from_slice(&t_seed[0..32]);
+        let mut t_rng = ChaChaRng::from_seed(rng_seed);
+
+        for i in 0..tcount {
+            if !send(Job::TAddress(i, random_secret_key(&mut t_rng))) {
+                return;
+            }
+        }
+    }));
+
+    return EntryStream { workers, threads: handles, total, next: 0, failed: false };
+}
+
+fn draw_seed(rng: &mut ChaChaRng, length: usize) -> Vec<u8> {
+    let mut seed = vec![0u8; length];
+    rng.fill(&mut seed[..]);
+    return seed;
+}
+
+/// Derive the entry of a job, and check it like `selftest::check_batch` does. Most HD z-addresses are checked
+/// against the parent key of the stream instead of being derived again all the way from the seed. Every
+/// `FULL_CHECK_EVERY`th one, starting with the first, is derived again from the seed.
+fn run_job(job: Job, hd: Option<&(Vec<u8>, Vec<ChildIndex>, ExtendedSpendingKey)>, path: &DerivationPath, coin: &CoinParams) -> Result<WalletEntry> {
+    let entry = match job {
+        Job::ZAddress(i, Some(seed)) => {
+            let spk = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(&seed), &path.child_indices(0)?);
+            zaddr_entry(i, &spk, Some(derivation(seed, path.path_of(0))))?
+        },
+        Job::ZAddress(i, None)       => {
+            let (seed, base, parent) = hd.ok_or_else(|| Error::Thread("No HD seed for a z-address".to_string()))?;
+            let spk = parent.derive_child(ChildIndex::Hardened(path.start + i));
+            let entry = zaddr_entry(i, &spk, Some(derivation(seed.clone(), path.path_of(i))))?;
+
+            if i % FULL_CHECK_EVERY != 0 {
+                selftest::check_child(&entry, seed, base, parent, coin)?;
+                return Ok(entry);
+            }
+            entry
+        },
+        Job::TAddress(i, sk)         => {
+            let (address, private_key) = encode_taddress(&sk, true);
+            WalletEntry {
+                num         : i,
+                kind        : AddressKind::Transparent,
+                address,
+                keys        : KeyMaterial { private_key, viewing_key: None },
+                derivation  : None,
+            }
+        }
+    };
+
+    selftest::check_entry(&entry, coin)?;
+
+    return Ok(entry);
+}
+
+fn derivation(hd_seed: Vec<u8>, path: String) -> Derivation {
+    Derivation { hd_seed, path, mnemonic: None, passphrase: false }
+}
+
+impl Iterator for EntryStream {
+    type Item = Result<WalletEntry>;
+
+    fn next(&mut self) -> Option<Result<WalletEntry>> {
+        if self.failed || self.next == self.total {
+            return None;
+        }
+
+        let worker = &self.workers[(self.next % self.workers.len() as u64) as usize];
+        self.next += 1;
+
+        let entry = match worker.recv() {
+            Ok(entry) => entry,
+            Err(_)    => Err(match self.stop() {
+                true  => Error::Thread("A key derivation thread stopped".to_string()),
+                false => Error::Thread("A key derivation thread panicked".to_string())
+            })
+        };
+
+        // Nothing after a failed entry can be trusted to be complete
+        self.failed = entry.is_err();
+
+        return Some(entry);
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = (self.total - self.next) as usize;
+        return (left, Some(left));
+    }
+}
+
+impl EntryStream {
+    /// Stop the threads and wait for them. Without the receiving ends, the workers stop at the next entry they
+    /// finish, and the dispatcher at the next job it hands out. Returns false if any of them panicked.
+    fn stop(&mut self) -> bool {
+        self.workers.clear();
+
+        let mut ok = true;
+        for handle in self.threads.drain(..) {
+            ok &= handle.join().is_ok();
+        }
+        return ok;
+    }
+}
+
+impl Drop for EntryStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::wallet_from_seed;
+
+    fn collect(stream: Result<EntryStream>) -> Vec<WalletEntry> {
+        stream.unwrap().collect::<Result<Vec<WalletEntry>>>().unwrap()
+    }
+
+    #[test]
+    fn test_stream_matches_batch() {
+        let seed: Vec<u8> = (0..32).collect();
+        let batch = wallet_from_seed(&seed, 7, 3, &DerivationPath::default(), 1, "test".to_string()).unwrap();
+
+        // The same entries in the same order, however many threads derive them
+        for threads in &[1, 2, 3, 16] {
+            let stream = stream_wallet(&seed, 7, 3, &DerivationPath::default(), *threads).unwrap();
+            assert_eq!(stream.size_hint(), (10, Some(10)));
+            assert_eq!(stream.collect::<Result<Vec<WalletEntry>>>().unwrap(), batch.entries);
+        }
+
+        let path = DerivationPath::account(3).unwrap().with_start(20).unwrap();
+        let batch = wallet_from_seed(&seed, 4, 0, &path, 1, "test".to_string()).unwrap();
+        assert_eq!(collect(stream_wallet(&seed, 4, 0, &path, 3)), batch.entries);
+    }
+
+    #[test]
+    fn test_parallel_taddresses() {
+        // The t-addresses are the ones drawn one after another from the RNG of the seed, so the t-addresses
+        // of batches made before they were derived in parallel are still recovered
+        let seed: Vec<u8> = (100..164).collect();
+        let mut rng_seed = [0u8; 32];
+        rng_seed.copy_from_slice(&seed[0..32]);
+        let mut rng = ChaChaRng::from_seed(rng_seed);
+
+        let entries = collect(stream_wallet(&seed, 0, 9, &DerivationPath::default(), 4));
+        assert_eq!(entries.len(), 9);
+        for (i, entry) in entries.iter().enumerate() {
+            let (address, wif) = encode_taddress(&random_secret_key(&mut rng), true);
+            assert_eq!((entry.num as usize, &entry.address, &entry.keys.private_key), (i, &address, &wif));
+        }
+    }
+
+    #[test]
+    fn test_nohd_stream() {
+        let rng = || ChaChaRng::from_seed([7u8; 32]);
+        let path = DerivationPath::account(2).unwrap();
+        let entries = collect(stream_nohd_wallet(rng(), 40, 5, 3, &path, 1));
+
+        for threads in &[2, 3, 8] {
+            assert_eq!(collect(stream_nohd_wallet(rng(), 40, 5, 3, &path, *threads)), entries);
+        }
+
+        // Every z-address has its own seed, at the start of the path
+        let seeds: Vec<&Vec<u8>> = entries[..5].iter().map(|e| &e.derivation.as_ref().unwrap().hd_seed).collect();
+        for (i, seed) in seeds.iter().enumerate() {
+            assert_eq!(seed.len(), 40);
+            assert!(!seeds[..i].contains(seed));
+        }
+        assert!(entries[..5].iter().all(|e| e.derivation.as_ref().unwrap().path == "m/32'/133'/2'/0'"));
+
+        assert!(stream_nohd_wallet(rng(), 16, 1, 0, &path, 1).is_err());
+    }
+
+    #[test]
+    fn test_bad_parent() {
+        let seed: Vec<u8> = (0..32).collect();
+        let path = DerivationPath::default();
+        let base = vec![ChildIndex::Hardened(32), ChildIndex::Hardened(133)];
+        let wrong = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(&seed), &[ChildIndex::Hardened(32), ChildIndex::Hardened(134)]);
+        let hd = (seed.clone(), base, wrong);
+
+        // Deriving a child of the wrong parent again from the same parent agrees with it, but the children that
+        // are derived again from the seed don't
+        assert!(run_job(Job::ZAddress(1, None), Some(&hd), &path, &params()).is_ok());
+        for i in &[0, FULL_CHECK_EVERY, 5 * FULL_CHECK_EVERY] {
+            match run_job(Job::ZAddress(*i, None), Some(&hd), &path, &params()) {
+                Err(Error::SelfTest(_)) => (),
+                r                       => panic!("Expected a self-test error, got {:?}", r)
+            };
+        }
+    }
+
+    #[test]
+    fn test_worker_panic() {
+        // A worker that panics after its first entry, instead of sending the rest
+        let entry = collect(stream_wallet(&[1; 32], 0, 1, &DerivationPath::default(), 1)).remove(0);
+        let (tx, rx) = mpsc::sync_channel(QUEUE_DEPTH);
+        let worker = thread::spawn(move || {
+            tx.send(Ok(entry)).unwrap();
+            panic!("Worker failed");
+        });
+
+        let mut stream = EntryStream { workers: vec![rx], threads: vec![worker], total: 3, next: 0, failed: false };
+        assert!(stream.next().unwrap().is_ok());
+        match stream.next() {
+            Some(Err(Error::Thread(msg))) => assert_eq!(msg, "A key derivation thread panicked"),
+            r                             => panic!("Expected a thread error, got {:?}", r)
+        };
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_stream_early_drop_and_errors() {
+        let seed: Vec<u8> = (0..64).collect();
+
+        // Taking only a few entries stops the workers instead of deriving the whole batch
+        let first: Vec<WalletEntry> = stream_wallet(&seed, 1_000_000, 1_000_000, &DerivationPath::default(), 4).unwrap()
+                                        .take(5).collect::<Result<_>>().unwrap();
+        assert_eq!(first.iter().map(|e| e.num).collect::<Vec<u32>>(), vec![0, 1, 2, 3, 4]);
+
+        assert_eq!(stream_wallet(&seed, 0, 0, &DerivationPath::default(), 4).unwrap().count(), 0);
+        assert!(stream_wallet(&seed[..16], 1, 0, &DerivationPath::default(), 1).is_err());
+
+        let last = DerivationPath::default().with_start((1 << 31) - 1).unwrap();
+        assert!(stream_wallet(&seed, 2, 0, &last, 1).is_err());
+    }
+}
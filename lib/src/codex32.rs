@@ -0,0 +1,429 @@
+use std::fmt;
+
+use crate::encryption::random_bytes;
+use crate::error::{Error, Result};
+
+/// The bech32 characters, in the order of their values
+const CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The share indices, in the order the shares are handed out. "s" is the index of the secret itself.
+const SHARE_INDICES: &str = "acdefghjklmnpqrtuvwxyz023456789";
+const SECRET_INDEX: char = 's';
+
+const HRP: &str = "ms";
+
+/// The header is the threshold, the 4 character identifier and the share index
+const HEADER_LENGTH: usize = 6;
+const IDENTIFIER_LENGTH: usize = 4;
+
+/// The checksum of a short codex32 string is 13 characters, and it protects up to 93 characters of data
+const CHECKSUM_LENGTH: usize = 13;
+const MAX_DATA_LENGTH: usize = 93;
+
+/// Seeds of 128 bits, up to what fits in a short codex32 string
+const MIN_SEED_LENGTH: usize = 16;
+const MAX_SEED_LENGTH: usize = (MAX_DATA_LENGTH - HEADER_LENGTH - CHECKSUM_LENGTH) * 5 / 8;
+
+/// At most 9 shares are needed, since the threshold is a single digit
+const MAX_THRESHOLD: u8 = 9;
+
+const MS32_CONST: u128 = 0x10ce0795c2fd1e62a;
+const MS32_GENERATOR: [u128; 5] = [0x19dc500ce73fde210, 0x1bfae00def77fe529, 0x1fbd920fffe7bee52, 0x1739640bdeee3fdad, 0x07729a039cfc75f5a];
+
+/// One codex32 share (BIP93), like "ms12namea320zyxwvutsrqpnmlkjhgfedcaxrpp870hkkqrm". The checksum can be
+/// checked, and the shares combined, by hand with the codex32 paper worksheets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Share {
+    /// How many shares are needed to recover the seed, or 0 for the seed itself, which isn't shared
+    pub threshold  : u8,
+    /// 4 random characters, the same for all shares of a seed
+    pub identifier : String,
+    /// The index of this share, a bech32 character. The seed itself is "s".
+    pub index      : char,
+    /// The payload, as 5 bit values
+    payload        : Vec<u8>,
+}
+
+impl Share {
+    /// Parse a codex32 string, checking its checksum. Upper case is fine, but not mixed case, and spaces between
+    /// the blocks of characters are ignored.
+    pub fn from_string(s: &str) -> Result<Share> {
+        let s: String = s.split_whitespace().collect();
+        if s.to_lowercase() != s && s.to_uppercase() != s {
+            return Err(Error::Shares(format!("'{}' mixes upper and lower case", s)));
+        }
+        let s = s.to_lowercase();
+
+        if !s.starts_with(&format!("{}1", HRP)) {
+            return Err(Error::Shares(format!("A codex32 share starts with '{}1'", HRP)));
+        }
+        let data = s[HRP.len() + 1..].chars().map(|c| match CHARSET.find(c) {
+            Some(v) => Ok(v as u8),
+            None    => Err(Error::Shares(format!("'{}' is not a codex32 character", c)))
+        }).collect::<Result<Vec<u8>>>()?;
+
+        if data.len() < HEADER_LENGTH + CHECKSUM_LENGTH + payload_length(MIN_SEED_LENGTH) || data.len() > MAX_DATA_LENGTH {
+            return Err(Error::Shares(format!("A codex32 share has {} to {} characters, got {}", HRP.len() + 1 + HEADER_LENGTH + CHECKSUM_LENGTH + payload_length(MIN_SEED_LENGTH), HRP.len() + 1 + MAX_DATA_LENGTH, s.len())));
+        }
+        if !ms32_verify_checksum(&data) {
+            return Err(Error::Shares(format!("Checksum mismatch in the share starting with '{}'", &s[..HRP.len() + 1 + HEADER_LENGTH])));
+        }
+
+        let payload = data[HEADER_LENGTH..data.len() - CHECKSUM_LENGTH].to_vec();
+        if payload.len() * 5 % 8 > 4 {
+            return Err(Error::Shares(format!("A payload of {} characters is not a whole number of bytes", payload.len())));
+        }
+
+        let chars: Vec<char> = s[HRP.len() + 1..].chars().collect();
+        let threshold = match chars[0].to_digit(10) {
+            Some(t) if t == 0 || (t >= 2 && t <= MAX_THRESHOLD as u32) => t as u8,
+            _ => return Err(Error::Shares(format!("The threshold '{}' should be 0, or 2 to {}", chars[0], MAX_THRESHOLD)))
+        };
+        let index = chars[HEADER_LENGTH - 1];
+        if threshold == 0 && index != SECRET_INDEX {
+            return Err(Error::Shares(format!("A share with threshold 0 is the seed itself, whose index is '{}'", SECRET_INDEX)));
+        }
+
+        return Ok(Share {
+            threshold,
+            identifier : chars[1..HEADER_LENGTH - 1].iter().collect(),
+            index,
+            payload,
+        });
+    }
+
+    /// The seed of an unshared secret, or of the share at index "s" that was recovered from the others
+    pub fn seed(&self) -> Result<Vec<u8>> {
+        if self.index != SECRET_INDEX {
+            return Err(Error::Shares(format!("Share '{}' is not the seed. Combine it with other shares", self.index)));
+        }
+
+        // The padding bits at the end are ignored
+        let mut seed = vec![];
+        let mut acc: u32 = 0;
+        let mut bits = 0;
+        for v in &self.payload {
+            acc = acc << 5 | *v as u32;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                seed.push((acc >> bits) as u8);
+                acc &= (1 << bits) - 1;
+            }
+        }
+
+        return Ok(seed);
+    }
+
+    /// The data part after "ms1", as 5 bit values, with the checksum
+    fn data(&self) -> Vec<u8> {
+        let mut data = vec![CHARSET.find((b'0' + self.threshold) as char).unwrap() as u8];
+        data.extend(self.identifier.chars().map(|c| CHARSET.find(c).unwrap() as u8));
+        data.push(CHARSET.find(self.index).unwrap() as u8);
+        data.extend_from_slice(&self.payload);
+
+        let checksum = ms32_create_checksum(&data);
+        data.extend_from_slice(&checksum);
+        return data;
+    }
+
+    fn from_data(data: &[u8]) -> Result<Share> {
+        let s: String = data.iter().map(|v| CHARSET.as_bytes()[*v as usize] as char).collect();
+        return Share::from_string(&format!("{}1{}", HRP, s));
+    }
+}
+
+impl fmt::Display for Share {
+    /// The codex32 string, in lower case. It's printed in upper case, which is easier to copy by hand.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data: String = self.data().iter().map(|v| CHARSET.as_bytes()[*v as usize] as char).collect();
+        write!(f, "{}1{}", HRP, data)
+    }
+}
+
+/// Whether `s` looks like a codex32 share rather than a SLIP-39 mnemonic, whose words have no digits
+pub fn is_codex32(s: &str) -> bool {
+    s.trim().to_lowercase().starts_with(&format!("{}1", HRP))
+}
+
+/// Split a seed into `count` codex32 shares, any `threshold` of which recover it. A threshold of 1 (with a
+/// count of 1) gives the seed itself as a single codex32 string.
+///
+/// The shares are combined again before they are returned, to make sure they recover the seed.
+pub fn generate_shares(seed: &[u8], threshold: u8, count: u8) -> Result<Vec<Share>> {
+    if seed.len() < MIN_SEED_LENGTH || seed.len() > MAX_SEED_LENGTH {
+        return Err(Error::Shares(format!("codex32 shares can hold seeds of {} to {} bytes, got {}", MIN_SEED_LENGTH, MAX_SEED_LENGTH, seed.len())));
+    }
+    if threshold == 0 || threshold > count || threshold > MAX_THRESHOLD || count as usize > SHARE_INDICES.len() {
+        return Err(Error::Shares(format!("Invalid {}of{}. There can be 1 to {} codex32 shares, and 1 to {} of them can be needed", threshold, count, SHARE_INDICES.len(), MAX_THRESHOLD)));
+    }
+    if threshold == 1 && count > 1 {
+        return Err(Error::Shares(format!("Invalid 1of{}. Every share would be a copy of the seed, so use 1of1 instead", count)));
+    }
+
+    let mut id_bytes = [0u8; IDENTIFIER_LENGTH];
+    random_bytes(&mut id_bytes)?;
+    let identifier: String = id_bytes.iter().map(|b| CHARSET.as_bytes()[(b & 0x1f) as usize] as char).collect();
+
+    if threshold == 1 {
+        let secret = Share { threshold: 0, identifier, index: SECRET_INDEX, payload: bytes_to_payload(seed) };
+        return Ok(vec![secret]);
+    }
+
+    // The seed and threshold - 1 random shares fix the polynomial, and the other shares are interpolated from them
+    let secret = Share { threshold, identifier: identifier.clone(), index: SECRET_INDEX, payload: bytes_to_payload(seed) };
+    let mut base = vec![secret.data()];
+
+    let mut shares = vec![];
+    for index in SHARE_INDICES.chars().take(count as usize) {
+        let share = if base.len() < threshold as usize {
+            let mut payload = vec![0u8; secret.payload.len()];
+            random_bytes(&mut payload)?;
+            let share = Share { threshold, identifier: identifier.clone(), index, payload: payload.iter().map(|v| v & 0x1f).collect() };
+            base.push(share.data());
+            share
+        } else {
+            Share::from_data(&interpolate(&base, CHARSET.find(index).unwrap() as u8))?
+        };
+        shares.push(share);
+    }
+
+    // Don't trust the shares until they give back the seed
+    let strings: Vec<String> = shares.iter().map(|s| s.to_string()).collect();
+    if combine_shares(&strings)? != seed || combine_shares(&strings[strings.len() - threshold as usize..])? != seed {
+        return Err(Error::SelfTest("The codex32 shares don't recover the seed".to_string()));
+    }
+
+    return Ok(shares);
+}
+
+/// Recover the seed from codex32 shares. Any shares beyond the threshold are checked against the others, so a
+/// share that was copied wrong (with a valid checksum) is caught.
+pub fn combine_shares<S: AsRef<str>>(strings: &[S]) -> Result<Vec<u8>> {
+    // The same share typed in twice doesn't count twice
+    let mut shares: Vec<Share> = vec![];
+    for s in strings {
+        let share = Share::from_string(s.as_ref())?;
+        if !shares.contains(&share) {
+            shares.push(share);
+        }
+    }
+    let first = shares.first().ok_or_else(|| Error::Shares("No shares".to_string()))?.clone();
+
+    for s in &shares {
+        if (s.threshold, &s.identifier, s.payload.len()) != (first.threshold, &first.identifier, first.payload.len()) {
+            return Err(Error::Shares("The shares are from different seeds".to_string()));
+        }
+    }
+    for (i, s) in shares.iter().enumerate() {
+        if shares[..i].iter().any(|o| o.index == s.index) {
+            return Err(Error::Shares(format!("There are two different shares '{}'", s.index)));
+        }
+    }
+
+    if let Some(secret) = shares.iter().find(|s| s.index == SECRET_INDEX) {
+        return secret.seed();
+    }
+    if first.threshold == 0 {
+        return first.seed();
+    }
+    if shares.len() < first.threshold as usize {
+        return Err(Error::Shares(format!("Need {} shares, but only got {}", first.threshold, shares.len())));
+    }
+
+    let (base, extra) = shares.split_at(first.threshold as usize);
+    let base: Vec<Vec<u8>> = base.iter().map(|s| s.data()).collect();
+    for s in extra {
+        if interpolate(&base, CHARSET.find(s.index).unwrap() as u8) != s.data() {
+            return Err(Error::Shares(format!("Share '{}' doesn't fit with the others. It may be mistyped, or from another set", s.index)));
+        }
+    }
+
+    return Share::from_data(&interpolate(&base, CHARSET.find(SECRET_INDEX).unwrap() as u8))?.seed();
+}
+
+/// The number of 5 bit characters for a seed of `length` bytes
+fn payload_length(length: usize) -> usize {
+    (length * 8 + 4) / 5
+}
+
+/// The seed as 5 bit values, padded with zero bits
+fn bytes_to_payload(bytes: &[u8]) -> Vec<u8> {
+    let mut payload = vec![];
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for b in bytes {
+        acc = acc << 8 | *b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            payload.push(((acc >> bits) & 0x1f) as u8);
+        }
+        acc &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        payload.push(((acc << (5 - bits)) & 0x1f) as u8);
+    }
+
+    return payload;
+}
+
+fn ms32_polymod(values: &[u8]) -> u128 {
+    let mut residue: u128 = 0x23181b3;
+    for v in values {
+        let b = residue >> 60;
+        residue = (residue & 0x0fff_ffff_ffff_ffff) << 5 ^ *v as u128;
+        for (i, g) in MS32_GENERATOR.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                residue ^= g;
+            }
+        }
+    }
+    return residue;
+}
+
+fn ms32_verify_checksum(data: &[u8]) -> bool {
+    ms32_polymod(data) == MS32_CONST
+}
+
+fn ms32_create_checksum(data: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let mut values = data.to_vec();
+    values.extend_from_slice(&[0; CHECKSUM_LENGTH]);
+    let polymod = ms32_polymod(&values) ^ MS32_CONST;
+
+    let mut checksum = [0u8; CHECKSUM_LENGTH];
+    for (i, c) in checksum.iter_mut().enumerate() {
+        *c = ((polymod >> (5 * (CHECKSUM_LENGTH - 1 - i))) & 0x1f) as u8;
+    }
+    return checksum;
+}
+
+/// GF(32) as bech32 uses it, modulo x^5 + x^3 + 1
+struct Gf32 {
+    exp : [u8; 31],
+    log : [u8; 32],
+}
+
+impl Gf32 {
+    fn new() -> Gf32 {
+        let mut tables = Gf32 { exp: [0; 31], log: [0; 32] };
+
+        let mut poly: u8 = 1;
+        for i in 0..31 {
+            tables.exp[i] = poly;
+            tables.log[poly as usize] = i as u8;
+
+            // Multiply by x
+            poly <<= 1;
+            if poly & 0x20 != 0 {
+                poly ^= 0x29;
+            }
+        }
+
+        return tables;
+    }
+}
+
+/// Evaluate the polynomial through the shares (the whole data part, checksum included) at index `x`, character
+/// by character, with Lagrange interpolation. The index of every share is its own character 5.
+fn interpolate(shares: &[Vec<u8>], x: u8) -> Vec<u8> {
+    if let Some(share) = shares.iter().find(|s| s[HEADER_LENGTH - 1] == x) {
+        return share.clone();
+    }
+
+    let gf = Gf32::new();
+    let log = |v: u8| gf.log[v as usize] as i32;
+    let indices: Vec<u8> = shares.iter().map(|s| s[HEADER_LENGTH - 1]).collect();
+
+    let log_product: i32 = indices.iter().map(|sx| log(sx ^ x)).sum();
+
+    let mut ans = vec![0u8; shares[0].len()];
+    for (sx, share) in indices.iter().zip(shares) {
+        let others: i32 = indices.iter().filter(|o| *o != sx).map(|o| log(sx ^ o)).sum();
+        let log_basis = (log_product - log(sx ^ x) - others).rem_euclid(31);
+
+        for (a, v) in ans.iter_mut().zip(share) {
+            if *v != 0 {
+                *a ^= gf.exp[((log(*v) + log_basis) % 31) as usize];
+            }
+        }
+    }
+
+    return ans;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codex32_vectors() {
+        // From the BIP93 test vectors
+        let secret = "ms10testsxxxxxxxxxxxxxxxxxxxxxxxxxx4nzvca9cmczlw";
+        assert_eq!(hex::encode(combine_shares(&[secret]).unwrap()), "318c6318c6318c6318c6318c6318c631");
+        assert_eq!(Share::from_string(secret).unwrap().to_string(), secret);
+
+        let shares = ["MS12NAMEA320ZYXWVUTSRQPNMLKJHGFEDCAXRPP870HKKQRM", "MS12NAMECACDEFGHJKLMNPQRSTUVWXYZ023FTR2GDZMPY6PN"];
+        assert_eq!(hex::encode(combine_shares(&shares).unwrap()), "d1808e096b35b209ca12132b264662a5");
+        assert_eq!(Share::from_data(&interpolate(&shares.iter().map(|s| Share::from_string(s).unwrap().data()).collect::<Vec<_>>(), 16)).unwrap().to_string(),
+                   "ms12names6xqguzttxkeqnjsjzv4jv3nz5k3kwgsphuh6evw");
+
+        let long = "ms10leetsllhdmn9m42vcsamx24zrxgs3qrl7ahwvhw4fnzrhve25gvezzyqqtum9pgv99ycma";
+        assert_eq!(hex::encode(combine_shares(&[long]).unwrap()), "ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100");
+
+        // One share isn't enough
+        match combine_shares(&shares[..1]) {
+            Err(Error::Shares(_)) => (),
+            _                     => panic!("Expected too few shares")
+        }
+    }
+
+    #[test]
+    fn test_generate_and_combine() {
+        let seed = [0x42u8; 32];
+        let shares: Vec<String> = generate_shares(&seed, 3, 5).unwrap().iter().map(|s| s.to_string()).collect();
+        assert_eq!(shares.len(), 5);
+        assert!(shares.iter().all(|s| s.starts_with("ms13") && s.len() == 74));
+        assert!(shares.iter().all(|s| is_codex32(s)));
+
+        // As printed, in upper case and in blocks
+        let printed: Vec<String> = shares.iter().map(|s| s.to_uppercase().as_bytes().chunks(4).map(|c| String::from_utf8_lossy(c).to_string()).collect::<Vec<_>>().join(" ")).collect();
+        assert_eq!(combine_shares(&printed[2..]).unwrap(), seed);
+
+        assert_eq!(combine_shares(&[&shares[4], &shares[0], &shares[2]]).unwrap(), seed);
+        assert_eq!(combine_shares(&shares.iter().map(|s| s.to_uppercase()).collect::<Vec<_>>()).unwrap(), seed);
+        assert!(combine_shares(&shares[..2]).is_err());
+
+        let single = generate_shares(&seed, 1, 1).unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].threshold, 0);
+        assert_eq!(single[0].seed().unwrap(), seed);
+
+        assert!(generate_shares(&seed, 1, 3).is_err());
+        assert!(generate_shares(&seed, 10, 12).is_err());
+        assert!(generate_shares(&[0u8; 8], 2, 3).is_err());
+    }
+
+    #[test]
+    fn test_typos() {
+        let share = "ms12namea320zyxwvutsrqpnmlkjhgfedcaxrpp870hkkqrm";
+        match Share::from_string(&share.replace("zyx", "zyz")) {
+            Err(Error::Shares(e)) => assert!(e.contains("Checksum"), "{}", e),
+            _                     => panic!("Expected a checksum mismatch")
+        }
+        assert!(Share::from_string("MS12NAMEa320zyxwvutsrqpnmlkjhgfedcaxrpp870hkkqrm").is_err());
+        assert!(Share::from_string("ms12namea320zyxwvutsrqpnmlkjhgfedcaxrpp870hkkqrb").is_err());
+        assert!(!is_codex32("shadow pistol academic"));
+
+        // A valid share that doesn't fit with the others
+        let seed = [7u8; 16];
+        let mut shares: Vec<String> = generate_shares(&seed, 2, 3).unwrap().iter().map(|s| s.to_string()).collect();
+        let other = generate_shares(&seed, 2, 3).unwrap();
+        let forged = Share { identifier: Share::from_string(&shares[0]).unwrap().identifier, ..other[2].clone() };
+        shares[2] = forged.to_string();
+        match combine_shares(&shares) {
+            Err(Error::Shares(e)) => assert!(e.contains("doesn't fit"), "{}", e),
+            _                     => panic!("Expected a share that doesn't fit")
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use base58::FromBase58;
+
+use crate::error::{Error, Result};
+use crate::paper::double_sha256;
+
+/// The bech32 character set, in the order of the 5 bit values they encode
+pub const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+const BECH32_GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+/// The bech32 checksum function over a list of 5 bit values. The checksum of a valid string is 1.
+pub fn bech32_polymod(values: &[u8]) -> u32 {
+    values.iter().fold(1, |chk, v| bech32_polymod_step(chk, *v))
+}
+
+/// Feed one more 5 bit value into the checksum. Apart from the initial value of 1, this is linear, so the
+/// effect of a substitution error on the checksum doesn't depend on the rest of the string.
+pub fn bech32_polymod_step(chk: u32, v: u8) -> u32 {
+    let b = chk >> 25;
+    let mut chk = ((chk & 0x1ffffff) << 5) ^ (v as u32);
+    for (i, g) in BECH32_GENERATOR.iter().enumerate() {
+        if (b >> i) & 1 == 1 {
+            chk ^= g;
+        }
+    }
+    return chk;
+}
+
+/// The human readable part expanded into 5 bit values, as it goes into the checksum
+pub fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut v: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    v.push(0);
+    v.extend(hrp.bytes().map(|b| b & 0x1f));
+    return v;
+}
+
+/// Split a bech32 string into the lowercased human readable part and the 5 bit values of the data part
+/// (including the checksum), without verifying the checksum.
+pub fn bech32_split(s: &str) -> Result<(String, Vec<u8>)> {
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(Error::InvalidKey("Mixed upper and lower case".to_string()));
+    }
+    let s = s.to_ascii_lowercase();
+
+    let sep = s.rfind('1').ok_or_else(|| Error::InvalidKey("Missing the '1' separator".to_string()))?;
+    let (hrp, data) = (&s[..sep], &s[sep+1..]);
+    if hrp.is_empty() || data.len() < 6 {
+        return Err(Error::InvalidKey("Too short".to_string()));
+    }
+
+    let values = data.chars().enumerate().map(|(i, c)| {
+        BECH32_CHARSET.iter().position(|b| *b as char == c).map(|v| v as u8)
+            .ok_or_else(|| Error::InvalidKey(format!("Invalid character '{}' at position {}", c, sep + 1 + i + 1)))
+    }).collect::<Result<Vec<u8>>>()?;
+
+    return Ok((hrp.to_string(), values));
+}
+
+/// Decode a bech32 string into its human readable part and data bytes.
+///
+/// The bech32 crate limits strings to 90 characters, which is too short for spending and viewing keys, so
+/// this has its own decoder.
+pub fn decode_bech32(s: &str) -> Result<(String, Vec<u8>)> {
+    let (hrp, values) = bech32_split(s)?;
+
+    let mut checked = bech32_hrp_expand(&hrp);
+    checked.extend_from_slice(&values);
+    if bech32_polymod(&checked) != 1 {
+        return Err(Error::InvalidKey("Bech32 checksum mismatch".to_string()));
+    }
+
+    let data = from_base32(&values[..values.len() - 6])?;
+    return Ok((hrp, data));
+}
+
+/// Regroup 5 bit values into bytes. The leftover padding bits have to be zero.
+fn from_base32(values: &[u8]) -> Result<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut ans = Vec::with_capacity(values.len() * 5 / 8);
+
+    for v in values {
+        acc = (acc << 5) | (*v as u32);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            ans.push((acc >> bits) as u8);
+        }
+    }
+
+    if bits >= 5 || (acc & ((1 << bits) - 1)) != 0 {
+        return Err(Error::InvalidKey("Invalid bech32 padding".to_string()));
+    }
+
+    return Ok(ans);
+}
+
+/// The base58 alphabet
+pub const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Decode a base58check string, returning the payload (including the version bytes) without the checksum
+pub fn decode_base58check(s: &str) -> Result<Vec<u8>> {
+    let mut payload = s.from_base58().map_err(|e| match e {
+        base58::FromBase58Error::InvalidBase58Character(c, i) => Error::InvalidKey(format!("Invalid character '{}' at position {}", c, i + 1)),
+        base58::FromBase58Error::InvalidBase58Length          => Error::InvalidKey("Invalid base58 length".to_string()),
+    })?;
+
+    if payload.len() < 5 {
+        return Err(Error::InvalidKey("Too short".to_string()));
+    }
+
+    let checksum = payload.split_off(payload.len() - 4);
+    if double_sha256(&payload)[..4] != checksum[..] {
+        return Err(Error::InvalidKey("Base58check checksum mismatch".to_string()));
+    }
+
+    return Ok(payload);
+}
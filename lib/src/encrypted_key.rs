@@ -0,0 +1,174 @@
+use bech32::{Bech32, ToBase32};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::encoding::decode_bech32;
+use crate::encryption::{random_bytes, KdfParams};
+use crate::error::{Error, Result};
+use crate::paper::{double_sha256, CoinParams};
+use crate::verify::{self, KeyKind};
+
+/// The version of the encrypted spending key format. Version 1 is scrypt with r=8 and p=1 (only N is stored),
+/// and ChaCha20-Poly1305.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// The fixed scrypt parameters of version 1
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// The random salt is short, so the encrypted key still fits in 8 lines on paper. The address checksum is
+/// salted in too, so two keys only get the same encryption key if both the salt and the checksum match.
+const SALT_LENGTH: usize = 8;
+const CHECKSUM_LENGTH: usize = 4;
+const HEADER_LENGTH: usize = 1 + 1 + SALT_LENGTH + CHECKSUM_LENGTH;
+
+/// Length of a serialized Sapling extended spending key
+const EXTENDED_KEY_LENGTH: usize = 169;
+const TAG_LENGTH: usize = 16;
+
+/// The bech32 prefix of an encrypted spending key, like "encrypted-secret-extended-key-main"
+pub fn encrypted_key_prefix(coin: &CoinParams) -> String {
+    format!("encrypted-{}", coin.zsecret_prefix)
+}
+
+/// Whether `s` looks like an encrypted Sapling spending key of this coin, so it should be decrypted with a
+/// passphrase before it can be used
+pub fn is_encrypted_spending_key(s: &str, coin: &CoinParams) -> bool {
+    s.trim().to_ascii_lowercase().starts_with(&format!("{}1", encrypted_key_prefix(coin)))
+}
+
+/// Encrypt a Sapling extended spending key (as printed by z_exportkey) with a passphrase.
+///
+/// There is no BIP38 for Sapling, so this is our own format: a bech32 string with the version, the scrypt
+/// cost, a random salt and a checksum of the default address of the key, followed by the key encrypted with
+/// ChaCha20-Poly1305. The header is authenticated along with the key, and the checksum lets us check that the
+/// decrypted key is the key for the address on the paper.
+pub fn encrypt_spending_key(key: &str, passphrase: &str, coin: &CoinParams, kdf: &KdfParams) -> Result<String> {
+    if kdf.r != SCRYPT_R || kdf.p != SCRYPT_P {
+        return Err(Error::Encryption(format!("Encrypted spending keys need scrypt r={} and p={}", SCRYPT_R, SCRYPT_P)));
+    }
+    kdf.check().map_err(|e| Error::Encryption(e.to_string()))?;
+
+    let decoded = verify::decode(key, coin)?;
+    if decoded.kind != KeyKind::SpendingKey {
+        return Err(Error::InvalidKey(format!("Expected a spending key, but got a {}", decoded.kind.description())));
+    }
+    let (_, plaintext) = decode_bech32(key.trim())?;
+
+    let mut header = vec![FORMAT_VERSION, kdf.log_n];
+    let mut salt = [0u8; SALT_LENGTH];
+    random_bytes(&mut salt)?;
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&address_checksum(&decoded.address));
+
+    let ciphertext = cipher(passphrase, &header, kdf)?
+                        .encrypt(&Nonce::from([0u8; 12]), Payload { msg: &plaintext, aad: &header })
+                        .map_err(|_| Error::Encryption("Couldn't encrypt the spending key".to_string()))?;
+
+    let mut data = header;
+    data.extend_from_slice(&ciphertext);
+
+    let encoded = Bech32::new(encrypted_key_prefix(coin), data.to_base32()).map_err(|e| Error::KeyEncoding(e.to_string()))?;
+    return Ok(encoded.to_string());
+}
+
+/// Decrypt an encrypted spending key with the passphrase, returning the spending key.
+///
+/// A wrong passphrase (or a typo in the encrypted key that the bech32 checksum didn't catch) fails the
+/// authentication.
+pub fn decrypt_spending_key(encrypted: &str, passphrase: &str, coin: &CoinParams) -> Result<String> {
+    let (hrp, data) = decode_bech32(encrypted.trim())?;
+    if hrp != encrypted_key_prefix(coin) {
+        return Err(Error::InvalidKey(format!("Expected a key starting with '{}', got '{}'", encrypted_key_prefix(coin), hrp)));
+    }
+
+    if data.is_empty() || data[0] != FORMAT_VERSION {
+        return Err(Error::Decryption(format!("Unknown encrypted spending key version {}", data.get(0).unwrap_or(&0))));
+    }
+    if data.len() != HEADER_LENGTH + EXTENDED_KEY_LENGTH + TAG_LENGTH {
+        return Err(Error::InvalidKey(format!("An encrypted spending key has {} bytes, got {}", HEADER_LENGTH + EXTENDED_KEY_LENGTH + TAG_LENGTH, data.len())));
+    }
+
+    let (header, ciphertext) = data.split_at(HEADER_LENGTH);
+    let kdf = KdfParams { log_n: header[1], r: SCRYPT_R, p: SCRYPT_P };
+    kdf.check()?;
+
+    let plaintext = cipher(passphrase, header, &kdf)?
+                        .decrypt(&Nonce::from([0u8; 12]), Payload { msg: ciphertext, aad: header })
+                        .map_err(|_| Error::Decryption("Wrong passphrase".to_string()))?;
+
+    let key = Bech32::new(coin.zsecret_prefix.clone(), plaintext.to_base32()).map_err(|e| Error::KeyEncoding(e.to_string()))?.to_string();
+
+    // The key was authenticated, so a mismatch here means it was encrypted with the wrong address
+    let decoded = verify::decode(&key, coin)?;
+    if address_checksum(&decoded.address)[..] != header[HEADER_LENGTH - CHECKSUM_LENGTH..] {
+        return Err(Error::Decryption("The decrypted key doesn't match the address checksum".to_string()));
+    }
+
+    return Ok(key);
+}
+
+/// The first 4 bytes of SHA256d of the default z-address of the key
+fn address_checksum(address: &str) -> [u8; CHECKSUM_LENGTH] {
+    let mut checksum = [0u8; CHECKSUM_LENGTH];
+    checksum.copy_from_slice(&double_sha256(address.as_bytes())[..CHECKSUM_LENGTH]);
+    return checksum;
+}
+
+/// The cipher keyed with scrypt of the NFC normalized passphrase, salted with the random salt and the address
+/// checksum. Every key is unique, so the nonce is always zero.
+fn cipher(passphrase: &str, header: &[u8], kdf: &KdfParams) -> Result<ChaCha20Poly1305> {
+    let normalized: String = passphrase.nfc().collect();
+    let key = kdf.derive_key(&normalized, &header[2..])?;
+    return Ok(ChaCha20Poly1305::new(&Key::from(key)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::{encode_privatekey, params};
+    use zcash_primitives::zip32::ExtendedSpendingKey;
+
+    fn spending_key() -> String {
+        encode_privatekey(&ExtendedSpendingKey::master(&[7; 32])).unwrap()
+    }
+
+    /// Cheap scrypt parameters, to keep the tests fast
+    fn fast() -> KdfParams {
+        KdfParams { log_n: 10, r: SCRYPT_R, p: SCRYPT_P }
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let key = spending_key();
+        let encrypted = encrypt_spending_key(&key, "TestingOneTwoThree", &params(), &fast()).unwrap();
+        assert!(encrypted.starts_with("encrypted-secret-extended-key-main1"));
+        assert!(is_encrypted_spending_key(&encrypted, &params()));
+        assert!(!is_encrypted_spending_key(&key, &params()));
+
+        // Fits in 8 lines of 45 characters on paper
+        assert!(encrypted.len() <= 8 * 45);
+
+        assert_eq!(decrypt_spending_key(&encrypted, "TestingOneTwoThree", &params()).unwrap(), key);
+        assert_eq!(decrypt_spending_key(&encrypted.to_ascii_uppercase(), "TestingOneTwoThree", &params()).unwrap(), key);
+
+        match decrypt_spending_key(&encrypted, "TestingOneTwoThre", &params()) {
+            Err(Error::Decryption(_)) => (),
+            _                         => panic!("Expected a wrong passphrase")
+        }
+
+        // The salt is random, so the same key encrypts differently every time
+        assert_ne!(encrypt_spending_key(&key, "TestingOneTwoThree", &params(), &fast()).unwrap(), encrypted);
+    }
+
+    #[test]
+    fn test_only_spending_keys() {
+        let key = spending_key();
+        let address = verify::decode(&key, &params()).unwrap().address;
+        match encrypt_spending_key(&address, "pass", &params(), &fast()) {
+            Err(Error::InvalidKey(_)) => (),
+            _                         => panic!("Expected an invalid key")
+        }
+    }
+}
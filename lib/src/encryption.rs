@@ -0,0 +1,650 @@
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use bech32::{Bech32, ToBase32};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use hkdf::Hkdf;
+use json::object;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::encoding::decode_bech32;
+use crate::error::{Error, Result};
+
+/// The version of the encrypted file format written by `encrypt_with_passphrase` and `encrypt_to_recipients`
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The "format" field of an encrypted file, to tell it apart from plaintext wallet files
+const FORMAT_NAME: &str = "silentdragonpaper-encrypted";
+
+const CIPHER_NAME: &str = "chacha20poly1305";
+
+/// The bech32 prefixes of recipients (public keys) and identities (their secret keys)
+const RECIPIENT_PREFIX: &str = "sdppub";
+const IDENTITY_PREFIX: &str = "sdpsecret";
+
+/// The HKDF info string for the key that wraps the file key for a recipient
+const WRAP_INFO: &[u8] = b"silentdragonpaper-x25519";
+
+const SALT_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+const KEY_LENGTH: usize = 32;
+const TAG_LENGTH: usize = 16;
+
+/// Files asking scrypt for more memory than this are refused, so a corrupted or malicious header can't
+/// exhaust the memory of the (offline) machine
+const MAX_KDF_MEMORY: u64 = 1 << 30;
+
+/// The scrypt parameters used to derive the encryption key from the passphrase
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KdfParams {
+    /// log2 of the scrypt cost N
+    pub log_n : u8,
+    /// The scrypt block size
+    pub r     : u32,
+    /// The scrypt parallelization
+    pub p     : u32,
+}
+
+impl Default for KdfParams {
+    /// 128 MiB of memory, which takes about a second on a laptop
+    fn default() -> KdfParams {
+        KdfParams { log_n: 17, r: 8, p: 1 }
+    }
+}
+
+impl KdfParams {
+    /// The memory scrypt needs with these parameters, in bytes
+    pub fn memory(&self) -> u64 {
+        (128 * self.r as u64) << self.log_n
+    }
+
+    fn to_json(&self, salt: &[u8]) -> json::JsonValue {
+        object!{
+            "name"  => "scrypt",
+            "log_n" => self.log_n,
+            "r"     => self.r,
+            "p"     => self.p,
+            "salt"  => hex::encode(salt)
+        }
+    }
+
+    fn from_json(kv: &json::JsonValue) -> Result<KdfParams> {
+        if kv["name"].as_str() != Some("scrypt") {
+            return Err(Error::Decryption(format!("Unknown key derivation function '{}'", kv["name"])));
+        }
+
+        let params = KdfParams {
+            log_n : kv["log_n"].as_u8().ok_or_else(|| invalid_header("kdf.log_n"))?,
+            r     : kv["r"].as_u32().ok_or_else(|| invalid_header("kdf.r"))?,
+            p     : kv["p"].as_u32().ok_or_else(|| invalid_header("kdf.p"))?,
+        };
+
+        params.check()?;
+        return Ok(params);
+    }
+
+    /// Refuse parameters read from a file that are out of range, or would need too much memory
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.log_n == 0 || self.log_n >= 32 || self.r == 0 || self.p == 0 || self.p > 16 || self.memory() > MAX_KDF_MEMORY {
+            return Err(Error::Decryption(format!("Unsupported scrypt parameters log_n={} r={} p={}", self.log_n, self.r, self.p)));
+        }
+        return Ok(());
+    }
+
+    /// How long one key derivation with these parameters takes on this machine, to estimate the time of a
+    /// large batch before starting it
+    pub fn benchmark(&self) -> Result<Duration> {
+        self.check()?;
+        let started = Instant::now();
+        self.derive_key("benchmark", &[0u8; 16])?;
+        return Ok(started.elapsed());
+    }
+
+    pub(crate) fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LENGTH]> {
+        let params = scrypt::ScryptParams::new(self.log_n, self.r, self.p)
+                        .map_err(|_| Error::Encryption(format!("Invalid scrypt parameters log_n={} r={} p={}", self.log_n, self.r, self.p)))?;
+
+        let mut key = [0u8; KEY_LENGTH];
+        scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key).map_err(|e| Error::Encryption(e.to_string()))?;
+
+        return Ok(key);
+    }
+}
+
+/// An X25519 public key that files can be encrypted to, written as a bech32 string starting with "sdppub1"
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Recipient(PublicKey);
+
+/// The secret key of a recipient, written as a bech32 string starting with "sdpsecret1". Only the holder of
+/// the identity can decrypt files encrypted to its recipient.
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    /// A new random identity
+    pub fn generate() -> Result<Identity> {
+        let mut secret = [0u8; KEY_LENGTH];
+        random_bytes(&mut secret)?;
+        return Ok(Identity(StaticSecret::from(secret)));
+    }
+
+    /// The public key to give out, so others can encrypt files to this identity
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+
+    /// The contents of an identity file: the identity, with its recipient in a comment
+    pub fn to_file_contents(&self) -> String {
+        format!("# SilentDragonPaper identity. Keep this file secret.\n# recipient: {}\n{}\n", self.recipient(), self)
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", encode_key(RECIPIENT_PREFIX, self.0.as_bytes()).map_err(|_| fmt::Error)?)
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", encode_key(IDENTITY_PREFIX, &self.0.to_bytes()).map_err(|_| fmt::Error)?)
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Recipient> {
+        Ok(Recipient(PublicKey::from(decode_key(RECIPIENT_PREFIX, s)?)))
+    }
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Identity> {
+        Ok(Identity(StaticSecret::from(decode_key(IDENTITY_PREFIX, s)?)))
+    }
+}
+
+/// Read an identity file written with `Identity::to_file_contents`. Lines starting with '#' are comments.
+pub fn read_identity_file(filename: &str) -> Result<Identity> {
+    let lines = key_file_lines(filename)?;
+    if lines.len() != 1 {
+        return Err(Error::InvalidKey(format!("{}: Expected a single identity, found {} lines", filename, lines.len())));
+    }
+
+    lines[0].1.parse().map_err(|e| match e {
+        Error::InvalidKey(msg) => Error::InvalidKey(format!("{}: {}", filename, msg)),
+        e                      => e
+    })
+}
+
+/// Read a recipients file, with one recipient per line. Lines starting with '#' are comments.
+pub fn read_recipients_file(filename: &str) -> Result<Vec<Recipient>> {
+    let recipients = key_file_lines(filename)?.iter()
+        .map(|(i, line)| line.parse().map_err(|e| match e {
+            Error::InvalidKey(msg) => Error::InvalidKey(format!("{} line {}: {}", filename, i + 1, msg)),
+            e                      => e
+        }))
+        .collect::<Result<Vec<Recipient>>>()?;
+
+    if recipients.is_empty() {
+        return Err(Error::InvalidKey(format!("{}: No recipients found", filename)));
+    }
+
+    return Ok(recipients);
+}
+
+/// A file encrypted along with the wallet, like the PDF of the wallet
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attachment {
+    /// The file name, without any directories
+    pub name : String,
+    pub data : Vec<u8>,
+}
+
+/// The decrypted contents of an encrypted file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decrypted {
+    pub plaintext  : Vec<u8>,
+    pub attachment : Option<Attachment>,
+}
+
+/// How a file is encrypted, which says what is needed to decrypt it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EncryptionKind {
+    Passphrase,
+    /// Encrypted to the public keys of one or more recipients
+    Recipients,
+}
+
+/// What to decrypt a file with
+#[derive(Clone)]
+pub enum DecryptionKey {
+    Passphrase(String),
+    Identity(Identity),
+}
+
+/// How `contents` is encrypted, or None if it isn't an encrypted file (like a plaintext wallet file)
+pub fn encryption_kind(contents: &str) -> Option<EncryptionKind> {
+    let value = json::parse(contents).ok()?;
+    if value["format"].as_str() != Some(FORMAT_NAME) {
+        return None;
+    }
+
+    if value.has_key("recipients") {
+        Some(EncryptionKind::Recipients)
+    } else {
+        Some(EncryptionKind::Passphrase)
+    }
+}
+
+/// Encrypt `plaintext` (usually the JSON form of a wallet batch), and optionally an attachment, with a key
+/// derived from `passphrase` with scrypt, using ChaCha20-Poly1305.
+///
+/// The result is a JSON document with a versioned header that has everything needed to decrypt it except the
+/// passphrase: the scrypt parameters, a random salt and random nonces. The header is authenticated along with
+/// the ciphertext, so changing any of it makes decryption fail.
+pub fn encrypt_with_passphrase(plaintext: &[u8], passphrase: &str, params: &KdfParams, attachment: Option<&Attachment>) -> Result<String> {
+    if passphrase.is_empty() {
+        return Err(Error::Encryption("The passphrase is empty".to_string()));
+    }
+
+    let mut salt = [0u8; SALT_LENGTH];
+    random_bytes(&mut salt)?;
+
+    let key = params.derive_key(passphrase, &salt)?;
+    seal(&key, ("kdf", params.to_json(&salt)), plaintext, attachment)
+}
+
+/// Encrypt `plaintext`, and optionally an attachment, so that any one of the `recipients` can decrypt it with
+/// their identity, and nobody else (including whoever encrypted it) can.
+///
+/// This works like age: the contents are encrypted with ChaCha20-Poly1305 under a random file key, and the
+/// file key is wrapped for every recipient with a key agreed between the recipient and a fresh ephemeral
+/// X25519 key, through HKDF-SHA256.
+pub fn encrypt_to_recipients(plaintext: &[u8], recipients: &[Recipient], attachment: Option<&Attachment>) -> Result<String> {
+    if recipients.is_empty() {
+        return Err(Error::Encryption("No recipients".to_string()));
+    }
+
+    let mut file_key = [0u8; KEY_LENGTH];
+    random_bytes(&mut file_key)?;
+
+    let stanzas = recipients.iter().map(|r| wrap_file_key(&file_key, r)).collect::<Result<Vec<Stanza>>>()?;
+    seal(&file_key, ("recipients", stanzas_json(&stanzas)), plaintext, attachment)
+}
+
+/// Decrypt a file written by `encrypt_with_passphrase` or `encrypt_to_recipients`.
+///
+/// A wrong passphrase and a file that was changed after it was written can't be told apart, and give the
+/// same error.
+pub fn decrypt(contents: &str, key: &DecryptionKey) -> Result<Decrypted> {
+    let value = json::parse(contents).map_err(|e| Error::Decryption(e.to_string()))?;
+    let kind = encryption_kind(contents).ok_or_else(|| Error::Decryption("Not an encrypted SilentDragonPaper file".to_string()))?;
+
+    let version = value["version"].as_u32().ok_or_else(|| invalid_header("version"))?;
+    if version > FORMAT_VERSION {
+        return Err(Error::Decryption(format!("Encrypted file format version {} is newer than the supported version {}. Please upgrade.", version, FORMAT_VERSION)));
+    }
+
+    if value["cipher"]["name"].as_str() != Some(CIPHER_NAME) {
+        return Err(Error::Decryption(format!("Unknown cipher '{}'", value["cipher"]["name"])));
+    }
+    let nonce = nonce_field(&value["cipher"])?;
+
+    let attachment = if value.has_key("attachment") {
+        let name = value["attachment"]["name"].as_str().ok_or_else(|| invalid_header("attachment.name"))?;
+        Some((name.to_string(), nonce_field(&value["attachment"])?))
+    } else {
+        None
+    };
+
+    let (protection, file_key, wrong_key) = match (kind, key) {
+        (EncryptionKind::Passphrase, DecryptionKey::Passphrase(passphrase)) => {
+            let params = KdfParams::from_json(&value["kdf"])?;
+            let salt = hex_field(&value["kdf"], "salt", Some(SALT_LENGTH))?;
+            (("kdf", params.to_json(&salt)), params.derive_key(passphrase, &salt)?, "Wrong passphrase, or the file is corrupted")
+        },
+        (EncryptionKind::Recipients, DecryptionKey::Identity(identity)) => {
+            let stanzas = stanzas_from_json(&value["recipients"])?;
+            (("recipients", stanzas_json(&stanzas)), unwrap_file_key(identity, &stanzas)?, "The file is corrupted")
+        },
+        (EncryptionKind::Passphrase, _) => return Err(Error::Decryption("The file is encrypted with a passphrase".to_string())),
+        (EncryptionKind::Recipients, _) => return Err(Error::Decryption("The file is encrypted to a public key, and needs an identity to decrypt".to_string())),
+    };
+
+    // The header is authenticated in our own serialization, so it doesn't depend on how the file was formatted
+    let header = header_json(protection, &nonce, attachment.as_ref().map(|(name, nonce)| (name.as_str(), nonce)));
+    let aad = json::stringify(header);
+
+    let cipher = ChaCha20Poly1305::new(&Key::from(file_key));
+    let open = |nonce: &[u8; NONCE_LENGTH], field: &str| -> Result<Vec<u8>> {
+        cipher.decrypt(&Nonce::from(*nonce), Payload { msg: &hex_field(&value, field, None)?, aad: aad.as_bytes() })
+            .map_err(|_| Error::Decryption(wrong_key.to_string()))
+    };
+
+    let plaintext = open(&nonce, "ciphertext")?;
+    let attachment = match attachment {
+        Some((name, nonce)) => Some(Attachment { name, data: open(&nonce, "attachment_ciphertext")? }),
+        None                => None
+    };
+
+    return Ok(Decrypted { plaintext, attachment });
+}
+
+/// The file key, wrapped for one recipient
+struct Stanza {
+    ephemeral   : [u8; KEY_LENGTH],
+    wrapped_key : Vec<u8>,
+}
+
+fn wrap_file_key(file_key: &[u8; KEY_LENGTH], recipient: &Recipient) -> Result<Stanza> {
+    let mut secret = [0u8; KEY_LENGTH];
+    random_bytes(&mut secret)?;
+    let ephemeral = StaticSecret::from(secret);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    let wrap_key = wrap_key(&ephemeral.diffie_hellman(&recipient.0).to_bytes(), &ephemeral_public, &recipient.0)?;
+    let wrapped_key = ChaCha20Poly1305::new(&Key::from(wrap_key)).encrypt(&Nonce::from([0u8; NONCE_LENGTH]), &file_key[..])
+                        .map_err(|_| Error::Encryption("ChaCha20-Poly1305 encryption failed".to_string()))?;
+
+    return Ok(Stanza { ephemeral: ephemeral_public.to_bytes(), wrapped_key });
+}
+
+/// Try to unwrap the file key from each stanza with the identity
+fn unwrap_file_key(identity: &Identity, stanzas: &[Stanza]) -> Result<[u8; KEY_LENGTH]> {
+    let recipient = identity.recipient();
+
+    for stanza in stanzas {
+        let ephemeral_public = PublicKey::from(stanza.ephemeral);
+        let wrap_key = match wrap_key(&identity.0.diffie_hellman(&ephemeral_public).to_bytes(), &ephemeral_public, &recipient.0) {
+            Ok(k)  => k,
+            Err(_) => continue
+        };
+
+        // Every wrap key is only used once, so a fixed nonce is fine
+        if let Ok(file_key) = ChaCha20Poly1305::new(&Key::from(wrap_key)).decrypt(&Nonce::from([0u8; NONCE_LENGTH]), &stanza.wrapped_key[..]) {
+            let mut key = [0u8; KEY_LENGTH];
+            key.copy_from_slice(&file_key);
+            return Ok(key);
+        }
+    }
+
+    return Err(Error::Decryption(format!("The file isn't encrypted to {}", recipient)));
+}
+
+/// The key that wraps the file key for a recipient, from the X25519 shared secret of the ephemeral key and
+/// the recipient
+fn wrap_key(shared: &[u8; KEY_LENGTH], ephemeral: &PublicKey, recipient: &PublicKey) -> Result<[u8; KEY_LENGTH]> {
+    // A low order point gives an all zero shared secret, which anyone can compute
+    if shared.iter().all(|b| *b == 0) {
+        return Err(Error::Decryption("Invalid ephemeral key".to_string()));
+    }
+
+    let mut salt = ephemeral.as_bytes().to_vec();
+    salt.extend_from_slice(recipient.as_bytes());
+
+    let mut key = [0u8; KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(&salt), shared).expand(WRAP_INFO, &mut key)
+        .map_err(|_| Error::Encryption("HKDF failed".to_string()))?;
+
+    return Ok(key);
+}
+
+fn stanzas_json(stanzas: &[Stanza]) -> json::JsonValue {
+    json::JsonValue::Array(stanzas.iter().map(|s| object!{
+        "type"        => "x25519",
+        "ephemeral"   => hex::encode(s.ephemeral),
+        "wrapped_key" => hex::encode(&s.wrapped_key)
+    }).collect())
+}
+
+fn stanzas_from_json(value: &json::JsonValue) -> Result<Vec<Stanza>> {
+    if !value.is_array() || value.is_empty() {
+        return Err(invalid_header("recipients"));
+    }
+
+    value.members().map(|kv| {
+        if kv["type"].as_str() != Some("x25519") {
+            return Err(Error::Decryption(format!("Unknown recipient type '{}'", kv["type"])));
+        }
+
+        let mut ephemeral = [0u8; KEY_LENGTH];
+        ephemeral.copy_from_slice(&hex_field(kv, "ephemeral", Some(KEY_LENGTH))?);
+        Ok(Stanza { ephemeral, wrapped_key: hex_field(kv, "wrapped_key", Some(KEY_LENGTH + TAG_LENGTH))? })
+    }).collect()
+}
+
+/// Encrypt the plaintext and the attachment under `key` with random nonces, and write out the file.
+/// `protection` is the part of the header that says how to get the key.
+fn seal(key: &[u8; KEY_LENGTH], protection: (&str, json::JsonValue), plaintext: &[u8], attachment: Option<&Attachment>) -> Result<String> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    random_bytes(&mut nonce)?;
+
+    let mut attachment_nonce = [0u8; NONCE_LENGTH];
+    random_bytes(&mut attachment_nonce)?;
+
+    let header = header_json(protection, &nonce, attachment.map(|a| (a.name.as_str(), &attachment_nonce)));
+    let aad = json::stringify(header.clone());
+
+    let cipher = ChaCha20Poly1305::new(&Key::from(*key));
+    let encrypt = |nonce: &[u8; NONCE_LENGTH], msg: &[u8]| {
+        cipher.encrypt(&Nonce::from(*nonce), Payload { msg, aad: aad.as_bytes() })
+            .map_err(|_| Error::Encryption("ChaCha20-Poly1305 encryption failed".to_string()))
+    };
+
+    let mut file = header;
+    file["ciphertext"] = hex::encode(encrypt(&nonce, plaintext)?).into();
+    if let Some(a) = attachment {
+        file["attachment_ciphertext"] = hex::encode(encrypt(&attachment_nonce, &a.data)?).into();
+    }
+
+    return Ok(json::stringify_pretty(file, 2));
+}
+
+fn header_json(protection: (&str, json::JsonValue), nonce: &[u8; NONCE_LENGTH], attachment: Option<(&str, &[u8; NONCE_LENGTH])>) -> json::JsonValue {
+    let mut header = object!{
+        "format"  => FORMAT_NAME,
+        "version" => FORMAT_VERSION
+    };
+    header[protection.0] = protection.1;
+    header["cipher"] = object!{
+        "name"  => CIPHER_NAME,
+        "nonce" => hex::encode(nonce)
+    };
+
+    if let Some((name, nonce)) = attachment {
+        header["attachment"] = object!{
+            "name"  => name,
+            "nonce" => hex::encode(nonce)
+        };
+    }
+
+    return header;
+}
+
+/// The non-comment lines of a key file, with their (0 based) line numbers
+fn key_file_lines(filename: &str) -> Result<Vec<(usize, String)>> {
+    let contents = fs::read_to_string(filename).map_err(|e| Error::Io(filename.to_string(), e))?;
+
+    Ok(contents.lines().enumerate()
+        .map(|(i, line)| (i, line.trim().to_string()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
+fn encode_key(hrp: &str, key: &[u8]) -> Result<String> {
+    let encoded = Bech32::new(hrp.to_string(), key.to_base32()).map_err(|e| Error::KeyEncoding(e.to_string()))?;
+    return Ok(encoded.to_string());
+}
+
+fn decode_key(expected_hrp: &str, s: &str) -> Result<[u8; KEY_LENGTH]> {
+    let (hrp, data) = decode_bech32(s.trim())?;
+    if hrp != expected_hrp {
+        return Err(Error::InvalidKey(format!("Expected a key starting with '{}', got '{}'", expected_hrp, hrp)));
+    }
+    if data.len() != KEY_LENGTH {
+        return Err(Error::InvalidKey(format!("An X25519 key has {} bytes, got {}", KEY_LENGTH, data.len())));
+    }
+
+    let mut key = [0u8; KEY_LENGTH];
+    key.copy_from_slice(&data);
+    return Ok(key);
+}
+
+pub(crate) fn random_bytes(buf: &mut [u8]) -> Result<()> {
+    let mut rng = OsRng::new().map_err(|e| Error::Entropy(e.to_string()))?;
+    rng.try_fill_bytes(buf).map_err(|e| Error::Entropy(e.to_string()))
+}
+
+fn nonce_field(kv: &json::JsonValue) -> Result<[u8; NONCE_LENGTH]> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    nonce.copy_from_slice(&hex_field(kv, "nonce", Some(NONCE_LENGTH))?);
+    return Ok(nonce);
+}
+
+/// A hex encoded field of the header, optionally of a fixed length
+fn hex_field(kv: &json::JsonValue, field: &str, length: Option<usize>) -> Result<Vec<u8>> {
+    let bytes = kv[field].as_str().and_then(|s| hex::decode(s).ok()).ok_or_else(|| invalid_header(field))?;
+
+    match length {
+        Some(l) if bytes.len() != l => Err(invalid_header(field)),
+        _                           => Ok(bytes)
+    }
+}
+
+fn invalid_header(field: &str) -> Error {
+    Error::Decryption(format!("Missing or invalid field '{}'", field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters, so the tests don't take long
+    const TEST_PARAMS: KdfParams = KdfParams { log_n: 10, r: 8, p: 1 };
+
+    fn passphrase(p: &str) -> DecryptionKey {
+        DecryptionKey::Passphrase(p.to_string())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let encrypted = encrypt_with_passphrase(b"secret keys", "correct horse", &TEST_PARAMS, None).unwrap();
+        assert_eq!(encryption_kind(&encrypted), Some(EncryptionKind::Passphrase));
+        assert!(!encrypted.contains("secret keys"));
+        assert_eq!(decrypt(&encrypted, &passphrase("correct horse")).unwrap(),
+                   Decrypted { plaintext: b"secret keys".to_vec(), attachment: None });
+
+        // Every file has its own salt and nonce
+        assert_ne!(encrypt_with_passphrase(b"secret keys", "correct horse", &TEST_PARAMS, None).unwrap(), encrypted);
+
+        assert_eq!(encryption_kind("[]"), None);
+        assert_eq!(encryption_kind("not json"), None);
+        assert!(encrypt_with_passphrase(b"secret keys", "", &TEST_PARAMS, None).is_err());
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let encrypted = encrypt_with_passphrase(b"secret keys", "correct horse", &TEST_PARAMS, None).unwrap();
+
+        match decrypt(&encrypted, &passphrase("battery staple")) {
+            Err(Error::Decryption(e)) => assert!(e.contains("Wrong passphrase")),
+            _                         => panic!("Expected a decryption error")
+        }
+
+        assert!(decrypt(&encrypted, &DecryptionKey::Identity(Identity::generate().unwrap())).is_err());
+    }
+
+    #[test]
+    fn test_tampered_header() {
+        let attachment = Attachment { name: "wallet.pdf".to_string(), data: b"%PDF".to_vec() };
+        let encrypted = encrypt_with_passphrase(b"secret keys", "correct horse", &TEST_PARAMS, Some(&attachment)).unwrap();
+        let value = json::parse(&encrypted).unwrap();
+
+        let tamper = |f: &dyn Fn(&mut json::JsonValue)| {
+            let mut v = value.clone();
+            f(&mut v);
+            decrypt(&json::stringify(v), &passphrase("correct horse"))
+        };
+
+        // Reformatting the file is fine
+        assert_eq!(tamper(&|_| ()).unwrap().attachment, Some(attachment.clone()));
+
+        assert!(tamper(&|v| v["kdf"]["r"] = 4.into()).is_err());
+        assert!(tamper(&|v| v["kdf"]["salt"] = hex::encode([0u8; SALT_LENGTH]).into()).is_err());
+        assert!(tamper(&|v| v["cipher"]["nonce"] = hex::encode([0u8; NONCE_LENGTH]).into()).is_err());
+        assert!(tamper(&|v| v["cipher"]["name"] = "aes".into()).is_err());
+        assert!(tamper(&|v| v["attachment"]["name"] = "other.pdf".into()).is_err());
+        assert!(tamper(&|v| v["version"] = (FORMAT_VERSION + 1).into()).is_err());
+        assert!(tamper(&|v| v["kdf"]["log_n"] = 40.into()).is_err());
+        assert!(tamper(&|v| v["kdf"]["log_n"] = 24.into()).is_err());
+
+        // Dropping the attachment, or swapping the two ciphertexts
+        assert!(tamper(&|v| { v.remove("attachment"); }).is_err());
+        assert!(tamper(&|v| {
+            let c = v["ciphertext"].take();
+            v["ciphertext"] = v["attachment_ciphertext"].take();
+            v["attachment_ciphertext"] = c;
+        }).is_err());
+
+        let mut ciphertext = hex::decode(value["ciphertext"].as_str().unwrap()).unwrap();
+        ciphertext[0] ^= 1;
+        assert!(tamper(&|v| v["ciphertext"] = hex::encode(&ciphertext).into()).is_err());
+    }
+
+    #[test]
+    fn test_recipients() {
+        let alice = Identity::generate().unwrap();
+        let bob = Identity::generate().unwrap();
+        let eve = Identity::generate().unwrap();
+
+        let attachment = Attachment { name: "wallet.pdf".to_string(), data: vec![0xab; 1000] };
+        let encrypted = encrypt_to_recipients(b"secret keys", &[alice.recipient(), bob.recipient()], Some(&attachment)).unwrap();
+        assert_eq!(encryption_kind(&encrypted), Some(EncryptionKind::Recipients));
+
+        for identity in &[&alice, &bob] {
+            let decrypted = decrypt(&encrypted, &DecryptionKey::Identity((*identity).clone())).unwrap();
+            assert_eq!(decrypted.plaintext, b"secret keys");
+            assert_eq!(decrypted.attachment, Some(attachment.clone()));
+        }
+
+        match decrypt(&encrypted, &DecryptionKey::Identity(eve)) {
+            Err(Error::Decryption(e)) => assert!(e.contains("isn't encrypted to")),
+            _                         => panic!("Expected a decryption error")
+        }
+        assert!(decrypt(&encrypted, &passphrase("correct horse")).is_err());
+        assert!(encrypt_to_recipients(b"secret keys", &[], None).is_err());
+
+        // The stanzas are part of the authenticated header
+        let mut value = json::parse(&encrypted).unwrap();
+        value["recipients"].array_remove(1);
+        assert!(decrypt(&json::stringify(value), &DecryptionKey::Identity(alice)).is_err());
+    }
+
+    #[test]
+    fn test_key_encoding() {
+        let identity = Identity::generate().unwrap();
+        let recipient = identity.recipient();
+
+        assert!(recipient.to_string().starts_with("sdppub1"));
+        assert!(identity.to_string().starts_with("sdpsecret1"));
+        assert_eq!(recipient.to_string().parse::<Recipient>().unwrap(), recipient);
+        assert_eq!(identity.to_string().parse::<Identity>().unwrap().recipient(), recipient);
+
+        assert!(identity.to_string().parse::<Recipient>().is_err());
+        assert!(recipient.to_string().parse::<Identity>().is_err());
+
+        let mut typo = recipient.to_string().into_bytes();
+        typo[10] = if typo[10] == b'q' { b'p' } else { b'q' };
+        assert!(String::from_utf8(typo).unwrap().parse::<Recipient>().is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while generating, reading or saving paper wallets
+#[derive(Debug)]
+pub enum Error {
+    /// The vanity prefix has a character that can't appear in a bech32 address
+    InvalidPrefix(char),
+    /// The vanity prefix is longer than the part of the address it is matched against
+    PrefixTooLong(usize),
+    /// Couldn't get randomness from the operating system
+    Entropy(String),
+    /// Couldn't derive or encode a key or an address
+    KeyEncoding(String),
+    /// A key or address couldn't be decoded, or doesn't belong to this coin
+    InvalidKey(String),
+    /// The wallet JSON couldn't be parsed, or an entry is missing a field
+    InvalidWallet(String),
+    /// The data doesn't fit in a QR code
+    QrCode(String),
+    /// Couldn't build the PDF
+    Pdf(String),
+    /// The output file already exists, and we were not asked to overwrite it
+    AlreadyExists(String),
+    /// An IO error while reading or writing a file
+    Io(String, io::Error),
+    /// Swap is active, and the hardened session was asked to refuse to run with it
+    SwapActive,
+    /// A worker thread stopped without producing a result
+    Thread(String),
+    /// Couldn't encrypt a file
+    Encryption(String),
+    /// Couldn't decrypt a file, because the passphrase is wrong, the file was changed or it's in an unknown format
+    Decryption(String),
+    /// The secret couldn't be split into shares, or the shares are invalid or don't recover a secret
+    Shares(String),
+    /// A known-answer test failed, or a generated key didn't check out when it was derived again
+    SelfTest(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidPrefix(c)         => write!(f, "Invalid character in prefix: '{}'. Note that ['b', 'i', 'o', '1'] are not allowed in addresses.", c),
+            Error::PrefixTooLong(len)       => write!(f, "Prefix is {} characters long, but can be at most {}", len, crate::paper::MAX_VANITY_PREFIX),
+            Error::Entropy(e)               => write!(f, "Couldn't get system entropy: {}", e),
+            Error::KeyEncoding(e)           => write!(f, "Couldn't encode key: {}", e),
+            Error::InvalidKey(e)            => write!(f, "Invalid key: {}", e),
+            Error::InvalidWallet(e)         => write!(f, "Invalid wallet: {}", e),
+            Error::QrCode(e)                => write!(f, "Couldn't create QR code: {}", e),
+            Error::Pdf(e)                   => write!(f, "Couldn't create PDF: {}", e),
+            Error::AlreadyExists(filename)  => write!(f, "{} already exists. Refusing to overwrite it.", filename),
+            Error::Io(filename, e)          => write!(f, "{}: {}", filename, e),
+            Error::SwapActive               => write!(f, "Swap is active, so key material could be written to disk. Disable it with 'swapoff -a' and try again."),
+            Error::Thread(e)                => write!(f, "Worker thread failed: {}", e),
+            Error::Encryption(e)            => write!(f, "Couldn't encrypt: {}", e),
+            Error::Decryption(e)            => write!(f, "Couldn't decrypt: {}", e),
+            Error::Shares(e)                => write!(f, "Invalid shares: {}", e),
+            Error::SelfTest(e)              => write!(f, "Self-test failed: {}. No keys were written. This can be caused by faulty hardware.", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,413 @@
+use std::io;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::wallet::{WalletBatch, WalletEntry};
+
+/// An output that wallet entries are written to one at a time, so that a batch never has to be in memory as
+/// a whole
+pub trait EntryWriter {
+    fn write_entry(&mut self, entry: &WalletEntry) -> Result<()>;
+
+    /// Write out anything that is still buffered. Nothing can be written after this.
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Write every entry of `entries` to `writer`, and finish it. Stops at the first error. Returns the number of
+/// entries written.
+pub fn write_entries<I>(entries: I, writer: &mut dyn EntryWriter) -> Result<u64>
+    where I: Iterator<Item = Result<WalletEntry>>
+{
+    let mut count = 0;
+    for entry in entries {
+        writer.write_entry(&entry?)?;
+        count += 1;
+    }
+    writer.finish()?;
+
+    return Ok(count);
+}
+
+/// A column of the CSV and JSON Lines output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Num,
+    Type,
+    Address,
+    PrivateKey,
+    ViewingKey,
+    /// The derivation path. In JSON Lines, this is the `seed` object of the wallet file, with the seed too.
+    Path,
+    /// The label of the export, the same on every row
+    Label,
+}
+
+impl Column {
+    /// The name of the column in the CSV header, and of the field in JSON Lines
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Column::Num        => "num",
+            Column::Type       => "type",
+            Column::Address    => "address",
+            Column::PrivateKey => "private_key",
+            Column::ViewingKey => "viewing_key",
+            Column::Path       => "path",
+            Column::Label      => "label",
+        }
+    }
+
+    /// The field of the JSON form of a wallet entry this column is written as
+    fn json_field(&self) -> &'static str {
+        match self {
+            Column::Path => "seed",
+            c            => c.as_str(),
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Column> {
+        match s {
+            "num"         => Ok(Column::Num),
+            "type"        => Ok(Column::Type),
+            "address"     => Ok(Column::Address),
+            "private_key" => Ok(Column::PrivateKey),
+            "viewing_key" => Ok(Column::ViewingKey),
+            "path"        => Ok(Column::Path),
+            "label"       => Ok(Column::Label),
+            _             => Err(Error::InvalidWallet(format!("Unknown column '{}'. The columns are num, type, address, private_key, viewing_key, path and label", s)))
+        }
+    }
+}
+
+/// Every column but the label, in order
+pub const DEFAULT_COLUMNS: [Column; 6] = [Column::Num, Column::Type, Column::Address, Column::PrivateKey, Column::ViewingKey, Column::Path];
+
+/// The columns an export needs for its entries to be read back by `read_csv` and `read_json_lines`
+pub const REQUIRED_COLUMNS: [Column; 4] = [Column::Num, Column::Type, Column::Address, Column::PrivateKey];
+
+/// Parse a list of columns separated by commas, like "address,private_key"
+pub fn parse_columns(s: &str) -> Result<Vec<Column>> {
+    let mut columns = vec![];
+    for name in s.split(',').map(|c| c.trim()) {
+        let column = name.parse::<Column>()?;
+        if columns.contains(&column) {
+            return Err(Error::InvalidWallet(format!("The column '{}' is listed twice", name)));
+        }
+        columns.push(column);
+    }
+
+    return Ok(columns);
+}
+
+/// Which columns to write, and the label to write in the label column
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportOptions {
+    pub columns : Vec<Column>,
+    pub label   : Option<String>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> ExportOptions {
+        ExportOptions { columns: DEFAULT_COLUMNS.to_vec(), label: None }
+    }
+}
+
+/// JSON Lines: every entry is the same JSON object as in the `wallets` list of a wallet file, on a line of its
+/// own, with only the fields of the selected columns
+pub struct JsonLinesWriter<W: Write> {
+    out     : BufWriter<W>,
+    name    : String,
+    options : ExportOptions,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    /// `name` is the name of the output in error messages
+    pub fn new(out: W, name: &str, options: &ExportOptions) -> JsonLinesWriter<W> {
+        JsonLinesWriter { out: BufWriter::new(out), name: name.to_string(), options: options.clone() }
+    }
+}
+
+impl<W: Write> EntryWriter for JsonLinesWriter<W> {
+    fn write_entry(&mut self, entry: &WalletEntry) -> Result<()> {
+        let all = entry.to_json();
+
+        let mut line = json::JsonValue::new_object();
+        for column in &self.options.columns {
+            if *column == Column::Label {
+                line["label"] = self.options.label.clone().unwrap_or_default().into();
+            } else if all.has_key(column.json_field()) {
+                line[column.json_field()] = all[column.json_field()].clone();
+            }
+        }
+
+        writeln!(self.out, "{}", line.dump()).map_err(|e| Error::Io(self.name.clone(), e))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush().map_err(|e| Error::Io(self.name.clone(), e))
+    }
+}
+
+/// CSV with a header line, and a row per entry. The viewing key and the path are empty for t-addresses.
+pub struct CsvWriter<W: Write> {
+    out     : BufWriter<W>,
+    name    : String,
+    options : ExportOptions,
+    header  : bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// `name` is the name of the output in error messages
+    pub fn new(out: W, name: &str, options: &ExportOptions) -> CsvWriter<W> {
+        CsvWriter { out: BufWriter::new(out), name: name.to_string(), options: options.clone(), header: false }
+    }
+
+    fn write_row(&mut self, fields: &[String]) -> io::Result<()> {
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        writeln!(self.out, "{}", row.join(","))
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header {
+            self.header = true;
+            let names: Vec<String> = self.options.columns.iter().map(|c| c.as_str().to_string()).collect();
+            self.write_row(&names)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> EntryWriter for CsvWriter<W> {
+    fn write_entry(&mut self, entry: &WalletEntry) -> Result<()> {
+        let fields: Vec<String> = self.options.columns.iter().map(|column| match column {
+            Column::Num        => entry.num.to_string(),
+            Column::Type       => entry.kind.as_str().to_string(),
+            Column::Address    => entry.address.clone(),
+            Column::PrivateKey => entry.keys.private_key.clone(),
+            Column::ViewingKey => entry.keys.viewing_key.clone().unwrap_or_default(),
+            Column::Path       => entry.derivation.as_ref().map(|d| d.path.clone()).unwrap_or_default(),
+            Column::Label      => self.options.label.clone().unwrap_or_default(),
+        }).collect();
+
+        self.write_header()
+            .and_then(|_| self.write_row(&fields))
+            .map_err(|e| Error::Io(self.name.clone(), e))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // An empty batch still gets its header, so the file says what it would contain
+        self.write_header()
+            .and_then(|_| self.out.flush())
+            .map_err(|e| Error::Io(self.name.clone(), e))
+    }
+}
+
+/// Write a whole batch to `out`, as CSV or JSON Lines
+pub fn write_batch<W: Write>(batch: &WalletBatch, out: W, name: &str, csv: bool, options: &ExportOptions) -> Result<u64> {
+    let entries = batch.entries.iter().cloned().map(Ok);
+    if csv {
+        return write_entries(entries, &mut CsvWriter::new(out, name, options));
+    }
+    return write_entries(entries, &mut JsonLinesWriter::new(out, name, options));
+}
+
+/// Quote a CSV field if it has a comma, a quote or a line break in it
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        return format!("\"{}\"", field.replace('"', "\"\""));
+    }
+    return field.to_string();
+}
+
+/// Read the entries of a JSON Lines export. Exports have no metadata, and entries whose path was left out
+/// have no seed.
+pub fn read_json_lines(text: &str) -> Result<WalletBatch> {
+    let mut entries = vec![];
+    for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let value = json::parse(line).map_err(|e| Error::InvalidWallet(format!("Line {}: {}", i + 1, e)))?;
+        entries.push(WalletEntry::from_json(&value).map_err(|e| Error::InvalidWallet(format!("Line {}: {}", i + 1, e)))?);
+    }
+
+    return Ok(WalletBatch { entries, metadata: None });
+}
+
+/// Read the entries of a CSV export. The columns can be in any order, but the ones in `REQUIRED_COLUMNS` have
+/// to be there. A CSV file has no seeds, so the entries have no derivation, and only their keys can be checked.
+pub fn read_csv(text: &str) -> Result<WalletBatch> {
+    let mut records = csv_records(text)?.into_iter();
+
+    let header = records.next().ok_or_else(|| Error::InvalidWallet("The CSV file is empty".to_string()))?;
+    let columns = header.iter().map(|name| name.trim().parse::<Column>()).collect::<Result<Vec<Column>>>()?;
+    for required in &REQUIRED_COLUMNS {
+        if !columns.contains(required) {
+            return Err(Error::InvalidWallet(format!("The CSV file has no '{}' column", required.as_str())));
+        }
+    }
+
+    let mut entries = vec![];
+    for (i, record) in records.enumerate() {
+        // The header is line 1
+        let invalid = |e: Error| Error::InvalidWallet(format!("Row {}: {}", i + 2, e));
+        if record.len() != columns.len() {
+            return Err(invalid(Error::InvalidWallet(format!("Expected {} fields, got {}", columns.len(), record.len()))));
+        }
+
+        let mut value = json::JsonValue::new_object();
+        for (column, field) in columns.iter().zip(record) {
+            match column {
+                Column::Num                            => value["num"] = field.parse::<u32>()
+                                                            .map_err(|_| invalid(Error::InvalidWallet(format!("Invalid num '{}'", field))))?.into(),
+                Column::Path | Column::Label           => {},
+                Column::ViewingKey if field.is_empty() => {},
+                c                                      => value[c.json_field()] = field.into(),
+            }
+        }
+        entries.push(WalletEntry::from_json(&value).map_err(invalid)?);
+    }
+
+    return Ok(WalletBatch { entries, metadata: None });
+}
+
+/// Split CSV text into records of fields. Quoted fields can have commas, quotes and line breaks in them.
+fn csv_records(text: &str) -> Result<Vec<Vec<String>>> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => { chars.next(); field.push('"'); },
+            (true, '"')                               => quoted = false,
+            (true, c)                                 => field.push(c),
+            (false, '"') if field.is_empty()          => quoted = true,
+            (false, ',')                              => record.push(std::mem::replace(&mut field, String::new())),
+            (false, '\r')                             => {},
+            (false, '\n')                             => {
+                record.push(std::mem::replace(&mut field, String::new()));
+                records.push(std::mem::replace(&mut record, vec![]));
+            },
+            (false, c)                                => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(Error::InvalidWallet("The CSV file ends inside a quoted field".to_string()));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    // Skip blank lines
+    return Ok(records.into_iter().filter(|r| !(r.len() == 1 && r[0].is_empty())).collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::{wallet_from_seed, DerivationPath};
+
+    fn test_batch() -> WalletBatch {
+        let seed: Vec<u8> = (0..32).collect();
+        wallet_from_seed(&seed, 2, 1, &DerivationPath::default(), 1, "test".to_string()).unwrap()
+    }
+
+    fn export(batch: &WalletBatch, csv: bool, options: &ExportOptions) -> String {
+        let mut out = Vec::new();
+        write_batch(batch, &mut out, "test", csv, options).unwrap();
+        return String::from_utf8(out).unwrap();
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let batch = test_batch();
+        let text = export(&batch, false, &ExportOptions::default());
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        for (line, entry) in lines.iter().zip(&batch.entries) {
+            assert_eq!(WalletEntry::from_json(&json::parse(line).unwrap()).unwrap(), *entry);
+        }
+
+        // Read back with the seeds
+        assert_eq!(read_json_lines(&text).unwrap().entries, batch.entries);
+
+        let options = ExportOptions { columns: parse_columns("label,num,type,address,private_key").unwrap(), label: Some("promo".to_string()) };
+        let text = export(&batch, false, &options);
+        let first = json::parse(text.lines().next().unwrap()).unwrap();
+        assert_eq!(first["label"], "promo");
+        assert!(!first.has_key("seed") && !first.has_key("viewing_key"));
+
+        let read = read_json_lines(&text).unwrap();
+        assert_eq!(read.entries[0].address, batch.entries[0].address);
+        assert!(read.entries.iter().all(|e| e.derivation.is_none()));
+
+        assert!(read_json_lines("{\"num\": 0}").is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        let batch = test_batch();
+        let text = export(&batch, true, &ExportOptions::default());
+
+        let rows: Vec<Vec<&str>> = text.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows[0], DEFAULT_COLUMNS.iter().map(|c| c.as_str()).collect::<Vec<_>>());
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[1], vec!["0", "zaddr", &batch.entries[0].address, &batch.entries[0].keys.private_key,
+                                 batch.entries[0].keys.viewing_key.as_ref().unwrap(), "m/32'/133'/0'"]);
+        assert_eq!(rows[3], vec!["0", "taddr", &batch.entries[2].address, &batch.entries[2].keys.private_key, "", ""]);
+
+        // Read back without the seeds, which aren't in the CSV
+        let read = read_csv(&text).unwrap();
+        assert_eq!(read.entries.len(), 3);
+        for (r, e) in read.entries.iter().zip(&batch.entries) {
+            assert_eq!((r.num, r.kind, &r.address, &r.keys), (e.num, e.kind, &e.address, &e.keys));
+            assert!(r.derivation.is_none());
+        }
+
+        // A header, even without entries
+        let empty = export(&WalletBatch::default(), true, &ExportOptions::default());
+        assert_eq!(empty.trim(), "num,type,address,private_key,viewing_key,path");
+        assert_eq!(read_csv(&empty).unwrap().entries.len(), 0);
+
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_csv_columns_and_label() {
+        let batch = test_batch();
+        let options = ExportOptions { columns: parse_columns("address, label ,private_key,type,num").unwrap(), label: Some("Shop, \"north\"\nwall".to_string()) };
+        let text = export(&batch, true, &options);
+
+        assert!(text.starts_with("address,label,private_key,type,num\n"));
+        let records = csv_records(&text).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1][1], "Shop, \"north\"\nwall");
+
+        let read = read_csv(&text).unwrap();
+        assert_eq!(read.entries[2].keys, batch.entries[2].keys);
+        assert_eq!(read.entries[0].keys.viewing_key, None);
+
+        assert!(read_csv("address,private_key\nzs1,key\n").is_err());
+        assert!(read_csv("num,type,address,private_key\n0,zaddr\n").is_err());
+        assert!(read_csv("num,type,address,private_key\n\"0,zaddr").is_err());
+        assert!(parse_columns("num,num").is_err());
+        assert!(parse_columns("num,seed").is_err());
+    }
+
+    #[test]
+    fn test_write_stops_at_error() {
+        let batch = test_batch();
+        let entries = vec![Ok(batch.entries[0].clone()), Err(Error::SelfTest("test".to_string())), Ok(batch.entries[1].clone())];
+
+        let mut out = Vec::new();
+        assert!(write_entries(entries.into_iter(), &mut JsonLinesWriter::new(&mut out, "test", &ExportOptions::default())).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs;
+
+use crate::error::{Error, Result};
+
+/// What to do if the system has active swap space, since the kernel could page key material out to disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SwapPolicy {
+    /// Report the active swap as a warning, but carry on
+    Warn,
+    /// Refuse to start the session
+    Refuse,
+}
+
+/// A hardened session to generate keys in. While the session is alive, the process memory is locked
+/// into RAM (so secrets are never paged out to swap) and core dumps are disabled (so secrets are never
+/// written to disk if the process crashes). The memory is unlocked again when the session is dropped.
+///
+/// Not every protection is available everywhere. Locking memory needs either root or an unlimited
+/// RLIMIT_MEMLOCK, and `PR_SET_DUMPABLE` is Linux only. Whatever couldn't be applied is reported in `warnings()`
+/// instead of failing, so the caller can decide whether that is acceptable.
+pub struct HardenedSession {
+    pub memory_locked       : bool,
+    pub core_dumps_disabled : bool,
+    pub swap_active         : bool,
+    warnings                : Vec<String>,
+}
+
+impl HardenedSession {
+    /// Start a hardened session. This has to be called before any secret is created, so that all the
+    /// key material ends up in locked memory.
+    pub fn begin(swap_policy: SwapPolicy) -> Result<HardenedSession> {
+        let mut warnings = Vec::new();
+
+        let swap_active = is_swap_active();
+        if swap_active {
+            if swap_policy == SwapPolicy::Refuse {
+                return Err(Error::SwapActive);
+            }
+            warnings.push("Swap is active, so key material could be written to disk. Consider running 'swapoff -a' first.".to_string());
+        }
+
+        let core_dumps_disabled = disable_core_dumps();
+        if !core_dumps_disabled {
+            warnings.push("Couldn't disable core dumps".to_string());
+        }
+
+        let memory_locked = lock_memory();
+        if !memory_locked {
+            warnings.push("Couldn't lock memory, key material may be swapped to disk. Run as root or raise the memlock limit (ulimit -l unlimited)".to_string());
+        }
+
+        return Ok(HardenedSession { memory_locked, core_dumps_disabled, swap_active, warnings });
+    }
+
+    /// Protections that couldn't be applied in this session
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+}
+
+impl Drop for HardenedSession {
+    fn drop(&mut self) {
+        if self.memory_locked {
+            unlock_memory();
+        }
+    }
+}
+
+/// Check /proc/swaps for any active swap device. The first line is the column header.
+fn is_swap_active() -> bool {
+    match fs::read_to_string("/proc/swaps") {
+        Ok(swaps) => swaps.lines().skip(1).any(|l| !l.trim().is_empty()),
+        Err(_)    => false
+    }
+}
+
+#[cfg(unix)]
+fn disable_core_dumps() -> bool {
+    let limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    let rlimit_ok = unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } == 0;
+
+    return rlimit_ok && set_not_dumpable();
+}
+
+#[cfg(not(unix))]
+fn disable_core_dumps() -> bool {
+    false
+}
+
+/// Mark the process as not dumpable, which also stops other processes from ptrace-ing it.
+#[cfg(target_os = "linux")]
+fn set_not_dumpable() -> bool {
+    unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) == 0 }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn set_not_dumpable() -> bool {
+    true
+}
+
+/// Lock all current and future pages of the process into RAM.
+///
+/// MCL_FUTURE makes every later allocation count against RLIMIT_MEMLOCK, and an allocation that goes over
+/// the limit fails (which aborts the process), so we only lock memory as root (which isn't bound by the
+/// limit) or if the limit is unlimited.
+#[cfg(target_os = "linux")]
+fn lock_memory() -> bool {
+    let is_root = unsafe { libc::geteuid() } == 0;
+
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    let unlimited = unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } == 0 && limit.rlim_cur == libc::RLIM_INFINITY;
+
+    if !is_root && !unlimited {
+        return false;
+    }
+
+    unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lock_memory() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn unlock_memory() {
+    unsafe { libc::munlockall(); }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unlock_memory() {
+}
@@ -0,0 +1,198 @@
+use crate::error::{Error, Result};
+use crate::paper::{encode_taddress, params, zaddr_entry};
+use crate::selftest;
+use crate::verify::{decode_spending_key, decode_wif};
+use crate::wallet::{AddressKind, BatchMetadata, DerivationProfile, KeyMaterial, WalletBatch, WalletEntry};
+
+/// Import a single Sapling extended spending key (as printed by `z_exportkey`) or a WIF private key (as
+/// printed by `dumpprivkey`), and derive its address.
+pub fn import_key(key: &str) -> Result<WalletEntry> {
+    let key = key.trim().trim_matches('"');
+
+    if key.to_ascii_lowercase().starts_with(&format!("{}1", params().zsecret_prefix)) {
+        import_spending_key(key)
+    } else {
+        import_wif(key)
+    }
+}
+
+/// Import all the keys in `text`. This can be the output of `z_exportkey` or `dumpprivkey`, a list of such
+/// keys one per line, or a `z_exportwallet` dump.
+///
+/// In a dump, every key line is followed by a comment with the address hushd has for that key, like
+/// `# addr=R...` or `# zaddr=zs1...`, which is checked against the address we derive. Keys that appear more
+/// than once are only imported once.
+pub fn import_keys(text: &str) -> Result<WalletBatch> {
+    let mut batch = WalletBatch::default();
+
+    for (i, line) in text.lines().enumerate() {
+        let (key_part, comment) = match line.find('#') {
+            Some(pos) => (&line[..pos], &line[pos+1..]),
+            None      => (line, "")
+        };
+
+        // Dump lines are "<key> <time> <label> # addr=<address>". Only the key is needed.
+        let key = match key_part.split_whitespace().next() {
+            Some(k) => k,
+            None    => continue
+        };
+
+        let line_error = |e: Error| match e {
+            Error::InvalidKey(msg) => Error::InvalidKey(format!("line {}: {}", i + 1, msg)),
+            e                      => e
+        };
+
+        let mut entry = import_key(key).map_err(line_error)?;
+
+        if let Some(expected) = dump_address(comment) {
+            if expected != entry.address {
+                return Err(line_error(Error::InvalidKey(format!("The key is for {}, but the dump says it's for {}", entry.address, expected))));
+            }
+        }
+
+        if batch.entries.iter().any(|e| e.address == entry.address) {
+            continue;
+        }
+
+        entry.num = batch.entries.iter().filter(|e| e.kind == entry.kind).count() as u32;
+        batch.entries.push(entry);
+    }
+
+    if batch.entries.is_empty() {
+        return Err(Error::InvalidKey("No keys found".to_string()));
+    }
+
+    // Keep the same order as generated wallets, with the z-addresses first
+    batch.entries.sort_by_key(|e| (e.kind == AddressKind::Transparent, e.num));
+    batch.metadata = Some(BatchMetadata::new(DerivationProfile::Imported, "Unknown (imported keys)".to_string()));
+    selftest::check_batch(&batch)?;
+
+    return Ok(batch);
+}
+
+/// The address in a dump comment, from an "addr=" or "zaddr=" field
+fn dump_address(comment: &str) -> Option<String> {
+    comment.split(|c: char| c.is_whitespace() || c == ',')
+        .find_map(|field| {
+            if field.starts_with("addr=") {
+                Some(field["addr=".len()..].to_string())
+            } else if field.starts_with("zaddr=") {
+                Some(field["zaddr=".len()..].to_string())
+            } else {
+                None
+            }
+        })
+}
+
+fn import_spending_key(key: &str) -> Result<WalletEntry> {
+    let spk = decode_spending_key(key, &params())?;
+
+    // We don't know the seed (if any) this key was derived from
+    return zaddr_entry(0, &spk, None);
+}
+
+fn import_wif(key: &str) -> Result<WalletEntry> {
+    let (sk, compressed) = decode_wif(key, &params())?;
+    let (address, wif) = encode_taddress(&sk, compressed);
+
+    return Ok(WalletEntry {
+        num         : 0,
+        kind        : AddressKind::Transparent,
+        address,
+        keys        : KeyMaterial { private_key: wif, viewing_key: None },
+        derivation  : None,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A z_exportwallet dump with two t-addresses, two z-addresses and the first t-address again. It's kept
+    /// here rather than read from a file, so the tests don't change with it. The spending keys are the ones
+    /// of m/32'/133'/0' and m of the seed 0..31, which `test_dump_keys` checks.
+    const DUMP: &str = "# Wallet dump created by Hush
+# * Created on 2021-01-01T00:00:00Z
+
+UtHdmHhzD1FSGJvpJkv9A254kTL5wnp2ePXtEjXXEfqX5okVfUxs 2021-01-01T00:00:00Z reserve=1 # addr=RHfM2F5XG5rnUcW2Fj4k9H5g8QrMsp2GMQ
+UpjRU78SBGA89DBoeEtsuTf2pryEiFC8SHJ2Wx9HCUQr3YaVpetc 2021-01-01T00:00:00Z reserve=1 # addr=RMnopcyppgUEBoHKEXtPWj9xfeHGwDuW8z
+UtHdmHhzD1FSGJvpJkv9A254kTL5wnp2ePXtEjXXEfqX5okVfUxs 2021-01-01T00:00:00Z change=1 # addr=RHfM2F5XG5rnUcW2Fj4k9H5g8QrMsp2GMQ
+
+# Zkeys
+
+secret-extended-key-main1qvmjmz6rqqqqpqzwtfucl5xld0ptzguvaate2mhn255ts7jtym9ram4j3vgg4g9wj2xetfdh8gepzmg3utfe96se4r0zhx6c02dpn9w46l75scpx6m6sh8ulfrf8j7yqkjk8vqcq279chxw9wpt2r2js8x4pqvn5j7dpc9sv3m5ze9p4fr2wx0605vr64dqupvzg2x3pmw7pty5gddk63vkxhekc7lq8lgdzmtcsehsn0ml404v0ztclm8utupzcvujfk4ylqk5sqsqplg80g 2021-01-01T00:00:00Z # zaddr=zs1mrhc9y7jdh5r9ece8u5khgvj9kg0zgkxzdduyv0whkg7lkcrkx5xqem3e48avjq9wn2rukydkwn
+secret-extended-key-main1qqqqqqqqqqqqqqxsj37ykqalw23h4dz0wgnk688nlhxha0e7wv6gklj4p46jqxrx36mvqryn6dsr9wdzdr5eap4gvpmk2c9lp6purggt28mq0j25wsjsdqsyah5rktclhkz0ndza07vkut4apgps45jrkj8d88m532yzr6sx89vgfzgrywuafyeuqgwm3x70we7lyxthktlsdquysvs6fh62lvsh0stukadh0940kw0s7053eyjxqld9d756yr3gx5ymez37lxt2zuscfzd9h 2021-01-01T00:00:00Z # zaddr=zs1mp3phxqu7vqwn4xv38yu4ujdtr0zf8uhxg798utekase0xj8p5qre5646d9rgfetsfzqye4gayt
+
+# End of dump
+";
+
+    fn error_message(r: Result<WalletBatch>) -> String {
+        match r {
+            Err(Error::InvalidKey(msg)) => msg,
+            r                           => panic!("Expected an invalid key, got {:?}", r)
+        }
+    }
+
+    #[test]
+    fn test_import_dump() {
+        let batch = import_keys(DUMP).unwrap();
+
+        // The z-addresses come first, the duplicate key is only imported once, and each type is numbered on its own
+        let entries: Vec<(AddressKind, u32, &str)> = batch.entries.iter().map(|e| (e.kind, e.num, e.address.as_str())).collect();
+        assert_eq!(entries, vec![
+            (AddressKind::Sapling,     0, "zs1mrhc9y7jdh5r9ece8u5khgvj9kg0zgkxzdduyv0whkg7lkcrkx5xqem3e48avjq9wn2rukydkwn"),
+            (AddressKind::Sapling,     1, "zs1mp3phxqu7vqwn4xv38yu4ujdtr0zf8uhxg798utekase0xj8p5qre5646d9rgfetsfzqye4gayt"),
+            (AddressKind::Transparent, 0, "RHfM2F5XG5rnUcW2Fj4k9H5g8QrMsp2GMQ"),
+            (AddressKind::Transparent, 1, "RMnopcyppgUEBoHKEXtPWj9xfeHGwDuW8z"),
+        ]);
+
+        assert!(batch.entries.iter().all(|e| e.derivation.is_none()));
+        assert_eq!(batch.entries[0].keys.viewing_key.as_ref().unwrap(), "zviews1qvmjmz6rqqqqpqzwtfucl5xld0ptzguvaate2mhn255ts7jtym9ram4j3vgg4g9wjgca9sw392zzfkn62uvctjgspy86atg2myma0yrgvfa04cv3dnwvrmkrw24zgqkwwfs3l3ejua8rr8z92tfsjxlpe0fws4vnxkuq0s943m5ze9p4fr2wx0605vr64dqupvzg2x3pmw7pty5gddk63vkxhekc7lq8lgdzmtcsehsn0ml404v0ztclm8utupzcvujfk4ylqk5sqsqtgueax");
+        assert_eq!(batch.metadata.unwrap().derivation_profile, DerivationProfile::Imported);
+
+        // A plain list of keys
+        let batch = import_keys("UtHdmHhzD1FSGJvpJkv9A254kTL5wnp2ePXtEjXXEfqX5okVfUxs\n\n  UpjRU78SBGA89DBoeEtsuTf2pryEiFC8SHJ2Wx9HCUQr3YaVpetc\n").unwrap();
+        assert_eq!(batch.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_dump_keys() {
+        use crate::paper::encode_privatekey;
+        use zcash_primitives::zip32::{ChildIndex, ExtendedSpendingKey};
+
+        let seed: Vec<u8> = (0..32).collect();
+        let master = ExtendedSpendingKey::master(&seed);
+        let child  = ExtendedSpendingKey::from_path(&master, &[ChildIndex::Hardened(32), ChildIndex::Hardened(133), ChildIndex::Hardened(0)]);
+
+        let zkeys: Vec<&str> = DUMP.lines().filter(|l| l.starts_with("secret-extended-key")).map(|l| l.split(' ').next().unwrap()).collect();
+        assert_eq!(zkeys, vec![encode_privatekey(&child).unwrap(), encode_privatekey(&master).unwrap()]);
+    }
+
+    #[test]
+    fn test_dump_errors() {
+        // The address in the comment of line 5 is the one of the key on line 4
+        let swapped = DUMP.replacen("addr=RMnopcyppgUEBoHKEXtPWj9xfeHGwDuW8z", "addr=RHfM2F5XG5rnUcW2Fj4k9H5g8QrMsp2GMQ", 1);
+        assert!(error_message(import_keys(&swapped)).starts_with("line 5: "));
+
+        let swapped = DUMP.replacen("zaddr=zs1mp3ph", "zaddr=zs1mrhc9", 1);
+        assert!(error_message(import_keys(&swapped)).starts_with("line 11: "));
+
+        let broken = DUMP.replacen("UpjRU78SBGA89DBoeEtsuTf2pryEiFC8SHJ2Wx9HCUQr3YaVpetc", "UpjRU78SBGA89DBoeEtsuTf2pryEiFC8SHJ2Wx9HCUQr3YaVpetd", 1);
+        assert!(error_message(import_keys(&broken)).starts_with("line 5: "));
+
+        // Only comments and headers
+        assert_eq!(error_message(import_keys("# Wallet dump created by Hush\n\n# Zkeys\n\n# End of dump\n")), "No keys found");
+    }
+
+    #[test]
+    fn test_import_key() {
+        let entry = import_key("  \"UpjRU78SBGA89DBoeEtsuTf2pryEiFC8SHJ2Wx9HCUQr3YaVpetc\"\n").unwrap();
+        assert_eq!((entry.kind, entry.address.as_str()), (AddressKind::Transparent, "RMnopcyppgUEBoHKEXtPWj9xfeHGwDuW8z"));
+        assert_eq!(entry.keys.private_key, "UpjRU78SBGA89DBoeEtsuTf2pryEiFC8SHJ2Wx9HCUQr3YaVpetc");
+
+        let entry = import_key("\"secret-extended-key-main1qqqqqqqqqqqqqqxsj37ykqalw23h4dz0wgnk688nlhxha0e7wv6gklj4p46jqxrx36mvqryn6dsr9wdzdr5eap4gvpmk2c9lp6purggt28mq0j25wsjsdqsyah5rktclhkz0ndza07vkut4apgps45jrkj8d88m532yzr6sx89vgfzgrywuafyeuqgwm3x70we7lyxthktlsdquysvs6fh62lvsh0stukadh0940kw0s7053eyjxqld9d756yr3gx5ymez37lxt2zuscfzd9h\"").unwrap();
+        assert_eq!((entry.kind, entry.address.as_str()), (AddressKind::Sapling, "zs1mp3phxqu7vqwn4xv38yu4ujdtr0zf8uhxg798utekase0xj8p5qre5646d9rgfetsfzqye4gayt"));
+
+        assert!(import_key("\"not a key\"").is_err());
+    }
+}
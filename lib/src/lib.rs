@@ -1,3 +1,21 @@
+pub mod error;
 pub mod paper;
+pub mod wallet;
+pub mod encoding;
+pub mod import;
+pub mod verify;
+pub mod repair;
+pub mod selftest;
+pub mod hardened;
+pub mod airgap;
+pub mod secure_file;
+pub mod encryption;
+pub mod bip38;
+pub mod encrypted_key;
+pub mod slip39;
+pub mod codex32;
+pub mod bip39;
+pub mod bulk;
+pub mod export;
 #[cfg(feature = "printpdf")]
 pub mod pdf;
\ No newline at end of file
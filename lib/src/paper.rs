@@ -1,19 +1,30 @@
+use std::fmt;
 use std::thread;
 use hex;
 use base58::{ToBase58};
 use bech32::{Bech32, u5, ToBase32};
-use rand::{Rng, ChaChaRng, FromEntropy, SeedableRng};
-use json::{array, object};
+use rand::{Rng, ChaChaRng, SeedableRng};
+use rand::rngs::OsRng;
+use json::object;
 use sha2::{Sha256, Digest};
 use std::io;
 use std::io::Write;
 use std::sync::mpsc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::panic;
 use std::time::{SystemTime};
 use zcash_primitives::zip32::{DiversifierIndex, DiversifierKey, ChildIndex, ExtendedSpendingKey, ExtendedFullViewingKey};
 
+use crate::bip39;
+use crate::bulk;
+use crate::error::{Error, Result};
+use crate::selftest;
+use crate::wallet::{AddressKind, BatchMetadata, Derivation, DerivationProfile, KeyMaterial, WalletBatch, WalletEntry};
+
+/// The longest vanity prefix we can search for. The prefix is matched against the 11 byte diversifier, 
+/// which is the first 88 bits (17.6 bech32 characters) of the address data.
+pub const MAX_VANITY_PREFIX: usize = 17;
+
 /// A trait for converting a [u8] to base58 encoded string.
 pub trait ToBase58Check {
     /// Converts a value of `self` to a base58 value, returning the owned string.
@@ -46,7 +57,9 @@ pub fn double_sha256(payload: &[u8]) -> Vec<u8> {
 /// Parameters used to generate addresses and private keys. Look in chainparams.cpp in hush3.git
 /// to get these values. 
 /// Usually these will be different for testnet and for mainnet.
+#[derive(Clone, Debug, PartialEq)]
 pub struct CoinParams {
+    pub network         : String,
     pub taddress_version: [u8; 1],
     pub tsecret_prefix  : [u8; 1],
     pub zaddress_prefix : String,
@@ -57,6 +70,7 @@ pub struct CoinParams {
 
 pub fn params() -> CoinParams {
         CoinParams {
+            network          : "main".to_string(),
             taddress_version : [0x3c],
             tsecret_prefix   : [0xBC],
             zaddress_prefix  : "zs".to_string(),
@@ -66,8 +80,159 @@ pub fn params() -> CoinParams {
         }
 }
 
-pub fn increment(s: &mut [u8; 32]) -> Result<(), ()> {
-    for k in 0..32 {
+impl CoinParams {
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "network"           => self.network.clone(),
+            "taddress_version"  => hex::encode(&self.taddress_version),
+            "tsecret_prefix"    => hex::encode(&self.tsecret_prefix),
+            "zaddress_prefix"   => self.zaddress_prefix.clone(),
+            "zsecret_prefix"    => self.zsecret_prefix.clone(),
+            "zviewkey_prefix"   => self.zviewkey_prefix.clone(),
+            "cointype"          => self.cointype
+        }
+    }
+
+    pub fn from_json(kv: &json::JsonValue) -> Result<CoinParams> {
+        let byte = |field: &str| -> Result<[u8; 1]> {
+            match kv[field].as_str().map(hex::decode) {
+                Some(Ok(ref b)) if b.len() == 1 => Ok([b[0]]),
+                _                               => Err(Error::InvalidWallet(format!("Missing or invalid coin parameter '{}'", field)))
+            }
+        };
+        let string = |field: &str| -> Result<String> {
+            kv[field].as_str().map(|s| s.to_string())
+                .ok_or_else(|| Error::InvalidWallet(format!("Missing or invalid coin parameter '{}'", field)))
+        };
+
+        Ok(CoinParams {
+            network          : string("network")?,
+            taddress_version : byte("taddress_version")?,
+            tsecret_prefix   : byte("tsecret_prefix")?,
+            zaddress_prefix  : string("zaddress_prefix")?,
+            zsecret_prefix   : string("zsecret_prefix")?,
+            zviewkey_prefix  : string("zviewkey_prefix")?,
+            cointype         : kv["cointype"].as_u32().ok_or_else(|| Error::InvalidWallet("Missing or invalid coin parameter 'cointype'".to_string()))?,
+        })
+    }
+}
+
+/// ZIP-32 master keys are derived from seeds of 32 to 252 bytes. Paper wallets use 32 byte seeds, unless
+/// asked for longer ones, and BIP39 mnemonics give 64 byte seeds.
+pub const MIN_SEED_LENGTH: usize     = 32;
+pub const MAX_SEED_LENGTH: usize     = 252;
+pub const DEFAULT_SEED_LENGTH: usize = 32;
+
+/// Check that `len` is a valid length for a ZIP-32 seed
+pub fn check_seed_length(len: usize) -> Result<()> {
+    if len < MIN_SEED_LENGTH || len > MAX_SEED_LENGTH {
+        return Err(Error::InvalidKey(format!("An HD seed has {} to {} bytes, got {}", MIN_SEED_LENGTH, MAX_SEED_LENGTH, len)));
+    }
+    return Ok(());
+}
+
+/// Decode an HD seed written in hex, as in the `HDSeed` field of a wallet. Whitespace is ignored.
+pub fn decode_seed(s: &str) -> Result<Vec<u8>> {
+    let hex_seed: String = s.split_whitespace().collect();
+    let seed = hex::decode(&hex_seed).map_err(|e| Error::InvalidKey(format!("The HD seed is not valid hex: {}", e)))?;
+    check_seed_length(seed.len())?;
+
+    return Ok(seed);
+}
+
+/// Where the z-addresses of a batch are derived. Every level is hardened, and the z-address number i is derived
+/// at `base/(start + i)'`. The default is m/32'/cointype'/index', the ZIP-32 account level, and `account` puts
+/// the addresses a level below an account, at m/32'/cointype'/account'/index'.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DerivationPath {
+    pub base  : Vec<u32>,
+    pub start : u32,
+}
+
+impl Default for DerivationPath {
+    fn default() -> DerivationPath {
+        DerivationPath { base: vec![32, params().cointype], start: 0 }
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    /// The base path, like m/32'/133'
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "m")?;
+        for i in &self.base {
+            write!(f, "/{}'", i)?;
+        }
+        Ok(())
+    }
+}
+
+impl DerivationPath {
+    /// The addresses of a ZIP-32 account, at m/32'/cointype'/account'/index'
+    pub fn account(account: u32) -> Result<DerivationPath> {
+        let path = DerivationPath { base: vec![32, params().cointype, account], start: 0 };
+        path.check_index(account)?;
+        return Ok(path);
+    }
+
+    /// Parse a base path like m/32'/133'/7', which the address index is added to. Every level must be hardened,
+    /// since the keys are spending keys.
+    pub fn parse(path: &str) -> Result<DerivationPath> {
+        let invalid = || Error::InvalidKey(format!("Invalid derivation path '{}'. Every level must be hardened, like m/32'/133'/0'", path));
+
+        let mut parts = path.trim().split('/');
+        if parts.next() != Some("m") {
+            return Err(invalid());
+        }
+
+        let base = parts.map(|p| {
+            if !p.ends_with('\'') {
+                return Err(invalid());
+            }
+            match p[..p.len()-1].parse::<u32>() {
+                Ok(i) if i < (1 << 31) => Ok(i),
+                _                      => Err(invalid())
+            }
+        }).collect::<Result<Vec<u32>>>()?;
+
+        return Ok(DerivationPath { base, start: 0 });
+    }
+
+    /// The base path and start index of an address derived at `path`, so that later addresses can be derived
+    /// after it
+    pub fn of_address(path: &str) -> Result<DerivationPath> {
+        let mut path = DerivationPath::parse(path)?;
+        path.start = path.base.pop().ok_or_else(|| Error::InvalidKey("The derivation path of an address has at least one level".to_string()))?;
+        return Ok(path);
+    }
+
+    /// Start deriving the addresses at `start` instead of 0
+    pub fn with_start(self, start: u32) -> Result<DerivationPath> {
+        self.check_index(start)?;
+        return Ok(DerivationPath { start, ..self });
+    }
+
+    /// The hardened child indices of the z-address number `i`
+    pub fn child_indices(&self, i: u32) -> Result<Vec<ChildIndex>> {
+        let index = self.start.checked_add(i).unwrap_or(u32::max_value());
+        self.check_index(index)?;
+        return Ok(self.base.iter().chain(Some(&index)).map(|c| ChildIndex::Hardened(*c)).collect());
+    }
+
+    /// The path of the z-address number `i`, like m/32'/133'/0'
+    pub fn path_of(&self, i: u32) -> String {
+        return format!("{}/{}'", self, self.start.saturating_add(i));
+    }
+
+    fn check_index(&self, index: u32) -> Result<()> {
+        if index >= (1 << 31) {
+            return Err(Error::InvalidKey(format!("The index {} is too large for a hardened derivation path. It has to be less than 2^31", index)));
+        }
+        return Ok(());
+    }
+}
+
+pub fn increment(s: &mut [u8]) -> std::result::Result<(), ()> {
+    for k in 0..s.len() {
         s[k] = s[k].wrapping_add(1);
         if s[k] != 0 {
             // No overflow
@@ -78,8 +243,8 @@ pub fn increment(s: &mut [u8; 32]) -> Result<(), ()> {
     Err(())
 }
 
-// Turn the prefix into Vec<u5>, so it can be matched directly without any encoding overhead.
-fn get_bech32_for_prefix(prefix: String) -> Result<Vec<u5>, String> {
+/// Turn the prefix into Vec<u5>, so it can be matched directly without any encoding overhead.
+pub fn get_bech32_for_prefix(prefix: String) -> Result<Vec<u5>> {
     // Reverse character set. Maps ASCII byte -> CHARSET index on [0,31]
     const CHARSET_REV: [i8; 128] = [
         -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
@@ -92,102 +257,151 @@ fn get_bech32_for_prefix(prefix: String) -> Result<Vec<u5>, String> {
         1,  0,  3, 16, 11, 28, 12, 14,  6,  4,  2, -1, -1, -1, -1, -1
     ];
 
+    if prefix.chars().count() > MAX_VANITY_PREFIX {
+        return Err(Error::PrefixTooLong(prefix.chars().count()));
+    }
+
     let mut ans = Vec::new();
     for c in prefix.chars() {
-        if CHARSET_REV[c as usize] == -1 {
-            return Err(format!("Invalid character in prefix: '{}'", c));
+        if !c.is_ascii() || CHARSET_REV[c as usize] == -1 {
+            return Err(Error::InvalidPrefix(c));
         }
-        ans.push(u5::try_from_u8(CHARSET_REV[c as usize] as u8).expect("Should be able to convert to u5"));
+        ans.push(u5::try_from_u8(CHARSET_REV[c as usize] as u8).map_err(|e| Error::KeyEncoding(e.to_string()))?);
     }
 
     return Ok(ans);
 }
 
-fn encode_address(spk: &ExtendedSpendingKey) -> String {
-    let (_d, addr) = spk.default_address().expect("Cannot get result");
+/// The raw 43 bytes (diversifier and pk_d) of the default address of a viewing key
+pub(crate) fn default_address_bytes(fvk: &ExtendedFullViewingKey) -> Result<Vec<u8>> {
+    let (_d, addr) = fvk.default_address().map_err(|_| Error::KeyEncoding("No valid diversifier for the default address".to_string()))?;
 
-    // Address is encoded as a bech32 string
     let mut v = vec![0; 43];
 
-    v.get_mut(..11).unwrap().copy_from_slice(&addr.diversifier.0);
-    addr.pk_d.write(v.get_mut(11..).unwrap()).expect("Cannot write!");
+    v[..11].copy_from_slice(&addr.diversifier.0);
+    addr.pk_d.write(&mut v[11..]).map_err(|e| Error::KeyEncoding(e.to_string()))?;
+
+    return Ok(v);
+}
+
+fn encode_address(spk: &ExtendedSpendingKey) -> Result<String> {
+    // Address is encoded as a bech32 string
+    let v = default_address_bytes(&ExtendedFullViewingKey::from(spk))?;
     let checked_data: Vec<u5> = v.to_base32();
-    let encoded : String = Bech32::new(params().zaddress_prefix.into(), checked_data).expect("bech32 failed").to_string();
+    let encoded : String = Bech32::new(params().zaddress_prefix.into(), checked_data).map_err(|e| Error::KeyEncoding(e.to_string()))?.to_string();
     
-    return encoded;
+    return Ok(encoded);
 }
 
-fn encode_privatekey(spk: &ExtendedSpendingKey) -> String {
+pub(crate) fn encode_privatekey(spk: &ExtendedSpendingKey) -> Result<String> {
     // Private Key is encoded as bech32 string
     let mut vp = Vec::new();
-    spk.write(&mut vp).expect("Can't write private key");
+    spk.write(&mut vp).map_err(|e| Error::KeyEncoding(e.to_string()))?;
     let c_d: Vec<u5> = vp.to_base32();
-    let encoded_pk = Bech32::new(params().zsecret_prefix.into(), c_d).expect("bech32 failed").to_string();
+    let encoded_pk = Bech32::new(params().zsecret_prefix.into(), c_d).map_err(|e| Error::KeyEncoding(e.to_string()))?.to_string();
 
-    return encoded_pk;
+    return Ok(encoded_pk);
 }
 
-/// A single thread that grinds through the Diversifiers to find the defualt key that matches the prefix
-pub fn vanity_thread(entropy: &[u8], prefix: String, tx: mpsc::Sender<String>, please_stop: Arc<AtomicBool>) {
-    
-    let mut seed: [u8; 32] = [0; 32];
-    seed.copy_from_slice(&entropy[0..32]);
+fn encode_viewingkey(spk: &ExtendedSpendingKey) -> Result<String> {
+    // Viewing Key is encoded as bech32 string
+    let mut vv = Vec::new();
+    ExtendedFullViewingKey::from(spk).write(&mut vv).map_err(|e| Error::KeyEncoding(e.to_string()))?;
+    let c_v: Vec<u5> = vv.to_base32();
+    let encoded_vk = Bech32::new(params().zviewkey_prefix.into(), c_v).map_err(|e| Error::KeyEncoding(e.to_string()))?.to_string();
+
+    return Ok(encoded_vk);
+}
+
+/// Build the wallet entry for the default address of a spending key
+pub(crate) fn zaddr_entry(num: u32, spk: &ExtendedSpendingKey, derivation: Option<Derivation>) -> Result<WalletEntry> {
+    Ok(WalletEntry {
+        num,
+        kind        : AddressKind::Sapling,
+        address     : encode_address(spk)?,
+        keys        : KeyMaterial { private_key: encode_privatekey(spk)?, viewing_key: Some(encode_viewingkey(spk)?) },
+        derivation,
+    })
+}
+
+/// A new ChaChaRng seeded from the operating system's entropy source
+fn system_rng() -> Result<ChaChaRng> {
+    let os_rng = OsRng::new().map_err(|e| Error::Entropy(e.to_string()))?;
+    ChaChaRng::from_rng(os_rng).map_err(|e| Error::Entropy(e.to_string()))
+}
+
+/// Messages sent by the vanity threads
+pub enum VanityMessage {
+    /// The thread checked this many more keys without finding a match
+    Processed(u64),
+    /// The thread found a matching address
+    Found(WalletEntry),
+}
+
+/// A single thread that grinds through the Diversifiers to find the defualt key that matches the prefix. 
+/// Progress and the result (or an error) are sent over `tx`.
+pub fn vanity_thread(entropy: &[u8], prefix: String, tx: mpsc::Sender<Result<VanityMessage>>, please_stop: Arc<AtomicBool>) {
+    match vanity_search(entropy, prefix, &tx, please_stop) {
+        Ok(Some(entry)) => { tx.send(Ok(VanityMessage::Found(entry))).ok(); },
+        Ok(None)        => (),
+        Err(e)          => { tx.send(Err(e)).ok(); }
+    };
+}
+
+fn vanity_search(entropy: &[u8], prefix: String, tx: &mpsc::Sender<Result<VanityMessage>>, please_stop: Arc<AtomicBool>) -> Result<Option<WalletEntry>> {
+    if entropy.len() < MIN_SEED_LENGTH || entropy.len() > MAX_SEED_LENGTH {
+        return Err(Error::Entropy(format!("Need {} to {} bytes of entropy, got {}", MIN_SEED_LENGTH, MAX_SEED_LENGTH, entropy.len())));
+    }
+
+    // The whole entropy is the seed, so any valid ZIP-32 seed length works
+    let mut seed = entropy.to_vec();
 
     let di = DiversifierIndex::new();
-    let vanity_bytes = get_bech32_for_prefix(prefix).expect("Bad char in prefix");
+    let vanity_bytes = get_bech32_for_prefix(prefix)?;
 
     let master_spk = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(&seed),
                             &[ChildIndex::Hardened(32), ChildIndex::Hardened(params().cointype), ChildIndex::Hardened(0)]);
 
     let mut spkv = vec![];
-    master_spk.write(&mut spkv).unwrap();
+    master_spk.write(&mut spkv).map_err(|e| Error::KeyEncoding(e.to_string()))?;
 
     let mut i: u32 = 0;
     loop {
         if increment(&mut seed).is_err() {
-            return;
+            return Ok(None);
         }
 
         let dk = DiversifierKey::master(&seed);
-        let (_ndk, nd) = dk.diversifier(di).unwrap();
+        let (_ndk, nd) = match dk.diversifier(di) {
+            Ok(d)  => d,
+            Err(_) => continue      // No valid diversifier for this key, so it can't be a match either
+        };
 
         // test for nd
-        let mut isequal = true;
-        for i in 0..vanity_bytes.len() {
-            if vanity_bytes[i] != nd.0.to_base32()[i] {
-                isequal = false;
-                break;
-            }
-        }
+        let nd_bytes = nd.0.to_base32();
+        let isequal = vanity_bytes.iter().zip(nd_bytes.iter()).all(|(a, b)| a == b);
 
         if isequal { 
             let len = spkv.len();
             spkv[(len-32)..len].copy_from_slice(&dk.0[0..32]);
-            let spk = ExtendedSpendingKey::read(&spkv[..]).unwrap();
-
-            
-            let encoded = encode_address(&spk);
-            let encoded_pk = encode_privatekey(&spk);
-            
-            let wallet = array!{object!{
-                "num"           => 0,
-                "address"       => encoded,
-                "private_key"   => encoded_pk,
-                "type"          => "zaddr"}};
-            
-            tx.send(json::stringify_pretty(wallet, 2)).unwrap();
-            return;
+            let spk = ExtendedSpendingKey::read(&spkv[..]).map_err(|e| Error::KeyEncoding(e.to_string()))?;
+
+            // The diversifier key was swapped in, so this key can't be re-derived from a seed
+            return Ok(Some(zaddr_entry(0, &spk, None)?));
         }
 
         i = i + 1;
         if i%5000 == 0 {
             if please_stop.load(Ordering::Relaxed) {
-                return;
+                return Ok(None);
+            }
+            if tx.send(Ok(VanityMessage::Processed(5000))).is_err() {
+                // Nobody is listening anymore
+                return Ok(None);
             }
-            tx.send("Processed:5000".to_string()).unwrap();
         }
 
-        if i == 0 { return; }
+        if i == 0 { return Ok(None); }
     }
 }
 
@@ -220,15 +434,12 @@ fn pretty_duration(secs: f64) -> (String, String) {
 }
 
 /// Generate a vanity address with the given prefix.
-pub fn generate_vanity_wallet(num_threads: u32, prefix: String) -> Result<String, String> {
+pub fn generate_vanity_wallet(num_threads: u32, prefix: String) -> Result<WalletBatch> {
     // Test the prefix first
-    match get_bech32_for_prefix(prefix.clone()) {
-        Ok(_)  => (),
-        Err(e) => return Err(format!("{}. Note that ['b', 'i', 'o', '1'] are not allowed in addresses.", e))
-    };
+    get_bech32_for_prefix(prefix.clone())?;
 
     // Get 32 bytes of system entropy
-    let mut system_rng = ChaChaRng::from_entropy();    
+    let mut system_rng = system_rng()?;
     
     let (tx, rx) = mpsc::channel();
     let please_stop = Arc::new(AtomicBool::new(false));
@@ -249,20 +460,35 @@ pub fn generate_vanity_wallet(num_threads: u32, prefix: String) -> Result<String
         });
         handles.push(handle);
     }
+
+    // Drop our own sender, so that recv() fails instead of blocking forever if all the threads stop
+    drop(tx);
     
     let mut processed: u64   = 0;
     let now = SystemTime::now();
 
-    let wallet: String;
+    let wallet: Result<WalletBatch>;
 
     // Calculate the estimated time
     let expected_combinations = (32 as f64).powf(prefix.len() as f64);
 
     loop {
-        let recv = rx.recv().unwrap();
-        if recv.starts_with(&"Processed") {
-            processed = processed + 5000;
-            let timeelapsed = now.elapsed().unwrap().as_secs() + 1; // Add one second to prevent any divide by zero problems.
+        let recv = match rx.recv() {
+            Ok(Ok(r))  => r,
+            Ok(Err(e)) => {
+                wallet = Err(e);
+                please_stop.store(true, Ordering::Relaxed);
+                break;
+            },
+            Err(_)     => {
+                wallet = Err(Error::Thread("All vanity threads stopped without finding an address".to_string()));
+                break;
+            }
+        };
+
+        if let VanityMessage::Processed(count) = recv {
+            processed = processed + count;
+            let timeelapsed = now.elapsed().map(|d| d.as_secs()).unwrap_or(0) + 1; // Add one second to prevent any divide by zero problems.
 
             let rate = processed / timeelapsed;            
             let expected_secs = expected_combinations / (rate as f64);
@@ -270,11 +496,12 @@ pub fn generate_vanity_wallet(num_threads: u32, prefix: String) -> Result<String
             let (s, d) = pretty_duration(expected_secs);
 
             print!("Checking addresses at {}/sec on {} CPU threads. [50% ETA = {} {}]   \r", rate, num_threads, s, d);
-            io::stdout().flush().ok().unwrap();
-        } else {
+            io::stdout().flush().ok();
+        } else if let VanityMessage::Found(entry) = recv {
             // Found a solution
             println!("");   // To clear the previous inline output to stdout;
-            wallet = recv;
+            let metadata = BatchMetadata::new(DerivationProfile::Vanity, "OsRng".to_string());
+            wallet = Ok(WalletBatch { entries: vec![entry], metadata: Some(metadata) });
 
             please_stop.store(true, Ordering::Relaxed);
             break;
@@ -282,26 +509,89 @@ pub fn generate_vanity_wallet(num_threads: u32, prefix: String) -> Result<String
     }
 
     for handle in handles {
-        handle.join().unwrap();
+        handle.join().map_err(|_| Error::Thread("Vanity thread panicked".to_string()))?;
     }    
 
+    // Don't trust the key until it has been derived again
+    let wallet = wallet?;
+    selftest::check_batch(&wallet)?;
+
     return Ok(wallet);
 }
 
-/// Generate a series of `count` addresses and private keys. 
-pub fn generate_wallet(nohd: bool, zcount: u32, tcount: u32, user_entropy: &[u8]) -> String {        
+/// Generate a series of `count` addresses and private keys at `path`, from seeds of `seed_length` bytes, on
+/// `threads` threads. The batch doesn't depend on the number of threads.
+pub fn generate_wallet(nohd: bool, zcount: u32, tcount: u32, path: &DerivationPath, seed_length: usize, threads: u32, user_entropy: &[u8]) -> Result<WalletBatch> {        
+    check_seed_length(seed_length)?;
+
+    if !nohd {
+        // Allow HD addresses, so use only 1 seed        
+        let (seed, entropy_source) = generate_seed(seed_length, user_entropy)?;
+        return wallet_from_seed(&seed, zcount, tcount, path, threads, entropy_source);
+    }
+
+    let (rng, entropy_source) = entropy_rng(user_entropy)?;
+
+    // Not using HD addresses, so derive a new seed every time. The stream decodes and derives every key
+    // again before anyone gets to see it.
+    let entries = bulk::stream_nohd_wallet(rng, seed_length, zcount, tcount, path, threads)?.collect::<Result<Vec<_>>>()?;
+
+    return Ok(WalletBatch { entries, metadata: Some(BatchMetadata::new(DerivationProfile::Zip32NoHd, entropy_source)) });
+}
+
+/// Generate a new seed of `length` bytes from the system entropy and the user's entropy. Returns the seed and a
+/// description of where its randomness came from.
+pub fn generate_seed(length: usize, user_entropy: &[u8]) -> Result<(Vec<u8>, String)> {
+    let (mut rng, entropy_source) = entropy_rng(user_entropy)?;
+
+    let mut seed = vec![0u8; length];
+    rng.fill(&mut seed[..]);
+
+    return Ok((seed, entropy_source));
+}
+
+/// Derive the batch of an HD seed: `zcount` z-addresses at `path`, and `tcount` t-addresses from an RNG seeded
+/// with it, on `threads` threads. The same seed, path and counts always give the same batch, so this also
+/// recovers a batch from its seed.
+pub fn wallet_from_seed(seed: &[u8], zcount: u32, tcount: u32, path: &DerivationPath, threads: u32, entropy_source: String) -> Result<WalletBatch> {
+    // The stream decodes and derives every key again before anyone gets to see it
+    let entries = bulk::stream_wallet(seed, zcount, tcount, path, threads)?.collect::<Result<Vec<_>>>()?;
+
+    return Ok(WalletBatch { entries, metadata: Some(BatchMetadata::new(DerivationProfile::Zip32Hd, entropy_source)) });
+}
+
+/// Generate a new 24 word BIP39 mnemonic from the system entropy and the user's entropy. Returns the mnemonic
+/// and a description of where its randomness came from.
+pub fn generate_mnemonic(user_entropy: &[u8]) -> Result<(String, String)> {
+    let (entropy, entropy_source) = generate_seed(32, user_entropy)?;
+    return Ok((bip39::entropy_to_mnemonic(&entropy)?, entropy_source));
+}
+
+/// Derive the batch of a BIP39 mnemonic and passphrase, like `wallet_from_seed`. The 64 byte BIP39 seed is the
+/// ZIP-32 master seed, so a different passphrase gives a different batch. The mnemonic is kept with every
+/// z-address, but the passphrase isn't.
+pub fn wallet_from_mnemonic(mnemonic: &str, passphrase: &str, zcount: u32, tcount: u32, path: &DerivationPath, threads: u32, entropy_source: String) -> Result<WalletBatch> {
+    let mnemonic = bip39::normalize_mnemonic(mnemonic)?;
+    let seed = bip39::mnemonic_to_seed(&mnemonic, passphrase)?;
+
+    let mut batch = wallet_from_seed(&seed, zcount, tcount, path, threads, entropy_source)?;
+    for d in batch.entries.iter_mut().filter_map(|e| e.derivation.as_mut()) {
+        d.mnemonic   = Some(mnemonic.clone());
+        d.passphrase = !passphrase.is_empty();
+    }
+
+    // The keys were checked by the stream, but the mnemonic wasn't
+    selftest::check_batch(&batch)?;
+
+    return Ok(batch);
+}
+
+/// An RNG seeded with 32 bytes of system entropy hashed together with the user's entropy
+fn entropy_rng(user_entropy: &[u8]) -> Result<(ChaChaRng, String)> {
     // Get 32 bytes of system entropy
     let mut system_entropy:[u8; 32] = [0; 32]; 
     {
-        let result = panic::catch_unwind(|| {
-            ChaChaRng::from_entropy()
-        });
-
-        let mut system_rng = match result {
-            Ok(rng)     => rng,
-            Err(_e)     => ChaChaRng::from_seed([0; 32])
-        };
-
+        let mut system_rng = system_rng()?;
         system_rng.fill(&mut system_entropy);
     }    
     
@@ -314,79 +604,15 @@ pub fn generate_wallet(nohd: bool, zcount: u32, tcount: u32, user_entropy: &[u8]
     final_entropy.clone_from_slice(&double_sha256(&state.result()[..]));
 
     // ...which will we use to seed the RNG
-    let mut rng = ChaChaRng::from_seed(final_entropy);
+    let rng = ChaChaRng::from_seed(final_entropy);
 
-    if !nohd {
-        // Allow HD addresses, so use only 1 seed        
-        let mut seed: [u8; 32] = [0; 32];
-        rng.fill(&mut seed);
-        
-        return gen_addresses_with_seed_as_json(zcount, tcount, |i| (seed.to_vec(), i));
-    } else {
-        // Not using HD addresses, so derive a new seed every time    
-        return gen_addresses_with_seed_as_json(zcount, tcount, |_| {            
-            let mut seed:[u8; 32] = [0; 32]; 
-            rng.fill(&mut seed);
-            
-            return (seed.to_vec(), 0);
-        });
-    }    
-}
+    let entropy_source = format!("SHA256d(OsRng + {} bytes of user entropy)", user_entropy.len());
 
-/// Generate `count` addresses with the given seed. The addresses are derived from m/32'/cointype'/index' where 
-/// index is 0..count
-/// 
-/// Note that cointype is 1 for testnet and 133 for mainnet
-/// 
-/// get_seed is a closure that will take the address number being derived, and return a tuple cointaining the 
-/// seed and child number to use to derive this wallet. 
-/// It is useful if we want to reuse (or not) the seed across multiple wallets.
-fn gen_addresses_with_seed_as_json<F>(zcount: u32, tcount: u32, mut get_seed: F) -> String 
-    where F: FnMut(u32) -> (Vec<u8>, u32)
-{
-    let mut ans = array![];
-
-    // Note that for t-addresses, we don't use HD addresses
-    let (seed, _) = get_seed(0);
-    let mut rng_seed: [u8; 32] = [0; 32];
-    rng_seed.clone_from_slice(&seed[0..32]);
-    
-    // First generate the Z addresses
-    for i in 0..zcount {
-        let (seed, child) = get_seed(i);
-        let (addr, pk, _vk, path) = get_zaddress(&seed, child);
-        ans.push(object!{
-                "num"           => i,
-                "address"       => addr,
-                "private_key"   => pk,
-                "type"          => "zaddr",
-                "seed"          => path
-        }).unwrap(); 
-    }      
-
-    // Next generate the T addresses
-    // derive a RNG from the seed
-    let mut rng = ChaChaRng::from_seed(rng_seed);
-
-    for i in 0..tcount {        
-        let (addr, pk_wif) = get_taddress(&mut rng);
-
-        ans.push(object!{
-            "num"               => i,
-            "address"           => addr,
-            "private_key"       => pk_wif,
-            "type"              => "taddr"
-        }).unwrap();
-    }
-
-    return json::stringify_pretty(ans, 2);
-}
-
-/// Generate a t address
-fn get_taddress(rng: &mut ChaChaRng) -> (String, String) {
-//    use secp256k1;
-    use ripemd160::{Ripemd160};
+    return Ok((rng, entropy_source));
+}
 
+/// The next secret key from the RNG of the t-addresses
+pub(crate) fn random_secret_key(rng: &mut ChaChaRng) -> secp256k1::SecretKey {
     let mut sk_bytes: [u8; 32] = [0;32];
 
     // There's a small chance the generated private key bytes are invalid, so
@@ -399,43 +625,373 @@ fn get_taddress(rng: &mut ChaChaRng) -> (String, String) {
             Err(_) => continue
         }
     };
-    
-    let pubkey = secp256k1::PublicKey::from_secret_key(&sk);
 
+    return sk;
+}
+
+/// The t-address and WIF of a secret key. The keys we generate are always compressed, but keys exported from
+/// old wallets might not be.
+pub(crate) fn encode_taddress(sk: &secp256k1::SecretKey, compressed: bool) -> (String, String) {
     // Address 
-    let mut hash160 = Ripemd160::new();
-    hash160.input(sha2::Sha256::digest(&pubkey.serialize_compressed().to_vec()));
-    let addr = hash160.result().to_base58check(&params().taddress_version, &[]);
+    let addr = pubkey_hash(sk, compressed).to_base58check(&params().taddress_version, &[]);
 
     // Private Key
-    let pk_wif = sk_bytes.to_base58check(&params().tsecret_prefix, &[0x01]);  
+    let suffix: &[u8] = if compressed { &[0x01] } else { &[] };
+    let pk_wif = sk.serialize().to_base58check(&params().tsecret_prefix, suffix);
 
     return (addr, pk_wif);
 }
 
-/// Generate a standard ZIP-32 address from the given seed at 32'/44'/0'/index
-fn get_zaddress(seed: &[u8], index: u32) -> (String, String, String, json::JsonValue) {
-   let spk: ExtendedSpendingKey = ExtendedSpendingKey::from_path(
-            &ExtendedSpendingKey::master(seed),
-            &[
-                ChildIndex::Hardened(32),
-                ChildIndex::Hardened(params().cointype),
-                ChildIndex::Hardened(index)
-            ],
-        );
-    let path = object!{
-        "HDSeed"    => hex::encode(seed),
-        "path"      => format!("m/32'/{}'/{}'", params().cointype, index)
-    };
+/// The Hash160 of the public key of a secret key, which is what a t-address encodes
+pub(crate) fn pubkey_hash(sk: &secp256k1::SecretKey, compressed: bool) -> Vec<u8> {
+    use ripemd160::{Ripemd160};
 
-    let encoded = encode_address(&spk);
-    let encoded_pk = encode_privatekey(&spk);
+    let pubkey = secp256k1::PublicKey::from_secret_key(sk);
 
-    // Viewing Key is encoded as bech32 string
-    let mut vv = Vec::new();
-    ExtendedFullViewingKey::from(&spk).write(&mut vv).expect("Can't write viewing key");
-    let c_v: Vec<u5> = vv.to_base32();
-    let encoded_vk = Bech32::new(params().zviewkey_prefix.into(), c_v).expect("bech32 failed").to_string();
+    let mut hash160 = Ripemd160::new();
+    if compressed {
+        hash160.input(sha2::Sha256::digest(&pubkey.serialize_compressed().to_vec()));
+    } else {
+        hash160.input(sha2::Sha256::digest(&pubkey.serialize().to_vec()));
+    }
+
+    return hash160.result().to_vec();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::{decode, KeyKind};
+
+    /// A fixed seed, so the tests are repeatable
+    fn test_seed() -> [u8; 32] {
+        let mut seed = [0u8; 32];
+        for (i, b) in seed.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        seed
+    }
+
+    #[test]
+    fn test_double_sha256() {
+        assert_eq!(hex::encode(double_sha256(b"")), "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456");
+        assert_eq!(hex::encode(double_sha256(b"hello")), "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50");
+    }
+
+    #[test]
+    fn test_to_base58check() {
+        // The public key hash of the secret key 1
+        let hash = hex::decode("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+
+        // The well known Bitcoin address, and the same hash as a Hush t-address
+        assert_eq!(hash.to_base58check(&[0x00], &[]), "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+        assert_eq!(hash.to_base58check(&params().taddress_version, &[]), "RKxTdfmtxtfLDKZBgx6SvNkBtNu9jRYnLh");
+
+        // Leading zero bytes are encoded as '1's
+        assert_eq!([0u8, 0, 0].to_base58check(&[0x00], &[]).chars().take(4).collect::<String>(), "1111");
+    }
+
+    #[test]
+    fn test_increment() {
+        let mut s = [0u8; 32];
+        assert!(increment(&mut s).is_ok());
+        assert_eq!(s[0], 1);
+        assert!(s[1..].iter().all(|b| *b == 0));
+
+        // Carry into the next byte
+        let mut s = [0u8; 32];
+        s[0] = 0xff;
+        assert!(increment(&mut s).is_ok());
+        assert_eq!(&s[..3], &[0, 1, 0]);
+
+        // Overflow wraps around to all zeros
+        let mut s = [0xffu8; 32];
+        assert!(increment(&mut s).is_err());
+        assert_eq!(s, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_get_bech32_for_prefix() {
+        let to_u8 = |v: Vec<u5>| v.iter().map(|u| u.to_u8()).collect::<Vec<u8>>();
+
+        assert_eq!(to_u8(get_bech32_for_prefix("qpzry9x8gf2tvdw0s3jn54khce6mua7l".chars().take(MAX_VANITY_PREFIX).collect()).unwrap()),
+                   (0..MAX_VANITY_PREFIX as u8).collect::<Vec<u8>>());
+        assert_eq!(to_u8(get_bech32_for_prefix("".to_string()).unwrap()), Vec::<u8>::new());
+
+        // Upper case is the same as lower case
+        assert_eq!(to_u8(get_bech32_for_prefix("QPZ".to_string()).unwrap()), vec![0, 1, 2]);
+
+        for c in &['b', 'i', 'o', '1', ' ', '-', 'é'] {
+            match get_bech32_for_prefix(format!("a{}", c)) {
+                Err(Error::InvalidPrefix(x)) => assert_eq!(x, *c),
+                _                            => panic!("'{}' should be rejected", c)
+            }
+        }
+
+        match get_bech32_for_prefix("q".repeat(MAX_VANITY_PREFIX + 1)) {
+            Err(Error::PrefixTooLong(len)) => assert_eq!(len, MAX_VANITY_PREFIX + 1),
+            _                              => panic!("Prefix should be too long")
+        }
+    }
+
+    #[test]
+    fn test_taddress() {
+        // Known answer for the secret key 1
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let sk = secp256k1::SecretKey::parse(&one).unwrap();
+        assert_eq!(encode_taddress(&sk, true), ("RKxTdfmtxtfLDKZBgx6SvNkBtNu9jRYnLh".to_string(),
+                                                "Up1YVLk7uuErCHVQyFCtfinZngmdwfyfc47WCQ8oJxgowjVzNeqs".to_string()));
+
+        // Addresses from the same RNG seed are the same, and every WIF decodes to its address
+        let mut rng1 = ChaChaRng::from_seed(test_seed());
+        let mut rng2 = ChaChaRng::from_seed(test_seed());
+        for _ in 0..10 {
+            let (addr, wif) = encode_taddress(&random_secret_key(&mut rng1), true);
+            assert_eq!(encode_taddress(&random_secret_key(&mut rng2), true), (addr.clone(), wif.clone()));
+
+            assert!(addr.starts_with("R"));
+            assert_eq!(decode(&addr, &params()).unwrap().kind, KeyKind::TransparentAddress);
+
+            let key = decode(&wif, &params()).unwrap();
+            assert_eq!(key.kind, KeyKind::Wif);
+            assert_eq!(key.address, addr);
+        }
+    }
+
+    #[test]
+    fn test_zaddress_encoding() {
+        let spk = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(&test_seed()),
+                    &[ChildIndex::Hardened(32), ChildIndex::Hardened(params().cointype), ChildIndex::Hardened(0)]);
+
+        let address = encode_address(&spk).unwrap();
+        let pk      = encode_privatekey(&spk).unwrap();
+        let vk      = encode_viewingkey(&spk).unwrap();
+
+        assert!(address.starts_with("zs1"));
+        assert_eq!(address.len(), 78);
+        assert!(pk.starts_with("secret-extended-key-main1"));
+        assert!(vk.starts_with("zviews1"));
+
+        // The keys decode back, and both belong to the address
+        assert_eq!(decode(&address, &params()).unwrap().kind, KeyKind::SaplingAddress);
+        let decoded_pk = decode(&pk, &params()).unwrap();
+        assert_eq!((decoded_pk.kind, decoded_pk.address), (KeyKind::SpendingKey, address.clone()));
+        let decoded_vk = decode(&vk, &params()).unwrap();
+        assert_eq!((decoded_vk.kind, decoded_vk.address), (KeyKind::ViewingKey, address.clone()));
+
+        // The entry has the same encodings
+        let entry = zaddr_entry(3, &spk, None).unwrap();
+        assert_eq!((entry.num, entry.kind), (3, AddressKind::Sapling));
+        assert_eq!((entry.address, entry.keys.private_key, entry.keys.viewing_key), (address, pk, Some(vk)));
+    }
+
+    #[test]
+    fn test_zip32_default_address() {
+        // From the zip32 tests in zcash_primitives, computed with its Rust implementation
+        let (_, addr) = ExtendedSpendingKey::master(&[0; 32]).default_address().unwrap();
+        assert_eq!(addr.diversifier.0, [59, 246, 250, 31, 131, 191, 69, 99, 200, 167, 19]);
+    }
+
+    #[test]
+    fn test_zip32_vectors() {
+        // The xsk and xfvk of the published ZIP-32 test vectors for the seed 0..31, from sapling_zip32.py in
+        // zcash-test-vectors, encoded with the prefixes of this coin
+        let master = ExtendedSpendingKey::master(&test_seed());
+        let vectors = [
+            (master.clone(),
+             "secret-extended-key-main1qqqqqqqqqqqqqqxsj37ykqalw23h4dz0wgnk688nlhxha0e7wv6gklj4p46jqxrx36mvqryn6dsr9wdzdr5eap4gvpmk2c9lp6purggt28mq0j25wsjsdqsyah5rktclhkz0ndza07vkut4apgps45jrkj8d88m532yzr6sx89vgfzgrywuafyeuqgwm3x70we7lyxthktlsdquysvs6fh62lvsh0stukadh0940kw0s7053eyjxqld9d756yr3gx5ymez37lxt2zuscfzd9h",
+             "zviews1qqqqqqqqqqqqqqxsj37ykqalw23h4dz0wgnk688nlhxha0e7wv6gklj4p46jqxrx36f5gtjlalal79h8y9eq9hrnqeeflll7skh4dqauufjzu0htt5u8rh8gulk7eczt39gyzlu9hftkjxmc83zmrgn5ytd3dy7uadnmzqgx89vgfzgrywuafyeuqgwm3x70we7lyxthktlsdquysvs6fh62lvsh0stukadh0940kw0s7053eyjxqld9d756yr3gx5ymez37lxt2zushy94w7",
+             [0xd8, 0x62, 0x1b, 0x98, 0x1c, 0xf3, 0x00, 0xe9, 0xd4, 0xcc, 0x89]),
+            (master.derive_child(ChildIndex::NonHardened(1)),
+             "secret-extended-key-main1qy2vyuf6qyqqqqqpgugsc6g6qwuanu96jqzuteus5k2m0uzwxv5a97jr3fnstk4uuc5zhsvh55tzslyw4rmgcsj2htfs9dzum725q7tp67utg4fx0g6seear9xy0mjs7lntdr3x9vtnznshfdvkr7lk6qjkyalgczrlkhwsptufcrlygsmdx5qkllmhu75pugrag7k3k77n3gt7crd2333dyw36wqn0gx23d0y0vz2dtjqptj8y7nn0w67fyrf7yjc89z7xcwrqmfhqxldc40",
+             "zviews1qy2vyuf6qyqqqqqpgugsc6g6qwuanu96jqzuteus5k2m0uzwxv5a97jr3fnstk4uumwpfdg56w5jt9xzryj67tmhvkj50vcww0a8kuqw5xll9e00425gkc2jadlakff808wuh9wjzl4ykm7ngqmwntddkw6unjlvadqm53f2tufcrlygsmdx5qkllmhu75pugrag7k3k77n3gt7crd2333dyw36wqn0gx23d0y0vz2dtjqptj8y7nn0w67fyrf7yjc89z7xcwrqmfhqjq6ha2",
+             [0x8b, 0x41, 0x38, 0x32, 0x0d, 0xfa, 0xfd, 0x7b, 0x39, 0x97, 0x81]),
+            (master.derive_child(ChildIndex::NonHardened(1)).derive_child(ChildIndex::Hardened(2)),
+             "secret-extended-key-main1qtden8s8qgqqpqyhec2lfmgmjuumqf32gcauk0wfkw7jxgafh2jyrjjzwaec82x5xk97syfuac6p8fclstzpljx629a7zdqynqewdqjujtdxhp87unrq6dmcqkwu26086rfj8y2h8723h00f9lrtnnmpgaekv8zuyua2dxgve7q3st5kyg7q9r8r6m4509xnzyae2p5aznzhtz8pjwm9alpgzw728mdpn70073k2zt06r0csxud535d55sxy6pdqmrwwpe7uv2c8kdcj46mkv",
+             "zviews1qtden8s8qgqqpqyhec2lfmgmjuumqf32gcauk0wfkw7jxgafh2jyrjjzwaec82x5xknvtyj6p7zl5nc7gp0r5jts6rz2fdypgsu0f6w52g8zpalaeuuyzvzwxpv3vgttadak2nv24egwe5vgljecf0pkcqxxvneswuj79ms3e7q3st5kyg7q9r8r6m4509xnzyae2p5aznzhtz8pjwm9alpgzw728mdpn70073k2zt06r0csxud535d55sxy6pdqmrwwpe7uv2c8kdc4r54yf",
+             [0xe8, 0xd0, 0x37, 0x93, 0xcd, 0xd2, 0xba, 0xcc, 0x9c, 0x70, 0x41]),
+        ];
+
+        for (spk, pk, vk, d0) in vectors.iter() {
+            assert_eq!(encode_privatekey(spk).unwrap(), *pk);
+            assert_eq!(encode_viewingkey(spk).unwrap(), *vk);
+
+            // Diversifier index 0 is valid for all of them, so it is the one of the default address
+            let (_, addr) = spk.default_address().unwrap();
+            assert_eq!(addr.diversifier.0, *d0);
+        }
+
+        // The vectors don't have whole addresses. These ones are from the zcash_primitives implementation
+        // the keys above were checked against, so they only catch a change in how addresses are encoded.
+        let entries = wallet_from_seed(&test_seed(), 1, 0, &DerivationPath::default(), 1, "test".to_string()).unwrap().entries;
+        assert_eq!(encode_address(&master).unwrap(), "zs1mp3phxqu7vqwn4xv38yu4ujdtr0zf8uhxg798utekase0xj8p5qre5646d9rgfetsfzqye4gayt");
+
+        // m/32'/133'/0', the first address of a wallet
+        assert_eq!(entries[0].address, "zs1mrhc9y7jdh5r9ece8u5khgvj9kg0zgkxzdduyv0whkg7lkcrkx5xqem3e48avjq9wn2rukydkwn");
+        assert_eq!(entries[0].keys.private_key, "secret-extended-key-main1qvmjmz6rqqqqpqzwtfucl5xld0ptzguvaate2mhn255ts7jtym9ram4j3vgg4g9wj2xetfdh8gepzmg3utfe96se4r0zhx6c02dpn9w46l75scpx6m6sh8ulfrf8j7yqkjk8vqcq279chxw9wpt2r2js8x4pqvn5j7dpc9sv3m5ze9p4fr2wx0605vr64dqupvzg2x3pmw7pty5gddk63vkxhekc7lq8lgdzmtcsehsn0ml404v0ztclm8utupzcvujfk4ylqk5sqsqplg80g");
+        assert_eq!(entries[0].keys.viewing_key.as_ref().unwrap(), "zviews1qvmjmz6rqqqqpqzwtfucl5xld0ptzguvaate2mhn255ts7jtym9ram4j3vgg4g9wjgca9sw392zzfkn62uvctjgspy86atg2myma0yrgvfa04cv3dnwvrmkrw24zgqkwwfs3l3ejua8rr8z92tfsjxlpe0fws4vnxkuq0s943m5ze9p4fr2wx0605vr64dqupvzg2x3pmw7pty5gddk63vkxhekc7lq8lgdzmtcsehsn0ml404v0ztclm8utupzcvujfk4ylqk5sqsqtgueax");
+    }
+
+    #[test]
+    fn test_zip32_paths() {
+        let seed = test_seed();
+        let master = ExtendedSpendingKey::master(&seed);
+        let batch = wallet_from_seed(&seed, 5, 0, &DerivationPath::default(), 1, "test".to_string()).unwrap();
+
+        let mut addresses = vec![];
+        for (i, entry) in (0..5).zip(batch.entries) {
+            // Same as deriving one level at a time
+            let spk = master.derive_child(ChildIndex::Hardened(32))
+                            .derive_child(ChildIndex::Hardened(params().cointype))
+                            .derive_child(ChildIndex::Hardened(i));
+            assert_eq!(entry.keys.private_key, encode_privatekey(&spk).unwrap());
+
+            // And as the entry of the key at the path
+            let at_path = ExtendedSpendingKey::from_path(&master, &DerivationPath::default().child_indices(i).unwrap());
+            assert_eq!(entry, zaddr_entry(i, &at_path, entry.derivation.clone()).unwrap());
+
+            let derivation = entry.derivation.clone().unwrap();
+            assert_eq!(derivation.hd_seed, seed.to_vec());
+            assert_eq!(derivation.path, format!("m/32'/133'/{}'", i));
+            assert_eq!(derivation.child_indices().unwrap(),
+                       vec![ChildIndex::Hardened(32), ChildIndex::Hardened(133), ChildIndex::Hardened(i)]);
+
+            assert!(!addresses.contains(&entry.address));
+            addresses.push(entry.address);
+        }
+    }
+
+    #[test]
+    fn test_derivation_paths() {
+        let seed = test_seed();
+        let default = DerivationPath::default();
+        assert_eq!(default.path_of(3), "m/32'/133'/3'");
+        assert_eq!(DerivationPath::parse("m/32'/133'").unwrap(), default);
+        assert_eq!(DerivationPath::of_address("m/32'/133'/5'").unwrap(), default.clone().with_start(5).unwrap());
+
+        // An account puts the addresses a level below it
+        let account = DerivationPath::account(2).unwrap();
+        assert_eq!(account.path_of(1), "m/32'/133'/2'/1'");
+        let batch = wallet_from_seed(&seed, 2, 0, &account, 1, "test".to_string()).unwrap();
+        let spk = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(&seed),
+                    &[ChildIndex::Hardened(32), ChildIndex::Hardened(133), ChildIndex::Hardened(2), ChildIndex::Hardened(1)]);
+        assert_eq!(batch.entries[1].keys.private_key, encode_privatekey(&spk).unwrap());
+        assert_eq!(batch.entries[1].derivation.as_ref().unwrap().path, "m/32'/133'/2'/1'");
+
+        // A start index continues where another batch stopped, without colliding with it
+        let all   = wallet_from_seed(&seed, 5, 0, &default, 1, "test".to_string()).unwrap();
+        let later = wallet_from_seed(&seed, 2, 0, &default.clone().with_start(3).unwrap(), 1, "test".to_string()).unwrap();
+        assert_eq!(later.entries[0].address, all.entries[3].address);
+        assert_eq!(later.entries[1].derivation.as_ref().unwrap().path, "m/32'/133'/4'");
+        assert_eq!(later.entries[1].num, 1);
+
+        let custom = DerivationPath::parse("m/44'/133'/7'").unwrap();
+        assert_eq!(wallet_from_seed(&seed, 1, 0, &custom, 1, "test".to_string()).unwrap().entries[0].derivation.as_ref().unwrap().path, "m/44'/133'/7'/0'");
+
+        // Only hardened levels, and indices below 2^31
+        for path in &["m/32/133'", "32'/133'", "m/32'/x'", "m/2147483648'", ""] {
+            assert!(DerivationPath::parse(path).is_err(), "{}", path);
+        }
+        assert!(DerivationPath::account(1 << 31).is_err());
+        assert!(default.clone().with_start(1 << 31).is_err());
+        let last = default.clone().with_start((1 << 31) - 1).unwrap();
+        assert!(wallet_from_seed(&seed, 1, 0, &last, 1, "test".to_string()).is_ok());
+        assert!(wallet_from_seed(&seed, 2, 0, &last, 1, "test".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_generate_wallet() {
+        let batch = generate_wallet(false, 3, 2, &DerivationPath::default(), DEFAULT_SEED_LENGTH, 2, b"some user entropy").unwrap();
+        assert_eq!(batch.entries.len(), 5);
+        assert_eq!(batch.metadata.as_ref().unwrap().derivation_profile, DerivationProfile::Zip32Hd);
+
+        let zs: Vec<&WalletEntry> = batch.entries.iter().filter(|e| e.kind == AddressKind::Sapling).collect();
+        let ts: Vec<&WalletEntry> = batch.entries.iter().filter(|e| e.kind == AddressKind::Transparent).collect();
+        assert_eq!(zs.iter().map(|e| e.num).collect::<Vec<u32>>(), vec![0, 1, 2]);
+        assert_eq!(ts.iter().map(|e| e.num).collect::<Vec<u32>>(), vec![0, 1]);
+
+        // HD addresses share a seed, at increasing indices
+        let seed = &zs[0].derivation.as_ref().unwrap().hd_seed;
+        for (i, z) in zs.iter().enumerate() {
+            let d = z.derivation.as_ref().unwrap();
+            assert_eq!(&d.hd_seed, seed);
+            assert_eq!(d.path, format!("m/32'/133'/{}'", i));
+        }
+        assert!(ts.iter().all(|t| t.derivation.is_none() && t.keys.viewing_key.is_none()));
+
+        // Without HD, every address has its own seed
+        let batch = generate_wallet(true, 3, 0, &DerivationPath::default(), DEFAULT_SEED_LENGTH, 2, b"").unwrap();
+        assert_eq!(batch.metadata.as_ref().unwrap().derivation_profile, DerivationProfile::Zip32NoHd);
+        let seeds: Vec<Vec<u8>> = batch.entries.iter().map(|e| e.derivation.as_ref().unwrap().hd_seed.clone()).collect();
+        assert!(seeds[0] != seeds[1] && seeds[1] != seeds[2] && seeds[0] != seeds[2]);
+        assert!(batch.entries.iter().all(|e| e.derivation.as_ref().unwrap().path == "m/32'/133'/0'"));
+
+        // Two runs never give the same keys
+        let other = generate_wallet(false, 1, 1, &DerivationPath::default(), DEFAULT_SEED_LENGTH, 2, b"some user entropy").unwrap();
+        assert!(other.entries[0].address != zs[0].address);
+    }
+
+    #[test]
+    fn test_seed_lengths() {
+        // Every ZIP-32 seed length works, and the whole seed is kept
+        for len in &[MIN_SEED_LENGTH, 64, MAX_SEED_LENGTH] {
+            let seed: Vec<u8> = (0..*len).map(|i| i as u8).collect();
+            let batch = wallet_from_seed(&seed, 1, 1, &DerivationPath::default(), 1, "test".to_string()).unwrap();
+            assert_eq!(batch.entries[0].derivation.as_ref().unwrap().hd_seed, seed);
+            let spk = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(&seed), &DerivationPath::default().child_indices(0).unwrap());
+            assert_eq!(batch.entries[0].keys.private_key, encode_privatekey(&spk).unwrap());
+            assert_eq!(WalletBatch::from_json_str(&batch.to_json_string()).unwrap().entries, batch.entries);
+        }
+
+        // A longer seed is another master key, even with the same first 32 bytes
+        let seed: Vec<u8> = (0..64).collect();
+        assert!(wallet_from_seed(&seed, 1, 0, &DerivationPath::default(), 1, "test".to_string()).unwrap().entries[0].address
+                != wallet_from_seed(&seed[..32], 1, 0, &DerivationPath::default(), 1, "test".to_string()).unwrap().entries[0].address);
+
+        assert!(wallet_from_seed(&seed[..31], 1, 0, &DerivationPath::default(), 1, "test".to_string()).is_err());
+        assert!(wallet_from_seed(&[0u8; MAX_SEED_LENGTH + 1], 1, 0, &DerivationPath::default(), 1, "test".to_string()).is_err());
+
+        let batch = generate_wallet(true, 2, 0, &DerivationPath::default(), 64, 2, b"").unwrap();
+        assert!(batch.entries.iter().all(|e| e.derivation.as_ref().unwrap().hd_seed.len() == 64));
+        assert!(generate_wallet(false, 1, 0, &DerivationPath::default(), 16, 2, b"").is_err());
 
-    return (encoded, encoded_pk, encoded_vk, path);
+        assert_eq!(decode_seed(&format!(" {}\n{} ", hex::encode(&seed[..32]), hex::encode(&seed[32..]))).unwrap(), seed);
+        assert!(decode_seed(&hex::encode(&seed[..31])).is_err());
+        assert!(decode_seed("not hex").is_err());
+    }
+
+    #[test]
+    fn test_wallet_from_mnemonic() {
+        let (mnemonic, _) = generate_mnemonic(b"some user entropy").unwrap();
+        assert_eq!(mnemonic.split(' ').count(), 24);
+
+        let batch = wallet_from_mnemonic(&mnemonic, "", 2, 1, &DerivationPath::default(), 1, "test".to_string()).unwrap();
+        let d = batch.entries[0].derivation.as_ref().unwrap();
+        assert_eq!(d.hd_seed, bip39::mnemonic_to_seed(&mnemonic, "").unwrap().to_vec());
+        assert_eq!(d.mnemonic.as_ref(), Some(&mnemonic));
+        assert!(!d.passphrase);
+
+        // Abbreviated words give the same wallet, and the mnemonic survives the JSON
+        let abbreviated: Vec<String> = mnemonic.split(' ').map(|w| w.chars().take(4).collect()).collect();
+        assert_eq!(wallet_from_mnemonic(&abbreviated.join(" "), "", 2, 1, &DerivationPath::default(), 1, "test".to_string()).unwrap().entries, batch.entries);
+        assert_eq!(WalletBatch::from_json_str(&batch.to_json_string()).unwrap().entries, batch.entries);
+
+        // A passphrase gives other addresses, and isn't stored
+        let protected = wallet_from_mnemonic(&mnemonic, "25th word", 2, 1, &DerivationPath::default(), 1, "test".to_string()).unwrap();
+        assert!(protected.entries.iter().zip(&batch.entries).all(|(a, b)| a.address != b.address));
+        assert!(protected.entries[0].derivation.as_ref().unwrap().passphrase);
+        assert!(!protected.to_json_string().contains("25th word"));
+    }
+
+    #[test]
+    fn test_vanity() {
+        let batch = generate_vanity_wallet(2, "a".to_string()).unwrap();
+        assert_eq!(batch.entries.len(), 1);
+        assert!(batch.entries[0].address.starts_with("zs1a"));
+        assert!(batch.entries[0].derivation.is_none());
+        assert_eq!(batch.metadata.unwrap().derivation_profile, DerivationProfile::Vanity);
+
+        assert!(generate_vanity_wallet(1, "ab".to_string()).is_err());
+    }
 }
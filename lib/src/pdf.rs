@@ -1,60 +1,332 @@
 extern crate printpdf;
 
-use crate::paper::params;
+use crate::bip38;
+use crate::encrypted_key::encrypt_spending_key;
+use crate::encryption::KdfParams;
+use crate::secure_file::write_secret_file;
+use crate::error::{Error, Result};
+use crate::export::EntryWriter;
+use crate::paper::{params, DerivationPath};
+use crate::codex32;
+use crate::slip39;
+use crate::wallet::{AddressKind, WalletBatch, WalletEntry};
 
 use qrcode::QrCode;
 use qrcode::types::Color;
 
+use std::fs;
 use std::io::BufWriter;
 use std::convert::From;
 use std::f64;
-use std::fs::File;
 use printpdf::*;
 
 
 /**
- * Save the list of wallets (address + private keys) to the given PDF file name.
+ * The shares that the HD seed of a wallet was split into, to print one per page
  */
-pub fn save_to_pdf(addresses: &str, filename: &str) -> Result<(), String> {
-    let (doc, page1, layer1) = PdfDocument::new("SilentDragonPaper Wallet", Mm(210.0), Mm(297.0), "Layer 1");
+#[derive(Clone, Debug)]
+pub enum SeedShares {
+    /// The member shares of every group
+    Slip39(Vec<Vec<slip39::Share>>),
+    Codex32(Vec<codex32::Share>),
+}
+
+impl SeedShares {
+    pub fn count(&self) -> usize {
+        match self {
+            SeedShares::Slip39(groups)  => groups.iter().map(|g| g.len()).sum(),
+            SeedShares::Codex32(shares) => shares.len()
+        }
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            SeedShares::Slip39(_)  => "SLIP-39",
+            SeedShares::Codex32(_) => "codex32"
+        }
+    }
+}
+
+/**
+ * The paper size to lay out the PDF for
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaperSize {
+    A4,
+    Letter,
+}
+
+impl PaperSize {
+    /**
+     * Width and height of the page in mm
+     */
+    pub fn dimensions(&self) -> (f64, f64) {
+        match self {
+            PaperSize::A4     => (210.0, 297.0),
+            PaperSize::Letter => (215.9, 279.4),
+        }
+    }
+}
+
+impl std::str::FromStr for PaperSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PaperSize> {
+        match s.to_lowercase().as_str() {
+            "a4"     => Ok(PaperSize::A4),
+            "letter" => Ok(PaperSize::Letter),
+            _        => Err(Error::Pdf(format!("Unknown paper size '{}'", s)))
+        }
+    }
+}
+
+/**
+ * Options for laying out the PDF
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct PdfOptions {
+    pub paper_size     : PaperSize,
+    /// Only print these pages (numbered from 1), for example to reprint a damaged page. All pages if None.
+    pub pages          : Option<Vec<usize>>,
+    /// Print the private keys encrypted with this passphrase (BIP38 for t-addresses, and our own format for
+    /// Sapling spending keys) instead of in plain text. The HD seed isn't printed then.
+    pub key_passphrase : Option<String>,
+    /// The scrypt cost of the encrypted spending keys. Every key is encrypted on its own, so this is paid once
+    /// for every z-address.
+    pub key_kdf        : KdfParams,
+}
+
+impl Default for PdfOptions {
+    fn default() -> PdfOptions {
+        PdfOptions { paper_size: PaperSize::A4, pages: None, key_passphrase: None, key_kdf: KdfParams::default() }
+    }
+}
 
-    let font  = doc.add_builtin_font(BuiltinFont::Courier).unwrap();
-    let font_bold = doc.add_builtin_font(BuiltinFont::CourierBold).unwrap();
+/**
+ * Save the list of wallets (address + private keys) to the given PDF file name. The file is created with
+ * owner-only permissions, and an existing file is only replaced if `overwrite` is set.
+ */
+pub fn save_to_pdf(wallet: &WalletBatch, filename: &str, options: &PdfOptions, overwrite: bool) -> Result<()> {
+    let contents = render_pdf(wallet, options)?;
+    return write_secret_file(filename, &contents, overwrite);
+}
+
+/**
+ * Writes a batch that is too large to render at once as a series of PDF files, with `per_file` entries (and
+ * pages) in each, named like wallet-0001.pdf, wallet-0002.pdf... for the file name wallet.pdf. Only the entries
+ * of one file are ever in memory.
+ *
+ * The HD seed and path are never printed. All the entries of a bulk batch share one seed, and the pages are
+ * handed out one by one, so the seed on any page would give away every address of the batch.
+ *
+ * The pages are numbered through the whole batch of `total` entries, and every footer says which of the files
+ * it is in. Unless `finish` succeeds, the files that were written are removed again when the writer is dropped,
+ * so a failed batch never leaves some of its files behind.
+ */
+pub struct PdfPartsWriter {
+    filename  : String,
+    per_file  : usize,
+    total     : u64,
+    options   : PdfOptions,
+    overwrite : bool,
+    pending   : Vec<WalletEntry>,
+    written   : Vec<String>,
+    finished  : bool,
+}
+
+/**
+ * Where the pages of one file are in a batch that is written to several PDF files
+ */
+struct Part {
+    /// The number of the file, from 1
+    number      : usize,
+    parts       : usize,
+    /// The page number in the whole batch of the first page of the file
+    first_page  : usize,
+    total_pages : u64,
+}
+
+impl PdfPartsWriter {
+    pub fn new(filename: &str, per_file: usize, total: u64, options: &PdfOptions, overwrite: bool) -> Result<PdfPartsWriter> {
+        if per_file == 0 {
+            return Err(Error::Pdf("Every PDF file needs at least 1 page".to_string()));
+        }
+        if options.pages.is_some() {
+            return Err(Error::Pdf("Can't select pages of a batch that is written to several PDF files".to_string()));
+        }
+
+        return Ok(PdfPartsWriter {
+            filename  : filename.to_string(),
+            per_file,
+            total,
+            options   : options.clone(),
+            overwrite,
+            pending   : Vec::new(),
+            written   : Vec::new(),
+            finished  : false,
+        });
+    }
+
+    /**
+     * The file name of part `part` (numbered from 1)
+     */
+    pub fn part_name(filename: &str, part: usize) -> String {
+        let stem = if filename.to_ascii_lowercase().ends_with(".pdf") { &filename[..filename.len()-4] } else { filename };
+        return format!("{}-{:04}.pdf", stem, part);
+    }
+
+    /**
+     * The number of files written so far
+     */
+    pub fn parts(&self) -> usize {
+        return self.written.len();
+    }
+
+    /**
+     * Remove the files written so far, after a failure. Returns how many there were.
+     */
+    pub fn abort(mut self) -> usize {
+        return self.remove_parts();
+    }
+
+    fn remove_parts(&mut self) -> usize {
+        let written = std::mem::replace(&mut self.written, Vec::new());
+        for name in &written {
+            fs::remove_file(name).ok();
+        }
+        return written.len();
+    }
+
+    fn write_part(&mut self) -> Result<()> {
+        let batch = WalletBatch { entries: std::mem::replace(&mut self.pending, Vec::new()), metadata: None };
+        let part = Part {
+            number      : self.written.len() + 1,
+            parts       : ((self.total + self.per_file as u64 - 1) / self.per_file as u64) as usize,
+            first_page  : self.written.len() * self.per_file + 1,
+            total_pages : self.total,
+        };
+        let name = PdfPartsWriter::part_name(&self.filename, part.number);
+
+        let contents = render_pages(&batch, &self.options, Some(&part))?;
+        write_secret_file(&name, &contents, self.overwrite)?;
+        self.written.push(name);
+
+        return Ok(());
+    }
+}
+
+impl Drop for PdfPartsWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.remove_parts();
+        }
+    }
+}
+
+impl EntryWriter for PdfPartsWriter {
+    fn write_entry(&mut self, entry: &WalletEntry) -> Result<()> {
+        let mut entry = entry.clone();
+        entry.derivation = None;
+        self.pending.push(entry);
+        if self.pending.len() == self.per_file {
+            self.write_part()?;
+        }
+        return Ok(());
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.write_part()?;
+        }
+        self.finished = true;
+        return Ok(());
+    }
+}
+
+/**
+ * Render the list of wallets to a PDF in memory, for example to encrypt it before it touches the disk.
+ */
+pub fn render_pdf(wallet: &WalletBatch, options: &PdfOptions) -> Result<Vec<u8>> {
+    return render_pages(wallet, options, None);
+}
+
+/**
+ * Render the wallets to a PDF, as one `part` of a larger batch if it is set
+ */
+fn render_pages(wallet: &WalletBatch, options: &PdfOptions, part: Option<&Part>) -> Result<Vec<u8>> {
+    let (page_width, page_height) = options.paper_size.dimensions();
+    let (doc, page1, layer1) = PdfDocument::new("SilentDragonPaper Wallet", Mm(page_width), Mm(page_height), "Layer 1");
+
+    let font  = doc.add_builtin_font(BuiltinFont::Courier).map_err(|e| Error::Pdf(e.to_string()))?;
+    let font_bold = doc.add_builtin_font(BuiltinFont::CourierBold).map_err(|e| Error::Pdf(e.to_string()))?;
 
-    let keys = json::parse(&addresses).unwrap();
 
     // Position on the PDF page.
     let mut pos = 0;
 
     let mut current_layer = doc.get_page(page1).get_layer(layer1);
     
-    let total_pages      = f64::ceil(keys.len() as f64 / 1.0);   // 1 per page
-    let mut current_page = 1; 
+    // The BIP39 mnemonic of the seed gets a page of its own after the addresses, unless the keys are protected
+    let mnemonic = wallet.entries.iter().filter_map(|e| e.derivation.as_ref())
+                                 .find_map(|d| d.mnemonic.as_ref().map(|m| (m, d.passphrase)))
+                                 .filter(|_| options.key_passphrase.is_none());
+    let mnemonic_page = wallet.entries.len() + 1;
+
+    let total_pages      = f64::ceil(wallet.entries.len() as f64 / 1.0) + if mnemonic.is_some() { 1.0 } else { 0.0 };   // 1 per page
+
+    if let Some(pages) = &options.pages {
+        if let Some(p) = pages.iter().find(|p| **p == 0 || **p as f64 > total_pages) {
+            return Err(Error::Pdf(format!("There is no page {}. The wallet has {} pages", p, total_pages)));
+        }
+    }
+
+    let coin = wallet.metadata.as_ref().map(|m| m.coin.clone()).unwrap_or_else(params);
+
+    let selected = wallet.entries.iter().enumerate().filter(|(i, _)| match &options.pages {
+        Some(pages) => pages.contains(&(i + 1)),
+        None        => true
+    });
+
+    for (i, entry) in selected {
+        // The page numbers in the footer are always the page numbers of the full wallet
+        let current_page = i + 1;
 
-    for kv in keys.members() {
         // Add next page when moving to the next position.
         if pos >= 1 {
             pos = 0;
-            current_page = current_page + 1;
 
             // Add a page
-            let (page2, _) = doc.add_page(Mm(210.0), Mm(297.0),"Page 2, Layer 1");
+            let (page2, _) = doc.add_page(Mm(page_width), Mm(page_height),"Page 2, Layer 1");
             current_layer = doc.get_page(page2).add_layer("Layer 3");
         }
 
-        let address  = kv["address"].as_str().unwrap();
-        let pk       = kv["private_key"].as_str().unwrap();
-        let is_taddr = !address.starts_with(&params().zaddress_prefix);
+        let address  = &entry.address;
+        let is_taddr = entry.kind == AddressKind::Transparent;
 
-        let (seed, hdpath) = if kv["type"].as_str().unwrap() == "zaddr" && kv.contains("seed") {
-            (kv["seed"]["HDSeed"].as_str().unwrap(), kv["seed"]["path"].as_str().unwrap())
-        } else {
-            ("", "")
+        let (pk, pk_title) = match &options.key_passphrase {
+            None                         => (entry.keys.private_key.clone(), "Private Key"),
+            Some(passphrase) if is_taddr => (bip38::encrypt_wif(&entry.keys.private_key, passphrase, &coin)?, "Private Key (BIP38 encrypted)"),
+            Some(passphrase)             => (encrypt_spending_key(&entry.keys.private_key, passphrase, &coin, &options.key_kdf)?, "Private Key (encrypted)")
+        };
+
+        // An encrypted spending key is longer, but in upper case it fits in a QR code of the same size, because
+        // the QR code can use its alphanumeric mode. Bech32 decoders accept either case.
+        let pk_qr = if options.key_passphrase.is_some() && !is_taddr { pk.to_ascii_uppercase() } else { pk.clone() };
+
+        // The HD seed would give away the key, so it isn't printed when the key is protected. A seed derived
+        // from a mnemonic isn't printed either, since it would get around the BIP39 passphrase.
+        let seed_line = match &entry.derivation {
+            Some(d) if !is_taddr && options.key_passphrase.is_none() => match &d.mnemonic {
+                Some(_) if d.passphrase => format!("Seed: The BIP39 mnemonic on page {} and its passphrase, Path: {}", mnemonic_page, d.path),
+                Some(_)                 => format!("Seed: The BIP39 mnemonic on page {}, Path: {}", mnemonic_page, d.path),
+                None                    => format!("HDSeed: {}, Path: {}", hex::encode(&d.hd_seed), d.path)
+            },
+            _                                                        => String::new()
         };
 
         // Add address + private key
-        add_address_to_page(&current_layer, &font, &font_bold, address, is_taddr, pos);
-        add_pk_to_page(&current_layer, &font, &font_bold, pk, address, is_taddr, seed, hdpath, pos);
+        add_address_to_page(&current_layer, &font, &font_bold, address, is_taddr, page_height, pos)?;
+        add_pk_to_page(&current_layer, &font, &font_bold, &pk, &pk_qr, pk_title, address, is_taddr, &seed_line, pos)?;
  
         let line1 = Line {
             points: vec![(Point::new(Mm(5.0), Mm(98.0)), false), (Point::new(Mm(205.0), Mm(98.0)), false)],
@@ -64,8 +336,9 @@ pub fn save_to_pdf(addresses: &str, filename: &str) -> Result<(), String> {
             is_clipping_path: false,
         };
 
+        // The address section is laid out from the top of the page, and the private key section from the bottom
 	    let line2 = Line {
-            points: vec![(Point::new(Mm(5.0), Mm(198.0)), false), (Point::new(Mm(205.0), Mm(198.0)), false)],
+            points: vec![(Point::new(Mm(5.0), Mm(page_height - 99.0)), false), (Point::new(Mm(205.0), Mm(page_height - 99.0)), false)],
             is_closed: true,
             has_fill: false,
             has_stroke: true,
@@ -78,8 +351,7 @@ pub fn save_to_pdf(addresses: &str, filename: &str) -> Result<(), String> {
         current_layer.set_outline_thickness(2.0);
 
         // Set title
-        current_layer.use_text("Speak and Transact Freely", 32f64, Mm(19.0), Mm(277.0), &font_bold);
-        current_layer.use_text("Private Cryptocurrency and Messenger on Zero Knowledge Proof Encryption", 13f64, Mm(7.0), Mm(266.0), &font_bold);
+        add_title_to_page(&current_layer, &font_bold, page_height);
 
         // Draw lines
         current_layer.add_shape(line1);
@@ -87,36 +359,233 @@ pub fn save_to_pdf(addresses: &str, filename: &str) -> Result<(), String> {
 
         // Add footer of page, only once for each pair of addresses
         if pos == 0 {
-            add_footer_to_page(&current_layer, &font, &format!("Page {} of {}", current_page, total_pages));
+            let footer = match part {
+                Some(p) => format!("Page {} of {}, file {} of {}", p.first_page + i, p.total_pages, p.number, p.parts),
+                None    => format!("Page {} of {}", current_page, total_pages)
+            };
+            add_footer_to_page(&current_layer, &font, &footer);
         }
 
         // Add to the position to move to the next set, but remember to add a new page every 2 wallets
         // We'll add a new page at the start of the loop, so we add it to the PDF only if required.
         pos = pos + 1;        
     };
-    
-    let file = match File::create(filename) {
-        Ok(f)  => f,
-        Err(e) => {            
-            return Err(format!("Couldn't open {} for writing. Aborting. {}", filename, e));
+
+    if let Some((words, passphrase)) = mnemonic {
+        if options.pages.as_ref().map(|p| p.contains(&mnemonic_page)).unwrap_or(true) {
+            if pos >= 1 {
+                let (page, _) = doc.add_page(Mm(page_width), Mm(page_height), "Mnemonic, Layer 1");
+                current_layer = doc.get_page(page).add_layer("Layer 3");
+            }
+
+            let zcount = wallet.entries.iter().filter(|e| e.kind != AddressKind::Transparent).count();
+            let tcount = wallet.entries.len() - zcount;
+
+            let mut info = vec!["These words are the HD seed of the wallet.".to_string()];
+            let mut recover = format!("Recover with: SilentDragonPaper restore -z {} -t {}", zcount, tcount);
+            if passphrase {
+                info.push("A BIP39 passphrase is needed too. It is not printed here.".to_string());
+                recover.push_str(" --seed-passphrase");
+            } else {
+                info.push("Anyone who has them can spend.".to_string());
+            }
+            info.push(recover);
+            info.extend(path_args(wallet));
+            info.push("with --address set to the wallet's first address".to_string());
+            info.extend(split_to_max(&wallet.entries[0].address, 78, 78));
+
+            add_heading_to_page(&current_layer, &font, &font_bold, "BIP39 Mnemonic", "", &info, page_height);
+            add_words_to_page(&current_layer, &font, &words.split_whitespace().collect::<Vec<_>>(), page_height);
+            add_footer_to_page(&current_layer, &font, &format!("Page {} of {}", mnemonic_page, total_pages));
         }
+    }
+    
+    let mut writer = BufWriter::new(Vec::new());
+    doc.save(&mut writer).map_err(|e| Error::Pdf(e.to_string()))?;
+    return writer.into_inner().map_err(|e| Error::Pdf(e.to_string()));
+}
+
+
+/**
+ * Save a wallet whose HD seed was split into shares. The addresses are printed without their private keys,
+ * followed by every share on a page of its own.
+ */
+pub fn save_shares_to_pdf(wallet: &WalletBatch, shares: &SeedShares, filename: &str, options: &PdfOptions, overwrite: bool) -> Result<()> {
+    let contents = render_shares_pdf(wallet, shares, options)?;
+    return write_secret_file(filename, &contents, overwrite);
+}
+
+/**
+ * Render the address pages and the share pages of a wallet whose HD seed was split into shares
+ */
+pub fn render_shares_pdf(wallet: &WalletBatch, shares: &SeedShares, options: &PdfOptions) -> Result<Vec<u8>> {
+    if options.pages.is_some() || options.key_passphrase.is_some() {
+        return Err(Error::Pdf("Can't select pages or protect the keys of a wallet that is split into shares".to_string()));
+    }
+
+    let (page_width, page_height) = options.paper_size.dimensions();
+    let (doc, page1, layer1) = PdfDocument::new("SilentDragonPaper Wallet Shares", Mm(page_width), Mm(page_height), "Layer 1");
+
+    let font  = doc.add_builtin_font(BuiltinFont::Courier).map_err(|e| Error::Pdf(e.to_string()))?;
+    let font_bold = doc.add_builtin_font(BuiltinFont::CourierBold).map_err(|e| Error::Pdf(e.to_string()))?;
+
+    let total_pages = wallet.entries.len() + shares.count();
+
+    // The counts the combine command needs to derive the same addresses again
+    let zcount = wallet.entries.iter().filter(|e| e.kind != AddressKind::Transparent).count();
+    let tcount = wallet.entries.len() - zcount;
+    let first_address = wallet.entries.first().map(|e| e.address.as_str()).unwrap_or("");
+
+    let recover_info = || {
+        let mut info = vec![format!("Recover with: SilentDragonPaper combine -z {} -t {}", zcount, tcount)];
+        info.extend(path_args(wallet));
+        info.push("The wallet's first address is".to_string());
+        info.extend(split_to_max(first_address, 78, 78));
+        info
     };
 
-    match doc.save(&mut BufWriter::new(file)) {
-        Ok(_)   => (),
-        Err(e)  => {
-            return Err(format!("Couldn't save {}. Aborting. {}", filename, e));
+    let mut layers = vec![doc.get_page(page1).get_layer(layer1)];
+    for _ in 1..total_pages {
+        let (page, layer) = doc.add_page(Mm(page_width), Mm(page_height), "Layer 1");
+        layers.push(doc.get_page(page).get_layer(layer));
+    }
+
+    // First the addresses, so funds can be sent to them without the shares
+    for (i, entry) in wallet.entries.iter().enumerate() {
+        let current_layer = &layers[i];
+        let is_taddr = entry.kind == AddressKind::Transparent;
+
+        add_title_to_page(current_layer, &font_bold, page_height);
+        add_address_to_page(current_layer, &font, &font_bold, &entry.address, is_taddr, page_height, 0)?;
+        add_line_to_page(current_layer, page_height - 99.0);
+
+        current_layer.use_text(format!("The private key of this address is split into {} shares.", shares.scheme()), 11f64, Mm(10.0), Mm(page_height - 112.0), &font);
+        current_layer.use_text("Combine enough of the shares to spend from it.", 11f64, Mm(10.0), Mm(page_height - 118.0), &font);
+
+        add_footer_to_page(current_layer, &font, &format!("Page {} of {}", i + 1, total_pages));
+    }
+
+    // Then every share on a page of its own, so the pages can be given to different people
+    let share_layers = &layers[wallet.entries.len()..];
+    match shares {
+        SeedShares::Slip39(groups)  => {
+            for (share, current_layer) in groups.iter().flatten().zip(share_layers) {
+                let group_size = groups[share.group_index as usize].len();
+
+                let mut info = vec![];
+                if share.group_count > 1 {
+                    info.push(format!("Group {} of {}. {} of the {} groups are needed to recover the wallet.", share.group_index + 1, share.group_count, share.group_threshold, share.group_count));
+                    info.push(format!("Any {} of the {} shares of this group complete the group.", share.member_threshold, group_size));
+                } else {
+                    info.push(format!("Any {} of the {} shares recover the wallet.", share.member_threshold, group_size));
+                }
+                info.extend(recover_info());
+
+                let title = format!("SLIP-39 Share {} of {}", share.member_index + 1, group_size);
+                add_heading_to_page(current_layer, &font, &font_bold, &title, &format!("Set {:05}", share.identifier), &info, page_height);
+
+                add_words_to_page(current_layer, &font, &share.words(), page_height);
+            }
+        },
+        SeedShares::Codex32(shares) => {
+            for (share, current_layer) in shares.iter().zip(share_layers) {
+                let (title, mut info) = if share.threshold == 0 {
+                    ("codex32 Seed".to_string(), vec!["This is the HD seed of the wallet. Anyone who has it can spend.".to_string()])
+                } else {
+                    (format!("codex32 Share {}", share.index.to_ascii_uppercase()), vec![format!("Any {} of the {} shares recover the wallet.", share.threshold, shares.len())])
+                };
+                info.extend(recover_info());
+
+                add_heading_to_page(current_layer, &font, &font_bold, &title, &format!("Set {}", share.identifier.to_uppercase()), &info, page_height);
+
+                // In upper case and in blocks of 4 characters, which are easier to copy and check by hand
+                let blocks = split_to_max(&share.to_string().to_uppercase(), 24, 4);
+                for (j, line) in blocks.iter().enumerate() {
+                    current_layer.use_text(line.clone(), 18f64, Mm(10.0), Mm(page_height - 112.0 - 11.0 * j as f64), &font);
+                }
+            }
         }
+    }
+
+    for (i, current_layer) in share_layers.iter().enumerate() {
+        add_footer_to_page(current_layer, &font, &format!("Page {} of {}", wallet.entries.len() + i + 1, total_pages));
+    }
+
+    let mut writer = BufWriter::new(Vec::new());
+    doc.save(&mut writer).map_err(|e| Error::Pdf(e.to_string()))?;
+    return writer.into_inner().map_err(|e| Error::Pdf(e.to_string()));
+}
+
+/**
+ * A line with the options that derive the z-addresses of the wallet at the same path again, unless they are at
+ * the default path
+ */
+fn path_args(wallet: &WalletBatch) -> Option<String> {
+    let path = wallet.entries.iter()
+                     .filter_map(|e| e.derivation.as_ref())
+                     .next()
+                     .and_then(|d| DerivationPath::of_address(&d.path).ok())?;
+
+    let mut args = vec![];
+    if path.base != DerivationPath::default().base {
+        args.push(format!("--path \"{}\"", path));
+    }
+    if path.start != 0 {
+        args.push(format!("--start-index {}", path.start));
+    }
+
+    return if args.is_empty() { None } else { Some(format!("and {}", args.join(" "))) };
+}
+
+/**
+ * Add the title, a heading with a note on its right (like the set of a share), and lines of information to a page
+ */
+fn add_heading_to_page(current_layer: &PdfLayerReference, font: &IndirectFontRef, font_bold: &IndirectFontRef, heading: &str, note: &str, info: &[String], page_height: f64) {
+    add_title_to_page(current_layer, font_bold, page_height);
+    add_line_to_page(current_layer, page_height - 38.0);
+
+    current_layer.use_text(heading, 18f64, Mm(10.0), Mm(page_height - 50.0), font_bold);
+    current_layer.use_text(note, 12f64, Mm(165.0), Mm(page_height - 50.0), font);
+
+    for (j, line) in info.iter().enumerate() {
+        current_layer.use_text(line.clone(), 10f64, Mm(10.0), Mm(page_height - 60.0 - (j as f64) * 6.0), font);
+    }
+}
+
+/**
+ * Add the words of a mnemonic in 3 columns, numbered so they can't be mixed up
+ */
+fn add_words_to_page(current_layer: &PdfLayerReference, font: &IndirectFontRef, words: &[&str], page_height: f64) {
+    let rows = (words.len() + 2) / 3;
+    for (j, word) in words.iter().enumerate() {
+        let x = 10.0 + 65.0 * (j / rows) as f64;
+        let y = page_height - 110.0 - 9.0 * (j % rows) as f64;
+        current_layer.use_text(format!("{:>2}. {}", j + 1, word), 14f64, Mm(x), Mm(y), font);
+    }
+}
+
+/**
+ * Draw a horizontal line across the page at `y`
+ */
+fn add_line_to_page(current_layer: &PdfLayerReference, y: f64) {
+    let line = Line {
+        points: vec![(Point::new(Mm(5.0), Mm(y)), false), (Point::new(Mm(205.0), Mm(y)), false)],
+        is_closed: true,
+        has_fill: false,
+        has_stroke: true,
+        is_clipping_path: false,
     };
 
-    return Ok(());
+    current_layer.set_outline_color(printpdf::Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+    current_layer.set_outline_thickness(2.0);
+    current_layer.add_shape(line);
 }
 
 /**
  * Generate a qrcode. The outout is a vector of RGB values of size (qrcode_modules * scalefactor) + padding
  */
-fn qrcode_scaled(data: &str, scalefactor: usize) -> (Vec<u8>, usize) {
-    let code = QrCode::new(data.as_bytes()).unwrap();
+fn qrcode_scaled(data: &str, scalefactor: usize) -> Result<(Vec<u8>, usize)> {
+    let code = QrCode::new(data.as_bytes()).map_err(|e| Error::QrCode(e.to_string()))?;
     let output_size = code.width();
 
     let imgdata = code.to_colors();
@@ -137,7 +606,15 @@ fn qrcode_scaled(data: &str, scalefactor: usize) -> (Vec<u8>, usize) {
         }
     }).collect();
 
-    return (scaledimg, finalsize);
+    return Ok((scaledimg, finalsize));
+}
+
+/**
+ * Add the title at the top of the page
+ */
+fn add_title_to_page(current_layer: &PdfLayerReference, font_bold: &IndirectFontRef, page_height: f64) {
+    current_layer.use_text("Speak and Transact Freely", 32f64, Mm(19.0), Mm(page_height - 20.0), &font_bold);
+    current_layer.use_text("Private Cryptocurrency and Messenger on Zero Knowledge Proof Encryption", 13f64, Mm(7.0), Mm(page_height - 31.0), &font_bold);
 }
 
 /**
@@ -151,14 +628,15 @@ fn add_footer_to_page(current_layer: &PdfLayerReference, font: &IndirectFontRef,
 /**
  * Add the address section to the PDF at `pos`. Note that each page can fit only 2 wallets, so pos has to effectively be either 0 or 1.
  */
-fn add_address_to_page(current_layer: &PdfLayerReference, font: &IndirectFontRef, font_bold: &IndirectFontRef, address: &str, is_taddr: bool, pos: u32) {
-    let (scaledimg, finalsize) = qrcode_scaled(address, if is_taddr {13} else {10});
+fn add_address_to_page(current_layer: &PdfLayerReference, font: &IndirectFontRef, font_bold: &IndirectFontRef, address: &str, is_taddr: bool, page_height: f64, pos: u32) -> Result<()> {
+    let (scaledimg, finalsize) = qrcode_scaled(address, if is_taddr {13} else {10})?;
 
     //         page_height  top_margin  vertical_padding  position               
-    let ypos = 297.0        - 5.0       - 77.0            - (140.0 * pos as f64);
+    let ypos = page_height  - 5.0       - 77.0            - (140.0 * pos as f64);
     let title = if is_taddr {"HUSH t-address"} else {"HUSH z-address"};
 
     add_address_at(current_layer, font, font_bold, title, address, &scaledimg, finalsize, ypos);
+    return Ok(());
 }
 
 fn add_address_at(current_layer: &PdfLayerReference, font: &IndirectFontRef, font_bold: &IndirectFontRef, title: &str, address: &str, qrcode: &Vec<u8>, finalsize: usize, ypos: f64) {
@@ -172,17 +650,19 @@ fn add_address_at(current_layer: &PdfLayerReference, font: &IndirectFontRef, fon
 }
 
 /**
- * Add the private key section to the PDF at `pos`, which can effectively be only 0 or 1.
+ * Add the private key section to the PDF at `pos`, which can effectively be only 0 or 1. This section is at a
+ * fixed distance from the bottom of the page (the A4 height is only used to compute that distance). `pk_qr` is
+ * what goes in the QR code, which can be `pk` in another case.
  */
-fn add_pk_to_page(current_layer: &PdfLayerReference, font: &IndirectFontRef, font_bold: &IndirectFontRef, pk: &str, address: &str, is_taddr: bool, seed: &str, path: &str, pos: u32) {
+fn add_pk_to_page(current_layer: &PdfLayerReference, font: &IndirectFontRef, font_bold: &IndirectFontRef, pk: &str, pk_qr: &str, pk_title: &str, address: &str, is_taddr: bool, seed_line: &str, pos: u32) -> Result<()> {
     //         page_height  top_margin  vertical_padding  position               
     let ypos = 297.0        - 5.0       - 242.0           - (140.0 * pos as f64);
     
-    let (scaledimg, finalsize) = qrcode_scaled(pk, if is_taddr {20} else {10});
+    let (scaledimg, finalsize) = qrcode_scaled(pk_qr, if is_taddr {20} else {10})?;
 
     add_qrcode_image_to_page(current_layer, &scaledimg, finalsize, Mm(145.0), Mm(ypos-17.5));
 
-    current_layer.use_text("Private Key", 14f64, Mm(10.0), Mm(ypos+37.5), &font_bold);
+    current_layer.use_text(pk_title, 14f64, Mm(10.0), Mm(ypos+37.5), &font_bold);
     let strs = split_to_max(&pk, 45, 45);   // No spaces, so user can copy the private key
     for i in 0..strs.len() {
         current_layer.use_text(strs[i].clone(), 12f64, Mm(10.0), Mm(ypos+32.5-((i*5) as f64)), &font);
@@ -196,10 +676,15 @@ fn add_pk_to_page(current_layer: &PdfLayerReference, font: &IndirectFontRef, fon
         current_layer.use_text(strs[i].clone(), 12f64, Mm(10.0), Mm(ypos-15.0-((i*5) as f64)), &font);
     }
 
-    // And add the seed too. 
-    if !seed.is_empty() {
-        current_layer.use_text(format!("HDSeed: {}, Path: {}", seed, path).as_str(), 8f64, Mm(10.0), Mm(ypos-35.0), &font);
+    // And add the seed too. A 32 byte seed fits on one line, but a longer one is wrapped into lines that
+    // start right below the address, so they stay clear of the footer.
+    let strs: Vec<String> = split_to_max(seed_line, 110, 110).into_iter().filter(|l| !l.is_empty()).collect();
+    let top = if strs.len() > 1 {ypos-26.0} else {ypos-35.0};
+    for i in 0..strs.len() {
+        current_layer.use_text(strs[i].clone(), 8f64, Mm(10.0), Mm(top-3.2*(i as f64)), &font);
     }
+
+    return Ok(());
 }
 
 /**
@@ -259,3 +744,225 @@ fn split_to_max(s: &str, max: usize, blocksize: usize) -> Vec<String> {
     // Add spaces
     return ans;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::encode_privatekey;
+    use array2d::Array2D;
+    use rand::{ChaChaRng, Rng, SeedableRng};
+    use std::path::Path;
+    use zcash_primitives::zip32::ExtendedSpendingKey;
+
+    const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+    /// A random string of base58 characters, up to `max_len` long
+    fn random_string(rng: &mut ChaChaRng, max_len: usize) -> String {
+        let len = rng.gen_range(0, max_len + 1);
+        (0..len).map(|_| BASE58[rng.gen_range(0, BASE58.len())] as char).collect()
+    }
+
+    #[test]
+    fn test_split_to_max() {
+        let lines = split_to_max("abcdefghij", 4, 2);
+        assert_eq!(lines, vec!["ab cd", "ef gh", "ij"]);
+
+        let lines = split_to_max("secret-extended-key-main1qq", 10, 5);
+        assert_eq!(lines, vec!["secre t-ext", "ended -key-", "main1 qq"]);
+    }
+
+    #[test]
+    fn test_split_to_max_properties() {
+        let mut rng = ChaChaRng::from_seed([1; 32]);
+
+        for _ in 0..2000 {
+            let s         = random_string(&mut rng, 300);
+            let max       = rng.gen_range(1, 80);
+            let blocksize = rng.gen_range(1, max + 1);
+
+            let lines = split_to_max(&s, max, blocksize);
+
+            // Nothing is lost or added, apart from the spaces
+            assert_eq!(lines.concat().replace(" ", ""), s);
+            assert_eq!(lines.len(), s.len() / max + 1);
+
+            for line in &lines {
+                assert!(line.replace(" ", "").len() <= max);
+
+                // Every block, except maybe the last one, is exactly blocksize long
+                let blocks: Vec<&str> = line.split(' ').collect();
+                for (i, b) in blocks.iter().enumerate() {
+                    assert!(b.len() <= blocksize);
+                    if i + 1 < blocks.len() {
+                        assert_eq!(b.len(), blocksize);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_qrcode_scale() {
+        let mut rng = ChaChaRng::from_seed([2; 32]);
+        let padding = 10;
+
+        for scale in 1..13 {
+            let data = random_string(&mut rng, 300);
+            let code = QrCode::new(data.as_bytes()).unwrap();
+            let colors = code.to_colors();
+            let width = code.width();
+
+            let (scaled, size) = qrcode_scaled(&data, scale).unwrap();
+            assert_eq!(size, width * scale + 2 * padding);
+            assert_eq!(scaled.len(), size * size * 3);
+
+            // Look at the image as rows of RGB pixels
+            let pixels: Vec<u8> = scaled.chunks(3).map(|rgb| {
+                assert!(rgb[0] == rgb[1] && rgb[1] == rgb[2]);
+                assert!(rgb[0] == 0 || rgb[0] == 255);
+                rgb[0]
+            }).collect();
+            let img = Array2D::from_row_major(&pixels, size, size);
+
+            for x in 0..size {
+                for y in 0..size {
+                    let expected = if x < padding || y < padding || x >= size - padding || y >= size - padding {
+                        // The border is white
+                        255
+                    } else if colors[(x - padding) / scale * width + (y - padding) / scale] == Color::Dark {
+                        0
+                    } else {
+                        255
+                    };
+                    assert_eq!(img[(x, y)], expected, "Wrong pixel at ({}, {}) with scale {}", x, y, scale);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_qrcode_too_long() {
+        // More than the largest QR code can hold
+        let data = "x".repeat(8000);
+        match qrcode_scaled(&data, 1) {
+            Err(Error::QrCode(_)) => (),
+            _                     => panic!("Expected a QR code error")
+        }
+    }
+
+    #[test]
+    fn test_encrypted_key_qrcode() {
+        let pk = encode_privatekey(&ExtendedSpendingKey::master(&[3; 32])).unwrap();
+        let encrypted = encrypt_spending_key(&pk, "passphrase", &params(), &KdfParams { log_n: 10, r: 8, p: 1 }).unwrap();
+
+        // In upper case, the longer encrypted key needs no larger a QR code than the plain key
+        let (_, plain_size) = qrcode_scaled(&pk, 10).unwrap();
+        let (_, encrypted_size) = qrcode_scaled(&encrypted.to_ascii_uppercase(), 10).unwrap();
+        assert!(encrypted_size <= plain_size, "{} > {}", encrypted_size, plain_size);
+    }
+
+    #[test]
+    fn test_protected_keys_use_kdf_options() {
+        let seed: Vec<u8> = (0..32).collect();
+        let entries = crate::bulk::stream_wallet(&seed, 1, 0, &DerivationPath::default(), 1).unwrap()
+                        .collect::<Result<Vec<WalletEntry>>>().unwrap();
+        let wallet  = WalletBatch { entries: entries.clone(), metadata: None };
+        let options = PdfOptions { key_passphrase: Some("passphrase".to_string()), key_kdf: KdfParams { log_n: 10, r: 8, p: 1 }, ..PdfOptions::default() };
+
+        // The printed key is split into blocks and lines, so join them back together
+        let text: String = pdf_text(&render_pdf(&wallet, &options).unwrap()).split_whitespace().collect();
+        let prefix = format!("{}1", crate::encrypted_key::encrypted_key_prefix(&params()));
+        let start = text.find(&prefix).unwrap();
+        let encrypted: String = text[start..].chars().take_while(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-').collect();
+
+        assert_eq!(crate::encrypted_key::decrypt_spending_key(&encrypted, "passphrase", &params()).unwrap(), entries[0].keys.private_key);
+
+        // log2 N is the second byte of the header
+        let (_, data) = crate::encoding::decode_bech32(&encrypted).unwrap();
+        assert_eq!(data[1], 10);
+    }
+
+    #[test]
+    fn test_pdf_part_names() {
+        assert_eq!(PdfPartsWriter::part_name("wallet.pdf", 1), "wallet-0001.pdf");
+        assert_eq!(PdfPartsWriter::part_name("dir/batch.PDF", 12), "dir/batch-0012.pdf");
+        assert_eq!(PdfPartsWriter::part_name("batch", 10000), "batch-10000.pdf");
+
+        assert!(PdfPartsWriter::new("wallet.pdf", 0, 10, &PdfOptions::default(), false).is_err());
+        let pages = PdfOptions { pages: Some(vec![1]), ..PdfOptions::default() };
+        assert!(PdfPartsWriter::new("wallet.pdf", 10, 10, &pages, false).is_err());
+    }
+
+    /// The text on all the pages of a PDF
+    fn pdf_text(pdf: &[u8]) -> String {
+        let doc = lopdf::Document::load_mem(pdf).unwrap();
+        let pages: Vec<u32> = doc.get_pages().keys().cloned().collect();
+        doc.extract_text(&pages).unwrap()
+    }
+
+    #[test]
+    fn test_pdf_parts_leave_out_seed() {
+        let seed: Vec<u8> = (0..32).collect();
+        let entries = crate::bulk::stream_wallet(&seed, 3, 1, &DerivationPath::default(), 2).unwrap()
+                        .collect::<Result<Vec<WalletEntry>>>().unwrap();
+
+        // A wallet of its own prints the seed
+        let wallet = WalletBatch { entries: entries.clone(), metadata: None };
+        assert!(pdf_text(&render_pdf(&wallet, &PdfOptions::default()).unwrap()).contains(&hex::encode(&seed)));
+
+        let filename = std::env::temp_dir().join(format!("sdp-parts-{:016x}.pdf", rand::random::<u64>())).to_string_lossy().to_string();
+        let mut writer = PdfPartsWriter::new(&filename, 3, 4, &PdfOptions::default(), false).unwrap();
+        crate::export::write_entries(entries.into_iter().map(Ok), &mut writer).unwrap();
+        assert_eq!(writer.parts(), 2);
+        drop(writer);
+
+        for part in 1..3 {
+            let name = PdfPartsWriter::part_name(&filename, part);
+            let text = pdf_text(&fs::read(&name).unwrap());
+            fs::remove_file(&name).unwrap();
+
+            assert!(text.contains("zs1") || text.contains("HUSH t-address"));
+            assert!(!text.contains(&hex::encode(&seed)));
+            assert!(!text.contains("HDSeed") && !text.contains("Path:"));
+        }
+    }
+
+    #[test]
+    fn test_pdf_parts() {
+        let entries = crate::bulk::stream_wallet(&[5; 32], 1, 4, &DerivationPath::default(), 1).unwrap()
+                        .collect::<Result<Vec<WalletEntry>>>().unwrap();
+        let filename = std::env::temp_dir().join(format!("sdp-parts-{:016x}.pdf", rand::random::<u64>())).to_string_lossy().to_string();
+        let names: Vec<String> = (1..4).map(|p| PdfPartsWriter::part_name(&filename, p)).collect();
+
+        // The pages are numbered through the whole batch
+        let mut writer = PdfPartsWriter::new(&filename, 2, 5, &PdfOptions::default(), false).unwrap();
+        crate::export::write_entries(entries.clone().into_iter().map(Ok), &mut writer).unwrap();
+        drop(writer);
+
+        let text = pdf_text(&fs::read(&names[1]).unwrap());
+        assert!(text.contains("Page 3 of 5, file 2 of 3") && text.contains("Page 4 of 5, file 2 of 3"));
+        assert!(pdf_text(&fs::read(&names[2]).unwrap()).contains("Page 5 of 5, file 3 of 3"));
+
+        // A part that can't be written, because it's already there, fails the batch, and the parts before it
+        // are removed
+        fs::remove_file(&names[0]).unwrap();
+        fs::remove_file(&names[2]).unwrap();
+        let mut writer = PdfPartsWriter::new(&filename, 2, 5, &PdfOptions::default(), false).unwrap();
+        match crate::export::write_entries(entries.clone().into_iter().map(Ok), &mut writer) {
+            Err(Error::AlreadyExists(name)) => assert_eq!(name, names[1]),
+            r                               => panic!("Expected AlreadyExists, got {:?}", r)
+        };
+        assert_eq!(writer.abort(), 1);
+        assert!(!Path::new(&names[0]).exists());
+        assert!(Path::new(&names[1]).exists());
+        fs::remove_file(&names[1]).unwrap();
+
+        // So does a stream that fails, and a writer that is dropped before it finishes
+        let failing = entries.clone().into_iter().map(Ok).take(3).chain(std::iter::once(Err(Error::Thread("Failed".to_string()))));
+        let mut writer = PdfPartsWriter::new(&filename, 2, 5, &PdfOptions::default(), true).unwrap();
+        assert!(crate::export::write_entries(failing, &mut writer).is_err());
+        assert_eq!(writer.parts(), 1);
+        drop(writer);
+        assert!(!Path::new(&names[0]).exists() && !Path::new(&names[1]).exists());
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+
+use crate::encoding::{bech32_hrp_expand, bech32_polymod, bech32_polymod_step, BASE58_ALPHABET, BECH32_CHARSET};
+use crate::paper::CoinParams;
+use crate::verify::{decode, Decoded};
+
+/// At most this many corrections are returned
+const MAX_CORRECTIONS: usize = 10;
+
+/// Longest base58check string we try to repair
+const MAX_BASE58_LENGTH: usize = 60;
+
+/// A possible correction of a mistyped key or address
+#[derive(Clone, Debug, PartialEq)]
+pub struct Correction {
+    /// The corrected key or address, which decodes correctly
+    pub corrected : String,
+    /// The (0 based) character positions that were changed
+    pub positions : Vec<usize>,
+    pub decoded   : Decoded,
+}
+
+/// Suggest corrections for a mistyped key or address, most likely first.
+///
+/// For bech32 strings (z-addresses, spending and viewing keys) the checksum is used to locate up to two
+/// substituted characters. A mistyped prefix is matched against the prefixes in `coin`. For base58check strings
+/// (t-addresses and WIF keys) every single character substitution and every swap of two adjacent characters is
+/// tried. Characters that were left out or typed twice can't be found.
+///
+/// Every correction decodes as a valid key or address for `coin`. If `s` is already valid, the only "correction"
+/// is `s` itself, with no changed positions. If nothing is found, the list is empty.
+pub fn repair(s: &str, coin: &CoinParams) -> Vec<Correction> {
+    let s = s.trim();
+
+    if let Ok(decoded) = decode(s, coin) {
+        return vec![Correction { corrected: s.to_string(), positions: vec![], decoded }];
+    }
+
+    let mut corrections = repair_bech32(s, coin);
+    corrections.extend(repair_base58(s, coin));
+
+    // Fewest changes first
+    corrections.sort_by(|a, b| a.positions.len().cmp(&b.positions.len()).then(a.positions.cmp(&b.positions)));
+    corrections.dedup_by(|a, b| a.corrected == b.corrected);
+    corrections.truncate(MAX_CORRECTIONS);
+
+    return corrections;
+}
+
+/// A line with a '^' under each of the `positions` of a string, to print below it
+pub fn highlight(positions: &[usize]) -> String {
+    let len = positions.iter().max().map(|m| m + 1).unwrap_or(0);
+    (0..len).map(|i| if positions.contains(&i) { '^' } else { ' ' }).collect()
+}
+
+fn repair_bech32(s: &str, coin: &CoinParams) -> Vec<Correction> {
+    // None of the prefixes have a '1', so any later '1' is a typo in the data part
+    let lower = s.to_ascii_lowercase();
+    let sep = match lower.find('1') {
+        Some(sep) => sep,
+        None      => return vec![]
+    };
+
+    // Pick the prefix of the coin this was meant to be. Allow one mistyped character, or two in the long
+    // spending key prefix.
+    let typed_hrp = &lower[..sep];
+    let hrp = match [&coin.zaddress_prefix, &coin.zsecret_prefix, &coin.zviewkey_prefix].iter()
+            .map(|hrp| (*hrp, differences(typed_hrp, hrp)))
+            .filter(|(hrp, d)| d.len() <= if hrp.len() < 10 { 1 } else { 2 })
+            .min_by_key(|(_, d)| d.len()) {
+        Some((hrp, _)) => hrp.clone(),
+        None           => return vec![]
+    };
+    let hrp_changes = differences(typed_hrp, &hrp);
+
+    // Characters that can't be in bech32 are certainly wrong, so they have to be one of the changed positions
+    let mut values = vec![];
+    let mut invalid = vec![];
+    for (i, c) in lower[sep+1..].chars().enumerate() {
+        match BECH32_CHARSET.iter().position(|b| *b as char == c) {
+            Some(v) => values.push(v as u8),
+            None    => {
+                values.push(0);
+                invalid.push(i);
+            }
+        }
+    }
+    if values.len() < 6 || invalid.len() > 2 {
+        return vec![];
+    }
+
+    let mut checked = bech32_hrp_expand(&hrp);
+    checked.extend_from_slice(&values);
+    let residue = bech32_polymod(&checked) ^ 1;
+
+    let n = values.len();
+    let syndromes = bech32_syndromes(n);
+
+    // Sets of (position, xor value) that would make the checksum valid
+    let mut fixes: Vec<Vec<(usize, u8)>> = vec![];
+    match invalid.len() {
+        0 => {
+            // Only the prefix was mistyped...
+            if residue == 0 {
+                fixes.push(vec![]);
+            }
+
+            // ...or a single substitution...
+            for i in 0..n {
+                for v in 1..32 {
+                    if syndromes[i][v] == residue {
+                        fixes.push(vec![(i, v as u8)]);
+                    }
+                }
+            }
+
+            // ...or two of them
+            if fixes.is_empty() {
+                let mut lookup: HashMap<u32, Vec<(usize, u8)>> = HashMap::new();
+                for i in 0..n {
+                    for v in 1..32 {
+                        lookup.entry(syndromes[i][v]).or_insert_with(Vec::new).push((i, v as u8));
+                    }
+                }
+
+                for i in 0..n {
+                    for v in 1..32 {
+                        if let Some(others) = lookup.get(&(residue ^ syndromes[i][v])) {
+                            for (j, w) in others.iter().filter(|(j, _)| *j > i) {
+                                fixes.push(vec![(i, v as u8), (*j, *w)]);
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        1 => {
+            let i = invalid[0];
+            for v in 0..32 {
+                if syndromes[i][v] == residue {
+                    fixes.push(vec![(i, v as u8)]);
+                }
+                for j in (0..n).filter(|j| *j != i) {
+                    for w in 1..32 {
+                        if syndromes[i][v] ^ syndromes[j][w] == residue {
+                            fixes.push(vec![(i, v as u8), (j, w as u8)]);
+                        }
+                    }
+                }
+            }
+        },
+        _ => {
+            let (i, j) = (invalid[0], invalid[1]);
+            for v in 0..32 {
+                for w in 0..32 {
+                    if syndromes[i][v] ^ syndromes[j][w] == residue {
+                        fixes.push(vec![(i, v as u8), (j, w as u8)]);
+                    }
+                }
+            }
+        }
+    };
+
+    fixes.iter().filter_map(|fix| {
+        let mut fixed = values.clone();
+        for (i, v) in fix {
+            fixed[*i] ^= v;
+        }
+
+        let corrected = format!("{}1{}", hrp, fixed.iter().map(|v| BECH32_CHARSET[*v as usize] as char).collect::<String>());
+
+        let mut positions = hrp_changes.clone();
+        positions.extend(fix.iter().map(|(i, _)| sep + 1 + i));
+        positions.extend(invalid.iter().map(|i| sep + 1 + i));
+        positions.sort();
+        positions.dedup();
+
+        decode(&corrected, coin).ok().map(|decoded| Correction { corrected, positions, decoded })
+    }).collect()
+}
+
+/// The change in the checksum caused by xoring each value into each of the `n` data positions. The checksum is
+/// linear, so this only depends on how far the position is from the end.
+fn bech32_syndromes(n: usize) -> Vec<[u32; 32]> {
+    let mut syndromes = vec![[0u32; 32]; n];
+    let mut current: Vec<u32> = (0..32).collect();
+
+    for i in (0..n).rev() {
+        for v in 0..32 {
+            syndromes[i][v] = current[v];
+            current[v] = bech32_polymod_step(current[v], 0);
+        }
+    }
+
+    return syndromes;
+}
+
+fn repair_base58(s: &str, coin: &CoinParams) -> Vec<Correction> {
+    let chars: Vec<char> = s.chars().collect();
+
+    // t-addresses and WIF keys are about 35 and 52 characters. Anything much longer is a bech32 key, and
+    // brute forcing it as base58 would only waste time.
+    if chars.len() > MAX_BASE58_LENGTH {
+        return vec![];
+    }
+
+    // Characters that can't be in base58 are certainly wrong, so with only one change allowed there can be
+    // at most one of them
+    let invalid: Vec<usize> = (0..chars.len()).filter(|i| !BASE58_ALPHABET.contains(&(chars[*i] as u8)) || !chars[*i].is_ascii()).collect();
+    if invalid.len() > 1 {
+        return vec![];
+    }
+
+    let mut candidates: Vec<(Vec<char>, Vec<usize>)> = vec![];
+    for i in 0..chars.len() {
+        if !invalid.is_empty() && invalid[0] != i {
+            continue;
+        }
+
+        for c in BASE58_ALPHABET.iter().map(|b| *b as char).filter(|c| *c != chars[i]) {
+            let mut fixed = chars.clone();
+            fixed[i] = c;
+            candidates.push((fixed, vec![i]));
+        }
+    }
+
+    if invalid.is_empty() {
+        for i in 1..chars.len() {
+            if chars[i] != chars[i-1] {
+                let mut fixed = chars.clone();
+                fixed.swap(i - 1, i);
+                candidates.push((fixed, vec![i - 1, i]));
+            }
+        }
+    }
+
+    candidates.into_iter().filter_map(|(fixed, positions)| {
+        let corrected: String = fixed.into_iter().collect();
+        match decode(&corrected, coin) {
+            Ok(decoded) => Some(Correction { corrected, positions, decoded }),
+            Err(_)      => None
+        }
+    }).collect()
+}
+
+/// The positions where two strings of the same length differ. Strings of different lengths differ everywhere.
+fn differences(a: &str, b: &str) -> Vec<usize> {
+    if a.len() != b.len() {
+        return (0..a.len().max(b.len())).collect();
+    }
+
+    a.bytes().zip(b.bytes()).enumerate().filter(|(_, (x, y))| x != y).map(|(i, _)| i).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::params;
+    use crate::verify::KeyKind;
+
+    const ZADDRESS: &str = "zs1mrhc9y7jdh5r9ece8u5khgvj9kg0zgkxzdduyv0whkg7lkcrkx5xqem3e48avjq9wn2rukydkwn";
+    const SPENDING_KEY: &str = "secret-extended-key-main1qtden8s8qgqqpqyhec2lfmgmjuumqf32gcauk0wfkw7jxgafh2jyrjjzwaec82x5xk97syfuac6p8fclstzpljx629a7zdqynqewdqjujtdxhp87unrq6dmcqkwu26086rfj8y2h8723h00f9lrtnnmpgaekv8zuyua2dxgve7q3st5kyg7q9r8r6m4509xnzyae2p5aznzhtz8pjwm9alpgzw728mdpn70073k2zt06r0csxud535d55sxy6pdqmrwwpe7uv2c8kdcj46mkv";
+    const TADDRESS: &str = "RKxTdfmtxtfLDKZBgx6SvNkBtNu9jRYnLh";
+    const WIF: &str = "Up1YVLk7uuErCHVQyFCtfinZngmdwfyfc47WCQ8oJxgowjVzNeqs";
+
+    /// Replace the characters at `positions` with other ones from `alphabet`
+    fn mistype(s: &str, positions: &[usize], alphabet: &[u8]) -> String {
+        s.char_indices().map(|(i, c)| {
+            match positions.contains(&i) {
+                true  => alphabet.iter().map(|b| *b as char).find(|b| *b != c).unwrap(),
+                false => c
+            }
+        }).collect()
+    }
+
+    fn finds(typed: &str, expected: &str, positions: Vec<usize>) {
+        let corrections = repair(typed, &params());
+        assert!(corrections.iter().any(|c| c.corrected == expected && c.positions == positions),
+                "{} wasn't corrected to {} at {:?}: {:?}", typed, expected, positions, corrections);
+    }
+
+    #[test]
+    fn test_valid() {
+        for s in &[ZADDRESS, SPENDING_KEY, TADDRESS, WIF] {
+            let corrections = repair(&format!("  {}\n", s), &params());
+            assert_eq!(corrections.len(), 1);
+            assert_eq!((corrections[0].corrected.as_str(), corrections[0].positions.len()), (*s, 0));
+        }
+    }
+
+    #[test]
+    fn test_bech32() {
+        for s in &[ZADDRESS, SPENDING_KEY] {
+            let data = s.find('1').unwrap() + 1;
+            let (a, b) = (data + 7, s.len() - 3);
+
+            // The only single substitution that fixes the checksum is the one that was made
+            let corrections = repair(&mistype(s, &[a], BECH32_CHARSET), &params());
+            assert_eq!(corrections[0].corrected, *s);
+            assert_eq!(corrections[0].positions, vec![a]);
+
+            finds(&mistype(s, &[a, b], BECH32_CHARSET), s, vec![a, b]);
+
+            // Characters that can't be in bech32, alone or with another substitution
+            finds(&mistype(s, &[a], b"bio"), s, vec![a]);
+            finds(&mistype(&mistype(s, &[a], b"b"), &[b], BECH32_CHARSET), s, vec![a, b]);
+        }
+
+        // A mistyped prefix
+        let corrections = repair(&ZADDRESS.replacen("zs", "zx", 1), &params());
+        assert_eq!((corrections[0].corrected.as_str(), &corrections[0].positions), (ZADDRESS, &vec![1]));
+        assert_eq!(corrections[0].decoded.kind, KeyKind::SaplingAddress);
+
+        finds(&SPENDING_KEY.replacen("secret", "secert", 1), SPENDING_KEY, vec![3, 4]);
+    }
+
+    #[test]
+    fn test_base58() {
+        for (s, kind) in &[(TADDRESS, KeyKind::TransparentAddress), (WIF, KeyKind::Wif)] {
+            let corrections = repair(&mistype(s, &[10], BASE58_ALPHABET), &params());
+            assert!(corrections.iter().any(|c| c.corrected == *s && c.positions == vec![10] && c.decoded.kind == *kind));
+            assert!(corrections.len() <= MAX_CORRECTIONS);
+
+            // Two adjacent characters swapped
+            let mut swapped: Vec<char> = s.chars().collect();
+            swapped.swap(20, 21);
+            assert_ne!(swapped[20], swapped[21]);
+            finds(&swapped.into_iter().collect::<String>(), s, vec![20, 21]);
+
+            // A character that can't be in base58
+            finds(&mistype(s, &[10], b"0"), s, vec![10]);
+        }
+    }
+
+    #[test]
+    fn test_unrepairable() {
+        assert!(repair("", &params()).is_empty());
+        assert!(repair("not a key", &params()).is_empty());
+        assert!(repair(&mistype(TADDRESS, &[3, 10], b"0O"), &params()).is_empty());
+        assert!(repair(&mistype(ZADDRESS, &[5, 10, 15], b"bio"), &params()).is_empty());
+        assert!(repair(&ZADDRESS.replacen("zs", "ab", 1), &params()).is_empty());
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(highlight(&[]), "");
+        assert_eq!(highlight(&[0]), "^");
+        assert_eq!(highlight(&[5, 2]), "  ^  ^");
+    }
+}
@@ -0,0 +1,248 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+/// Write `contents` to `filename`, making sure the file is only ever readable by the current user.
+///
+/// The contents are first written to a temporary file in the same directory (created with mode 0600),
+/// fsynced, and then moved into place, so a crash or a full disk never leaves a half written key file behind.
+/// Unless `overwrite` is set, an existing file is never replaced.
+pub fn write_secret_file(filename: &str, contents: &[u8], overwrite: bool) -> Result<()> {
+    let mut file = SecretFile::create(filename, overwrite)?;
+    file.write_all(contents).map_err(|e| Error::Io(filename.to_string(), e))?;
+    return file.commit();
+}
+
+/// A secret file that is written a piece at a time, for outputs too large to build in memory first. Like
+/// `write_secret_file`, it goes to a temp file that only the current user can read, and `commit` moves it into
+/// place. If it is dropped without being committed, the temp file is removed and `filename` is left alone.
+pub struct SecretFile {
+    filename  : String,
+    dir       : PathBuf,
+    tmp_path  : PathBuf,
+    file      : Option<File>,
+    overwrite : bool,
+}
+
+impl SecretFile {
+    pub fn create(filename: &str, overwrite: bool) -> Result<SecretFile> {
+        let path = Path::new(filename);
+
+        if !overwrite && path.exists() {
+            return Err(Error::AlreadyExists(filename.to_string()));
+        }
+
+        let dir = match path.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+            _                                     => PathBuf::from(".")
+        };
+
+        let (tmp_path, file) = create_temp_file(&dir, path).map_err(|e| Error::Io(filename.to_string(), e))?;
+
+        return Ok(SecretFile { filename: filename.to_string(), dir, tmp_path, file: Some(file), overwrite });
+    }
+
+    /// fsync everything written so far, and move the file into place
+    pub fn commit(mut self) -> Result<()> {
+        let filename = self.filename.clone();
+        let io_err = |e: io::Error| Error::Io(filename.clone(), e);
+
+        if let Some(file) = self.file.take() {
+            file.sync_all().map_err(io_err)?;
+        }
+        move_into_place(&self.tmp_path, Path::new(&filename), &filename, self.overwrite)?;
+
+        // The temp file is either renamed already, or a hard link that has to be cleaned up
+        fs::remove_file(&self.tmp_path).ok();
+
+        return sync_dir(&self.dir).map_err(io_err);
+    }
+}
+
+impl Write for SecretFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.file.as_mut() {
+            Some(f) => f.write(buf),
+            None    => Err(io::Error::new(io::ErrorKind::Other, "The file is already committed"))
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(f) => f.flush(),
+            None    => Ok(())
+        }
+    }
+}
+
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.tmp_path).ok();
+    }
+}
+
+/// Create a new, empty temp file next to `path` that only the current user can read and write
+fn create_temp_file(dir: &Path, path: &Path) -> io::Result<(PathBuf, File)> {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+    let mut tries = 0;
+    loop {
+        let tmp_path = dir.join(format!(".{}.{:08x}.tmp", name, rand::random::<u32>()));
+
+        match open_private(&tmp_path) {
+            Ok(f)  => return Ok((tmp_path, f)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists && tries < 10 => tries += 1,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn open_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Move the finished temp file to `path`. When we're not allowed to overwrite, a hard link is used, since
+/// creating it fails atomically if `path` already exists. Some filesystems (like FAT on USB sticks) don't
+/// support hard links, in which case we fall back to checking first and then renaming.
+fn move_into_place(tmp_path: &Path, path: &Path, filename: &str, overwrite: bool) -> Result<()> {
+    if overwrite {
+        return fs::rename(tmp_path, path).map_err(|e| Error::Io(filename.to_string(), e));
+    }
+
+    match fs::hard_link(tmp_path, path) {
+        Ok(_) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => Err(Error::AlreadyExists(filename.to_string())),
+        Err(_) => {
+            if path.exists() {
+                return Err(Error::AlreadyExists(filename.to_string()));
+            }
+            fs::rename(tmp_path, path).map_err(|e| Error::Io(filename.to_string(), e))
+        }
+    }
+}
+
+/// fsync the directory, so the new directory entry survives a power loss too
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new empty directory for one test, removed when it's dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let dir = std::env::temp_dir().join(format!("sdp-secure-file-{:016x}", rand::random::<u64>()));
+            fs::create_dir(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn file(&self, name: &str) -> String {
+            self.0.join(name).to_string_lossy().to_string()
+        }
+
+        fn entries(&self) -> Vec<String> {
+            let mut names: Vec<String> = fs::read_dir(&self.0).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn test_write_secret_file() {
+        let dir = TempDir::new();
+        let filename = dir.file("wallet.json");
+
+        write_secret_file(&filename, b"first", false).unwrap();
+        assert_eq!(fs::read(&filename).unwrap(), b"first");
+        assert_eq!(dir.entries(), vec!["wallet.json"]);
+
+        // An existing file is left alone without overwrite, and replaced with it
+        match write_secret_file(&filename, b"second", false) {
+            Err(Error::AlreadyExists(f)) => assert_eq!(f, filename),
+            r                            => panic!("Expected AlreadyExists, got {:?}", r)
+        };
+        assert_eq!(fs::read(&filename).unwrap(), b"first");
+
+        write_secret_file(&filename, b"second", true).unwrap();
+        assert_eq!(fs::read(&filename).unwrap(), b"second");
+        assert_eq!(dir.entries(), vec!["wallet.json"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let filename = dir.file("wallet.pdf");
+        write_secret_file(&filename, b"key", false).unwrap();
+        assert_eq!(fs::metadata(&filename).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // Overwriting a file that others could read doesn't keep its mode
+        fs::set_permissions(&filename, fs::Permissions::from_mode(0o644)).unwrap();
+        write_secret_file(&filename, b"key", true).unwrap();
+        assert_eq!(fs::metadata(&filename).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_secret_file() {
+        let dir = TempDir::new();
+        let filename = dir.file("keys.csv");
+
+        let mut file = SecretFile::create(&filename, false).unwrap();
+        file.write_all(b"num,type\n").unwrap();
+        file.write_all(b"0,z\n").unwrap();
+        assert!(!Path::new(&filename).exists());
+        file.commit().unwrap();
+        assert_eq!(fs::read(&filename).unwrap(), b"num,type\n0,z\n");
+
+        // Dropped before it is committed: the temp file is removed and the existing file isn't touched
+        {
+            let mut file = SecretFile::create(&filename, true).unwrap();
+            file.write_all(b"partial").unwrap();
+            assert_eq!(dir.entries().len(), 2);
+        }
+        assert_eq!(dir.entries(), vec!["keys.csv"]);
+        assert_eq!(fs::read(&filename).unwrap(), b"num,type\n0,z\n");
+
+        // A file that appears while the secret file is written isn't replaced either
+        let other = dir.file("other.csv");
+        let mut file = SecretFile::create(&other, false).unwrap();
+        file.write_all(b"mine").unwrap();
+        fs::write(&other, b"theirs").unwrap();
+        match file.commit() {
+            Err(Error::AlreadyExists(_)) => (),
+            r                            => panic!("Expected AlreadyExists, got {:?}", r)
+        };
+        assert_eq!(fs::read(&other).unwrap(), b"theirs");
+        assert_eq!(dir.entries(), vec!["keys.csv", "other.csv"]);
+    }
+}
@@ -0,0 +1,308 @@
+use bech32::Bech32;
+use zcash_primitives::zip32::{ChildIndex, ExtendedSpendingKey};
+
+use crate::bip39;
+use crate::encoding::decode_bech32;
+use crate::error::{Error, Result};
+use crate::paper::{double_sha256, encode_privatekey, encode_taddress, params, pubkey_hash, CoinParams, ToBase58Check};
+use crate::verify::{decode, decode_spending_key, KeyKind};
+use crate::wallet::{AddressKind, WalletBatch, WalletEntry};
+
+/// The spending key m/1/2' of the published ZIP-32 test vectors for the seed 0..31, encoded for Hush
+const ZIP32_KEY: &str = "secret-extended-key-main1qtden8s8qgqqpqyhec2lfmgmjuumqf32gcauk0wfkw7jxgafh2jyrjjzwaec82x5xk97syfuac6p8fclstzpljx629a7zdqynqewdqjujtdxhp87unrq6dmcqkwu26086rfj8y2h8723h00f9lrtnnmpgaekv8zuyua2dxgve7q3st5kyg7q9r8r6m4509xnzyae2p5aznzhtz8pjwm9alpgzw728mdpn70073k2zt06r0csxud535d55sxy6pdqmrwwpe7uv2c8kdcj46mkv";
+
+/// The address at m/32'/133'/0' for the same seed, as the zcash_primitives implementation derives it
+const ZIP32_ADDRESS: &str = "zs1mrhc9y7jdh5r9ece8u5khgvj9kg0zgkxzdduyv0whkg7lkcrkx5xqem3e48avjq9wn2rukydkwn";
+
+/// Check the hashes, encodings and key derivations that the generator depends on against known answers, and
+/// check that a Sapling key derived from a fixed seed survives a round trip through the encoder and decoder.
+/// This should be run once at startup, before any keys are generated.
+pub fn known_answer_tests() -> Result<()> {
+    // SHA256d("hello")
+    expect("double_sha256", &hex::encode(double_sha256(b"hello")),
+           "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50")?;
+
+    // The public key hash of the secret key 1, which is the well known Bitcoin address 1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    let sk = secp256k1::SecretKey::parse(&one).map_err(|_| Error::SelfTest("secp256k1 rejected the secret key 1".to_string()))?;
+    let hash = pubkey_hash(&sk, true);
+    expect("pubkey_hash", &hex::encode(&hash), "751e76e8199196d454941c45d1b3a323f1433bd6")?;
+    expect("to_base58check", &hash.to_base58check(&[0x00], &[]), "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH")?;
+
+    // The same key as a Hush t-address and WIF
+    let (addr, wif) = encode_taddress(&sk, true);
+    expect("t-address", &addr, "RKxTdfmtxtfLDKZBgx6SvNkBtNu9jRYnLh")?;
+    expect("WIF", &wif, "Up1YVLk7uuErCHVQyFCtfinZngmdwfyfc47WCQ8oJxgowjVzNeqs")?;
+
+    // Valid bech32 strings from BIP 173, through both the bech32 crate and our own decoder
+    let b = Bech32::new("a".to_string(), vec![]).map_err(|e| Error::SelfTest(e.to_string()))?;
+    expect("bech32 encoding", &b.to_string(), "a12uel5l")?;
+    let (hrp, data) = decode_bech32("abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw")
+                        .map_err(|e| Error::SelfTest(format!("bech32 decoding: {}", e)))?;
+    expect("bech32 decoding", &format!("{}:{}", hrp, hex::encode(data)), "abcdef:00443214c74254b635cf84653a56d7c675be77df")?;
+
+    // The spending key m/1/2' of the seed 0..31 from the published ZIP-32 test vectors, derived in one go and
+    // one level at a time, and decoded back
+    let seed: Vec<u8> = (0..32).collect();
+    let master = ExtendedSpendingKey::master(&seed);
+    let path = [ChildIndex::NonHardened(1), ChildIndex::Hardened(2)];
+    let spk = ExtendedSpendingKey::from_path(&master, &path);
+    let stepwise = path.iter().fold(master.clone(), |k, i| k.derive_child(*i));
+
+    expect("ZIP-32 derivation", &encode_privatekey(&spk)?, ZIP32_KEY)?;
+    expect("ZIP-32 derivation one level at a time", &encode_privatekey(&stepwise)?, ZIP32_KEY)?;
+
+    let decoded = decode_spending_key(ZIP32_KEY, &params()).map_err(|e| Error::SelfTest(format!("Sapling key decoding: {}", e)))?;
+    expect("Sapling key round trip", &encode_privatekey(&decoded)?, ZIP32_KEY)?;
+
+    // The first address of a wallet with the same seed
+    let first = ExtendedSpendingKey::from_path(&master, &[ChildIndex::Hardened(32), ChildIndex::Hardened(133), ChildIndex::Hardened(0)]);
+    expect("Sapling address", &decode(&encode_privatekey(&first)?, &params())?.address, ZIP32_ADDRESS)?;
+
+    return Ok(());
+}
+
+/// Check every entry of a batch with `check_entry`
+pub fn check_batch(batch: &WalletBatch) -> Result<()> {
+    let coin = batch.metadata.as_ref().map(|m| m.coin.clone()).unwrap_or_else(params);
+
+    for entry in &batch.entries {
+        check_entry(entry, &coin)?;
+    }
+
+    return Ok(());
+}
+
+/// Independently check a wallet entry: decode its private key (and viewing key) strings with our own decoders,
+/// derive the address again and compare, and if it has a seed and path, derive the key again from those.
+pub fn check_entry(entry: &WalletEntry, coin: &CoinParams) -> Result<()> {
+    let fail = |what: &str| Error::SelfTest(format!("{} of {} {} doesn't match", what, entry.kind.as_str(), entry.num));
+    let decode_err = |what: &str, e: Error| Error::SelfTest(format!("{} of {} {}: {}", what, entry.kind.as_str(), entry.num, e));
+
+    let (address_kind, key_kind) = match entry.kind {
+        AddressKind::Sapling     => (KeyKind::SaplingAddress, KeyKind::SpendingKey),
+        AddressKind::Transparent => (KeyKind::TransparentAddress, KeyKind::Wif),
+    };
+
+    let address = decode(&entry.address, coin).map_err(|e| decode_err("Address", e))?;
+    if address.kind != address_kind {
+        return Err(fail("Address type"));
+    }
+
+    let key = decode(&entry.keys.private_key, coin).map_err(|e| decode_err("Private key", e))?;
+    if key.kind != key_kind || key.address != entry.address {
+        return Err(fail("Private key"));
+    }
+
+    match (&entry.keys.viewing_key, entry.kind) {
+        (Some(vk), AddressKind::Sapling) => {
+            let viewing = decode(vk, coin).map_err(|e| decode_err("Viewing key", e))?;
+            if viewing.kind != KeyKind::ViewingKey || viewing.address != entry.address {
+                return Err(fail("Viewing key"));
+            }
+        },
+        (None, AddressKind::Transparent) => (),
+        _                                => return Err(fail("Viewing key"))
+    };
+
+    if let Some(d) = &entry.derivation {
+        let spk = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(&d.hd_seed), &d.child_indices()?);
+        check_derived_key(entry, &spk)?;
+
+        // The passphrase isn't stored, so only without one can the seed be derived from the mnemonic again
+        match &d.mnemonic {
+            Some(m) if !d.passphrase => {
+                let seed = bip39::mnemonic_to_seed(m, "").map_err(|e| decode_err("Mnemonic", e))?;
+                if seed[..] != d.hd_seed[..] {
+                    return Err(fail("Seed derived from the mnemonic"));
+                }
+            },
+            _                        => ()
+        };
+    }
+
+    return Ok(());
+}
+
+/// Check that `parent`, which was derived in one go from `seed` at `path`, is the same key as the one derived
+/// from the master key one level at a time. The children of a checked parent are checked with `check_child`.
+///
+/// Both derivations run the same `derive_child` code, so this only catches a fault while the parent was
+/// derived, like a flipped bit. The derivation code itself is checked against published vectors by
+/// `known_answer_tests`.
+pub fn check_parent(seed: &[u8], path: &[ChildIndex], parent: &ExtendedSpendingKey) -> Result<()> {
+    let stepwise = path.iter().fold(ExtendedSpendingKey::master(seed), |k, i| k.derive_child(*i));
+    expect("Parent key derived from the seed", &encode_privatekey(parent)?, &encode_privatekey(&stepwise)?)?;
+
+    return Ok(());
+}
+
+/// Check a wallet entry like `check_entry` does, but derive its key again from `parent`, a key at `path` that
+/// `check_parent` checked against the seed, instead of from the seed. The entry has to record the same seed,
+/// and a path one level below the parent.
+///
+/// The key is derived again from the same `parent`, so this catches a fault while the child was derived or
+/// encoded, but not a `parent` that went bad after it was checked. To catch that, some of the children have to
+/// be checked with `check_entry`, which derives them from the seed.
+pub fn check_child(entry: &WalletEntry, seed: &[u8], path: &[ChildIndex], parent: &ExtendedSpendingKey, coin: &CoinParams) -> Result<()> {
+    let fail = |what: &str| Error::SelfTest(format!("{} of {} {} doesn't match", what, entry.kind.as_str(), entry.num));
+
+    let d = entry.derivation.as_ref().ok_or_else(|| fail("Derivation"))?;
+    let indices = d.child_indices()?;
+    if d.hd_seed[..] != seed[..] || indices.len() != path.len() + 1 || indices[..path.len()] != path[..] {
+        return Err(fail("Derivation"));
+    }
+
+    let mut without_seed = entry.clone();
+    without_seed.derivation = None;
+    check_entry(&without_seed, coin)?;
+
+    return check_derived_key(entry, &parent.derive_child(indices[path.len()]));
+}
+
+/// Compare the private key of an entry with a key derived for it
+fn check_derived_key(entry: &WalletEntry, spk: &ExtendedSpendingKey) -> Result<()> {
+    let mut derived = vec![];
+    spk.write(&mut derived).map_err(|e| Error::KeyEncoding(e.to_string()))?;
+    let (_, stored) = decode_bech32(&entry.keys.private_key)
+                        .map_err(|e| Error::SelfTest(format!("Private key of {} {}: {}", entry.kind.as_str(), entry.num, e)))?;
+    if derived != stored {
+        return Err(Error::SelfTest(format!("Key derived from the seed of {} {} doesn't match", entry.kind.as_str(), entry.num)));
+    }
+
+    return Ok(());
+}
+
+fn expect(what: &str, got: &str, expected: &str) -> Result<()> {
+    if got != expected {
+        return Err(Error::SelfTest(format!("{}: expected {}, got {}", what, expected, got)));
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::{wallet_from_mnemonic, wallet_from_seed, DerivationPath};
+
+    fn batch() -> WalletBatch {
+        let seed: Vec<u8> = (0..32).collect();
+        wallet_from_seed(&seed, 2, 2, &DerivationPath::default(), 1, "test".to_string()).unwrap()
+    }
+
+    /// Change the character at `i` to another one that is valid in both bech32 and base58
+    fn mutate(s: &str, i: usize) -> String {
+        let c = if &s[i..i+1] == "q" { "p" } else { "q" };
+        format!("{}{}{}", &s[..i], c, &s[i+1..])
+    }
+
+    fn is_selftest_error(r: Result<()>) -> bool {
+        match r {
+            Err(Error::SelfTest(_)) => true,
+            _                       => false
+        }
+    }
+
+    #[test]
+    fn test_known_answers() {
+        known_answer_tests().unwrap();
+    }
+
+    #[test]
+    fn test_check_entry() {
+        let batch = batch();
+        check_batch(&batch).unwrap();
+
+        for entry in &batch.entries {
+            let coin = params();
+            check_entry(entry, &coin).unwrap();
+
+            // One wrong character anywhere in the address or the keys
+            for i in &[entry.address.len() / 2, entry.address.len() - 1] {
+                let mut bad = entry.clone();
+                bad.address = mutate(&entry.address, *i);
+                assert!(is_selftest_error(check_entry(&bad, &coin)));
+            }
+
+            let mut bad = entry.clone();
+            bad.keys.private_key = mutate(&entry.keys.private_key, entry.keys.private_key.len() / 2);
+            assert!(is_selftest_error(check_entry(&bad, &coin)));
+
+            if let Some(vk) = &entry.keys.viewing_key {
+                let mut bad = entry.clone();
+                bad.keys.viewing_key = Some(mutate(vk, vk.len() / 2));
+                assert!(is_selftest_error(check_entry(&bad, &coin)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_derivation() {
+        let batch = batch();
+        let coin = params();
+
+        // A different byte of the seed, or a different path, doesn't derive the key
+        let mut bad = batch.entries[0].clone();
+        bad.derivation.as_mut().unwrap().hd_seed[5] ^= 1;
+        assert!(is_selftest_error(check_entry(&bad, &coin)));
+
+        let mut bad = batch.entries[0].clone();
+        bad.derivation.as_mut().unwrap().path = "m/32'/133'/1'".to_string();
+        assert!(is_selftest_error(check_entry(&bad, &coin)));
+
+        // The keys of another address in the same batch
+        let mut bad = batch.entries[0].clone();
+        bad.keys = batch.entries[1].keys.clone();
+        assert!(is_selftest_error(check_entry(&bad, &coin)));
+
+        let mut bad = batch.clone();
+        bad.entries[3].address = batch.entries[2].address.clone();
+        assert!(is_selftest_error(check_batch(&bad)));
+    }
+
+    #[test]
+    fn test_check_child() {
+        let batch = batch();
+        let seed: Vec<u8> = (0..32).collect();
+        let base = [ChildIndex::Hardened(32), ChildIndex::Hardened(133)];
+        let parent = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(&seed), &base);
+        let coin = params();
+
+        check_parent(&seed, &base, &parent).unwrap();
+        assert!(is_selftest_error(check_parent(&seed, &base[..1], &parent)));
+
+        check_child(&batch.entries[1], &seed, &base, &parent, &coin).unwrap();
+
+        // Another parent, another seed, a path that isn't one level below the parent, or a wrong key
+        let other = ExtendedSpendingKey::from_path(&ExtendedSpendingKey::master(&seed), &[ChildIndex::Hardened(32), ChildIndex::Hardened(134)]);
+        assert!(is_selftest_error(check_child(&batch.entries[1], &seed, &base, &other, &coin)));
+        assert!(is_selftest_error(check_child(&batch.entries[1], &seed[1..], &base, &parent, &coin)));
+        assert!(is_selftest_error(check_child(&batch.entries[1], &seed, &base[..1], &parent, &coin)));
+
+        let mut bad = batch.entries[1].clone();
+        bad.keys = batch.entries[0].keys.clone();
+        assert!(is_selftest_error(check_child(&bad, &seed, &base, &parent, &coin)));
+
+        // A t-address has no derivation to check
+        assert!(is_selftest_error(check_child(&batch.entries[2], &seed, &base, &parent, &coin)));
+    }
+
+    #[test]
+    fn test_check_mnemonic() {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let batch = wallet_from_mnemonic(mnemonic, "", 1, 0, &DerivationPath::default(), 1, "test".to_string()).unwrap();
+        check_batch(&batch).unwrap();
+
+        // A mnemonic that doesn't give the seed
+        let mut bad = batch.clone();
+        bad.entries[0].derivation.as_mut().unwrap().mnemonic = Some(mnemonic.replace("about", "above"));
+        assert!(check_batch(&bad).is_err());
+
+        // With a passphrase the seed can't be derived from the mnemonic alone, so it isn't compared
+        let batch = wallet_from_mnemonic(mnemonic, "TREZOR", 1, 0, &DerivationPath::default(), 1, "test".to_string()).unwrap();
+        assert!(batch.entries[0].derivation.as_ref().unwrap().passphrase);
+        check_batch(&batch).unwrap();
+    }
+}
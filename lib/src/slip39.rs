@@ -0,0 +1,609 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::encryption::random_bytes;
+use crate::error::{Error, Result};
+
+/// The SLIP-39 wordlist, one word per line. Every word is identified by its first 4 letters.
+const WORDLIST: &str = include_str!("slip39_wordlist.txt");
+
+/// Every word encodes 10 bits
+const RADIX_BITS: usize = 10;
+
+/// The header is 4 words: the identifier, the extendable flag and the iteration exponent, then the group and
+/// member parameters. The checksum is the last 3 words.
+const HEADER_WORDS: usize = 4;
+const CHECKSUM_WORDS: usize = 3;
+
+/// The shortest secret is 128 bits, which takes 13 words
+const MIN_SECRET_LENGTH: usize = 16;
+const MIN_MNEMONIC_WORDS: usize = HEADER_WORDS + 13 + CHECKSUM_WORDS;
+
+/// At most 16 groups, and 16 members in a group, since the indices are 4 bits
+const MAX_SHARE_COUNT: usize = 16;
+
+/// The customization strings of the checksum, without and with the extendable flag
+const CUSTOMIZATION: &str = "shamir";
+const CUSTOMIZATION_EXTENDABLE: &str = "shamir_extendable";
+
+const RS1024_GENERATOR: [u32; 10] = [0xe0e040, 0x1c1c080, 0x3838100, 0x7070200, 0xe0e0009, 0x1c0c2412, 0x38086c24, 0x3090fc48, 0x21b1f890, 0x3f3f120];
+
+/// The passphrase is stretched with 10000 << iteration_exponent iterations of PBKDF2, spread over the 4 rounds
+/// of the Feistel cipher that encrypts the master secret
+const BASE_ITERATION_COUNT: u32 = 10000;
+const ROUND_COUNT: u8 = 4;
+
+/// The x coordinates of the digest and the secret on the sharing polynomial
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+const DIGEST_LENGTH: usize = 4;
+
+/// 20000 iterations of PBKDF2, as Trezor uses
+pub const DEFAULT_ITERATION_EXPONENT: u8 = 1;
+
+/// A group of shares, of which any `threshold` out of `count` recover the group's part of the secret
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroupSpec {
+    pub threshold : u8,
+    pub count     : u8,
+}
+
+impl FromStr for GroupSpec {
+    type Err = Error;
+
+    /// Parse a group like "2of3"
+    fn from_str(s: &str) -> Result<GroupSpec> {
+        let invalid = || Error::Shares(format!("'{}' should be like 2of3", s));
+
+        let mut parts = s.trim().splitn(2, "of");
+        let threshold = parts.next().and_then(|t| t.trim().parse::<u8>().ok()).ok_or_else(invalid)?;
+        let count     = parts.next().and_then(|c| c.trim().parse::<u8>().ok()).ok_or_else(invalid)?;
+
+        return Ok(GroupSpec { threshold, count });
+    }
+}
+
+impl fmt::Display for GroupSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}of{}", self.threshold, self.count)
+    }
+}
+
+/// One SLIP-39 share, written as a mnemonic of 20 words (for a 128 bit secret) to 33 words (for 256 bits)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Share {
+    /// A random identifier, the same for all shares of a secret
+    pub identifier         : u16,
+    /// Extendable shares don't salt the encryption with the identifier, so more groups can be made later
+    pub extendable         : bool,
+    pub iteration_exponent : u8,
+    /// The group of this share, counted from 0
+    pub group_index        : u8,
+    /// How many groups are needed to recover the secret
+    pub group_threshold    : u8,
+    pub group_count        : u8,
+    /// The index of this share in its group, counted from 0
+    pub member_index       : u8,
+    /// How many shares of this group are needed to recover the group's part
+    pub member_threshold   : u8,
+    pub value              : Vec<u8>,
+}
+
+impl Share {
+    /// The words of the mnemonic
+    pub fn words(&self) -> Vec<&'static str> {
+        let wordlist: Vec<&str> = WORDLIST.lines().collect();
+        self.to_indices().iter().map(|i| wordlist[*i as usize]).collect()
+    }
+
+    pub fn to_mnemonic(&self) -> String {
+        self.words().join(" ")
+    }
+
+    /// Parse a mnemonic, checking its checksum. Words can be abbreviated to their first 4 letters.
+    pub fn from_mnemonic(mnemonic: &str) -> Result<Share> {
+        let indices = mnemonic.split_whitespace().map(word_index).collect::<Result<Vec<u16>>>()?;
+        if indices.len() < MIN_MNEMONIC_WORDS {
+            return Err(Error::Shares(format!("A share has at least {} words, got {}", MIN_MNEMONIC_WORDS, indices.len())));
+        }
+
+        let id_exp = (indices[0] as u32) << RADIX_BITS | indices[1] as u32;
+        let extendable = (id_exp >> 4) & 1 == 1;
+        if !rs1024_verify_checksum(customization(extendable), &indices) {
+            return Err(Error::Shares(format!("Checksum mismatch in the share starting with '{}'", first_words(mnemonic))));
+        }
+
+        let params = (indices[2] as u32) << RADIX_BITS | indices[3] as u32;
+        let share = Share {
+            identifier         : (id_exp >> 5) as u16,
+            extendable,
+            iteration_exponent : (id_exp & 0xf) as u8,
+            group_index        : (params >> 16) as u8,
+            group_threshold    : ((params >> 12) & 0xf) as u8 + 1,
+            group_count        : ((params >> 8) & 0xf) as u8 + 1,
+            member_index       : ((params >> 4) & 0xf) as u8,
+            member_threshold   : (params & 0xf) as u8 + 1,
+            value              : indices_to_value(&indices[HEADER_WORDS..indices.len() - CHECKSUM_WORDS])?,
+        };
+
+        if share.group_threshold > share.group_count {
+            return Err(Error::Shares(format!("The share starting with '{}' needs {} groups, but there are only {}", first_words(mnemonic), share.group_threshold, share.group_count)));
+        }
+
+        return Ok(share);
+    }
+
+    fn to_indices(&self) -> Vec<u16> {
+        let id_exp = (self.identifier as u32) << 5 | (self.extendable as u32) << 4 | self.iteration_exponent as u32;
+        let params = (self.group_index as u32) << 16 | (self.group_threshold as u32 - 1) << 12 | (self.group_count as u32 - 1) << 8
+                        | (self.member_index as u32) << 4 | (self.member_threshold as u32 - 1);
+
+        let mut indices = vec![(id_exp >> RADIX_BITS) as u16, (id_exp & 0x3ff) as u16, (params >> RADIX_BITS) as u16, (params & 0x3ff) as u16];
+        indices.extend(value_to_indices(&self.value));
+
+        let checksum = rs1024_create_checksum(customization(self.extendable), &indices);
+        indices.extend_from_slice(&checksum);
+
+        return indices;
+    }
+}
+
+/// Split the master secret into groups of SLIP-39 shares, encrypting it with the passphrase first. Any
+/// `group_threshold` of the groups recover the secret, and each group needs its own threshold of member shares.
+///
+/// Returns the member shares of every group. The shares are combined again before they are returned, to make
+/// sure they recover the secret.
+pub fn generate_shares(master_secret: &[u8], passphrase: &str, group_threshold: u8, groups: &[GroupSpec], iteration_exponent: u8) -> Result<Vec<Vec<Share>>> {
+    if master_secret.len() < MIN_SECRET_LENGTH || master_secret.len() % 2 != 0 {
+        return Err(Error::Shares(format!("The secret has to be an even number of bytes, and at least {}", MIN_SECRET_LENGTH)));
+    }
+    if groups.is_empty() || groups.len() > MAX_SHARE_COUNT {
+        return Err(Error::Shares(format!("There can be 1 to {} groups, got {}", MAX_SHARE_COUNT, groups.len())));
+    }
+    if group_threshold == 0 || group_threshold as usize > groups.len() {
+        return Err(Error::Shares(format!("The group threshold has to be between 1 and the number of groups ({}), got {}", groups.len(), group_threshold)));
+    }
+    for g in groups {
+        if g.threshold == 0 || g.threshold > g.count || g.count as usize > MAX_SHARE_COUNT {
+            return Err(Error::Shares(format!("Invalid group {}. A group has 1 to {} shares, and needs 1 to all of them", g, MAX_SHARE_COUNT)));
+        }
+        if g.threshold == 1 && g.count > 1 {
+            return Err(Error::Shares(format!("Invalid group {}. Every share would be a copy of the secret, so use 1of1 instead", g)));
+        }
+    }
+    if iteration_exponent > 0xf {
+        return Err(Error::Shares(format!("The iteration exponent can be at most 15, got {}", iteration_exponent)));
+    }
+    check_passphrase(passphrase)?;
+
+    let mut id_bytes = [0u8; 2];
+    random_bytes(&mut id_bytes)?;
+    let identifier = u16::from_be_bytes(id_bytes) & 0x7fff;
+    let extendable = true;
+
+    let encrypted = feistel(master_secret, passphrase, iteration_exponent, identifier, extendable, true);
+    let group_secrets = split_secret(group_threshold, groups.len() as u8, &encrypted)?;
+
+    let mut ans = vec![];
+    for ((group_index, group_secret), g) in group_secrets.iter().zip(groups) {
+        let members = split_secret(g.threshold, g.count, group_secret)?;
+        ans.push(members.into_iter().map(|(member_index, value)| Share {
+            identifier,
+            extendable,
+            iteration_exponent,
+            group_index        : *group_index,
+            group_threshold,
+            group_count        : groups.len() as u8,
+            member_index,
+            member_threshold   : g.threshold,
+            value,
+        }).collect::<Vec<_>>());
+    }
+
+    // Don't trust the shares until they give back the secret
+    let mnemonics: Vec<String> = ans.iter().flatten().map(|s| s.to_mnemonic()).collect();
+    if combine_mnemonics(&mnemonics, passphrase)? != master_secret {
+        return Err(Error::SelfTest("The shares don't recover the secret".to_string()));
+    }
+
+    return Ok(ans);
+}
+
+/// Recover the master secret from SLIP-39 share mnemonics. Shares of incomplete groups are ignored, as long as
+/// enough groups are complete.
+pub fn combine_mnemonics<S: AsRef<str>>(mnemonics: &[S], passphrase: &str) -> Result<Vec<u8>> {
+    let shares = mnemonics.iter().map(|m| Share::from_mnemonic(m.as_ref())).collect::<Result<Vec<_>>>()?;
+    let first = shares.first().ok_or_else(|| Error::Shares("No shares".to_string()))?;
+
+    for s in &shares {
+        if (s.identifier, s.extendable, s.iteration_exponent, s.group_threshold, s.group_count, s.value.len())
+            != (first.identifier, first.extendable, first.iteration_exponent, first.group_threshold, first.group_count, first.value.len()) {
+            return Err(Error::Shares("The shares are from different secrets".to_string()));
+        }
+    }
+    check_passphrase(passphrase)?;
+
+    // The member shares of every group, without duplicates
+    let mut groups: BTreeMap<u8, BTreeMap<u8, &Share>> = BTreeMap::new();
+    for s in &shares {
+        let members = groups.entry(s.group_index).or_insert_with(BTreeMap::new);
+        if let Some(other) = members.get(&s.member_index) {
+            if other.value != s.value {
+                return Err(Error::Shares(format!("There are two different shares {} of group {}", s.member_index + 1, s.group_index + 1)));
+            }
+        }
+        members.insert(s.member_index, s);
+    }
+
+    let mut group_secrets = vec![];
+    let mut missing = vec![];
+    for (group_index, members) in &groups {
+        let threshold = members.values().next().map(|s| s.member_threshold).unwrap_or(0);
+        if members.values().any(|s| s.member_threshold != threshold) {
+            return Err(Error::Shares(format!("The shares of group {} need different numbers of shares", group_index + 1)));
+        }
+
+        if members.len() >= threshold as usize {
+            let values: Vec<(u8, Vec<u8>)> = members.iter().map(|(i, s)| (*i, s.value.clone())).collect();
+            group_secrets.push((*group_index, recover_secret(threshold, &values)?));
+        } else {
+            missing.push(format!("{} more of group {}", threshold as usize - members.len(), group_index + 1));
+        }
+    }
+
+    if first.group_count == 1 && group_secrets.is_empty() {
+        let members = groups.values().next().map(|m| m.len()).unwrap_or(0);
+        return Err(Error::Shares(format!("Need {} shares, but only got {}", first.member_threshold, members)));
+    }
+    if group_secrets.len() < first.group_threshold as usize {
+        let mut msg = format!("Need {} complete groups of the {}, but only {} are complete", first.group_threshold, first.group_count, group_secrets.len());
+        if !missing.is_empty() {
+            msg.push_str(&format!(". Still need {}", missing.join(", ")));
+        }
+        return Err(Error::Shares(msg));
+    }
+
+    let encrypted = recover_secret(first.group_threshold, &group_secrets)?;
+    return Ok(feistel(&encrypted, passphrase, first.iteration_exponent, first.identifier, first.extendable, false));
+}
+
+/// The index of a word in the wordlist. Abbreviations of at least 4 letters are enough.
+fn word_index(word: &str) -> Result<u16> {
+    let word = word.to_ascii_lowercase();
+    if word.len() >= 4 {
+        if let Some(i) = WORDLIST.lines().position(|w| w.starts_with(&word)) {
+            return Ok(i as u16);
+        }
+    }
+
+    return Err(Error::Shares(format!("'{}' is not a SLIP-39 word", word)));
+}
+
+fn first_words(mnemonic: &str) -> String {
+    mnemonic.split_whitespace().take(3).collect::<Vec<_>>().join(" ")
+}
+
+fn customization(extendable: bool) -> &'static str {
+    if extendable { CUSTOMIZATION_EXTENDABLE } else { CUSTOMIZATION }
+}
+
+/// The passphrase has to be printable ASCII, so it can be typed on any device
+fn check_passphrase(passphrase: &str) -> Result<()> {
+    if passphrase.bytes().any(|b| b < 32 || b > 126) {
+        return Err(Error::Shares("The passphrase can only have printable ASCII characters".to_string()));
+    }
+    return Ok(());
+}
+
+/// Pack the bytes of the share value into 10 bit words, padding with zero bits at the front
+fn value_to_indices(value: &[u8]) -> Vec<u16> {
+    let words = (value.len() * 8 + RADIX_BITS - 1) / RADIX_BITS;
+
+    let mut acc: u32 = 0;
+    let mut bits = words * RADIX_BITS - value.len() * 8;
+    let mut ans = Vec::with_capacity(words);
+
+    for b in value {
+        acc = (acc << 8) | *b as u32;
+        bits += 8;
+        while bits >= RADIX_BITS {
+            bits -= RADIX_BITS;
+            ans.push((acc >> bits) as u16 & 0x3ff);
+        }
+        acc &= (1 << bits) - 1;
+    }
+
+    return ans;
+}
+
+/// Unpack the share value from 10 bit words. The value is a whole number of 16 bit words, and the padding at
+/// the front has to be zero.
+fn indices_to_value(indices: &[u16]) -> Result<Vec<u8>> {
+    let padding = (indices.len() * RADIX_BITS) % 16;
+    if padding > 8 {
+        return Err(Error::Shares("Invalid share length".to_string()));
+    }
+
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut skip = padding;
+    let mut ans = Vec::with_capacity(indices.len() * RADIX_BITS / 8);
+
+    for i in indices {
+        acc = (acc << RADIX_BITS) | *i as u32;
+        bits += RADIX_BITS;
+
+        if skip > 0 {
+            if acc >> (bits - skip) != 0 {
+                return Err(Error::Shares("Invalid padding".to_string()));
+            }
+            bits -= skip;
+            acc &= (1 << bits) - 1;
+            skip = 0;
+        }
+
+        while bits >= 8 {
+            bits -= 8;
+            ans.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+
+    return Ok(ans);
+}
+
+fn rs1024_polymod(values: &[u32]) -> u32 {
+    let mut chk: u32 = 1;
+    for v in values {
+        let b = chk >> 20;
+        chk = ((chk & 0xfffff) << 10) ^ v;
+        for (i, g) in RS1024_GENERATOR.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    return chk;
+}
+
+fn rs1024_values(customization: &str, data: &[u16]) -> Vec<u32> {
+    customization.bytes().map(|b| b as u32).chain(data.iter().map(|d| *d as u32)).collect()
+}
+
+fn rs1024_create_checksum(customization: &str, data: &[u16]) -> [u16; CHECKSUM_WORDS] {
+    let mut values = rs1024_values(customization, data);
+    values.extend_from_slice(&[0; CHECKSUM_WORDS]);
+
+    let polymod = rs1024_polymod(&values) ^ 1;
+    return [(polymod >> 20) as u16 & 0x3ff, (polymod >> 10) as u16 & 0x3ff, polymod as u16 & 0x3ff];
+}
+
+fn rs1024_verify_checksum(customization: &str, data: &[u16]) -> bool {
+    rs1024_polymod(&rs1024_values(customization, data)) == 1
+}
+
+/// The log and exp tables of GF(256) with the Rijndael polynomial x^8 + x^4 + x^3 + x + 1, generated by x + 1
+struct Gf256 {
+    exp : [u8; 255],
+    log : [u8; 256],
+}
+
+impl Gf256 {
+    fn new() -> Gf256 {
+        let mut tables = Gf256 { exp: [0; 255], log: [0; 256] };
+
+        let mut poly: u16 = 1;
+        for i in 0..255 {
+            tables.exp[i] = poly as u8;
+            tables.log[poly as usize] = i as u8;
+
+            // Multiply by x + 1
+            poly = (poly << 1) ^ poly;
+            if poly & 0x100 != 0 {
+                poly ^= 0x11b;
+            }
+        }
+
+        return tables;
+    }
+}
+
+/// Evaluate the polynomial through the shares at `x`, byte by byte, with Lagrange interpolation
+fn interpolate(shares: &[(u8, Vec<u8>)], x: u8) -> Vec<u8> {
+    if let Some((_, value)) = shares.iter().find(|(sx, _)| *sx == x) {
+        return value.clone();
+    }
+
+    let gf = Gf256::new();
+    let log = |v: u8| gf.log[v as usize] as i32;
+
+    let log_product: i32 = shares.iter().map(|(sx, _)| log(sx ^ x)).sum();
+
+    let mut ans = vec![0u8; shares[0].1.len()];
+    for (sx, value) in shares {
+        let others: i32 = shares.iter().filter(|(o, _)| o != sx).map(|(o, _)| log(sx ^ o)).sum();
+        let log_basis = (log_product - log(sx ^ x) - others).rem_euclid(255);
+
+        for (a, v) in ans.iter_mut().zip(value) {
+            if *v != 0 {
+                *a ^= gf.exp[((log(*v) + log_basis) % 255) as usize];
+            }
+        }
+    }
+
+    return ans;
+}
+
+/// The digest that is shared along with the secret, so that a wrong set of shares is detected
+fn share_digest(random: &[u8], secret: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(random).expect("HMAC takes keys of any length");
+    mac.input(secret);
+    return mac.result().code()[..DIGEST_LENGTH].to_vec();
+}
+
+/// Shamir's secret sharing of `secret` into `count` shares, any `threshold` of which recover it
+fn split_secret(threshold: u8, count: u8, secret: &[u8]) -> Result<Vec<(u8, Vec<u8>)>> {
+    if threshold == 1 {
+        return Ok((0..count).map(|i| (i, secret.to_vec())).collect());
+    }
+
+    let random_share_count = threshold - 2;
+    let mut shares = vec![];
+    for i in 0..random_share_count {
+        let mut value = vec![0u8; secret.len()];
+        random_bytes(&mut value)?;
+        shares.push((i, value));
+    }
+
+    let mut random = vec![0u8; secret.len() - DIGEST_LENGTH];
+    random_bytes(&mut random)?;
+    let mut digest = share_digest(&random, secret);
+    digest.extend_from_slice(&random);
+
+    let mut base = shares.clone();
+    base.push((DIGEST_INDEX, digest));
+    base.push((SECRET_INDEX, secret.to_vec()));
+
+    for i in random_share_count..count {
+        shares.push((i, interpolate(&base, i)));
+    }
+
+    return Ok(shares);
+}
+
+/// Recover the secret from `threshold` or more shares, checking the digest
+fn recover_secret(threshold: u8, shares: &[(u8, Vec<u8>)]) -> Result<Vec<u8>> {
+    if threshold == 1 {
+        return Ok(shares[0].1.clone());
+    }
+
+    let secret = interpolate(shares, SECRET_INDEX);
+    let digest = interpolate(shares, DIGEST_INDEX);
+    if share_digest(&digest[DIGEST_LENGTH..], &secret) != digest[..DIGEST_LENGTH] {
+        return Err(Error::Shares("The shares don't fit together. Some may be mistyped, or from another set".to_string()));
+    }
+
+    return Ok(secret);
+}
+
+/// The 4 round Feistel cipher that encrypts the master secret with the passphrase before it is split
+fn feistel(secret: &[u8], passphrase: &str, iteration_exponent: u8, identifier: u16, extendable: bool, encrypt: bool) -> Vec<u8> {
+    let half = secret.len() / 2;
+    let mut left = secret[..half].to_vec();
+    let mut right = secret[half..].to_vec();
+
+    let mut salt_prefix = vec![];
+    if !extendable {
+        salt_prefix.extend_from_slice(CUSTOMIZATION.as_bytes());
+        salt_prefix.extend_from_slice(&identifier.to_be_bytes());
+    }
+    let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / ROUND_COUNT as u32;
+
+    let rounds: Vec<u8> = if encrypt { (0..ROUND_COUNT).collect() } else { (0..ROUND_COUNT).rev().collect() };
+    for i in rounds {
+        let mut password = vec![i];
+        password.extend_from_slice(passphrase.as_bytes());
+
+        let mut salt = salt_prefix.clone();
+        salt.extend_from_slice(&right);
+
+        let mut f = vec![0u8; half];
+        pbkdf2::pbkdf2::<Hmac<Sha256>>(&password, &salt, iterations as usize, &mut f);
+
+        let new_right: Vec<u8> = left.iter().zip(&f).map(|(l, f)| l ^ f).collect();
+        left = right;
+        right = new_right;
+    }
+
+    right.extend_from_slice(&left);
+    return right;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wordlist() {
+        let words: Vec<&str> = WORDLIST.lines().collect();
+        assert_eq!(words.len(), 1 << RADIX_BITS);
+
+        // Sorted, and every word is identified by its first 4 letters
+        for pair in words.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert_ne!(pair[0][..4], pair[1][..4]);
+        }
+    }
+
+    #[test]
+    fn test_slip39_vectors() {
+        // From the SLIP-39 test vectors, with the passphrase "TREZOR"
+        let vectors: [(&[&str], &str); 3] = [
+            (&["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard"],
+             "bb54aac4b89dc868ba37d9cc21b2cece"),
+            (&["shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+               "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking"],
+             "b43ceb7e57a0ea8766221624d01b0864"),
+            (&["theory painting academic academic armed sweater year military elder discuss acne wildlife boring employer fused large satoshi bundle carbon diagnose anatomy hamster leaves tracks paces beyond phantom capital marvel lips brave detect luck"],
+             "989baf9dcaad5b10ca33dfd8cc75e42477025dce88ae83e75a230086a0e00e92"),
+        ];
+
+        for (mnemonics, secret) in vectors.iter() {
+            assert_eq!(hex::encode(combine_mnemonics(mnemonics, "TREZOR").unwrap()), *secret);
+
+            // The shares encode back to the same words
+            for m in mnemonics.iter() {
+                assert_eq!(Share::from_mnemonic(m).unwrap().to_mnemonic(), *m);
+            }
+        }
+
+        // Only 1 of the 2 shares that are needed
+        match combine_mnemonics(&vectors[1].0[..1], "TREZOR") {
+            Err(Error::Shares(_)) => (),
+            _                     => panic!("Expected too few shares")
+        }
+    }
+
+    #[test]
+    fn test_generate_and_combine() {
+        let secret = [0x42u8; 32];
+        let groups = [GroupSpec { threshold: 2, count: 3 }, GroupSpec { threshold: 3, count: 5 }, GroupSpec { threshold: 1, count: 1 }];
+        let shares = generate_shares(&secret, "", 2, &groups, 0).unwrap();
+
+        assert_eq!(shares.iter().map(|g| g.len()).collect::<Vec<_>>(), vec![3, 5, 1]);
+        assert!(shares.iter().flatten().all(|s| s.words().len() == 33));
+
+        let mnemonic = |g: usize, m: usize| shares[g][m].to_mnemonic();
+
+        // Any 2 complete groups recover the secret, with incomplete groups ignored
+        assert_eq!(combine_mnemonics(&[mnemonic(0, 2), mnemonic(0, 0), mnemonic(2, 0)], "").unwrap(), secret);
+        assert_eq!(combine_mnemonics(&[mnemonic(1, 4), mnemonic(1, 1), mnemonic(1, 2), mnemonic(2, 0), mnemonic(0, 1)], "").unwrap(), secret);
+
+        // One complete group isn't enough
+        match combine_mnemonics(&[mnemonic(0, 0), mnemonic(0, 1), mnemonic(1, 0)], "") {
+            Err(Error::Shares(e)) => assert!(e.contains("2 more of group 2"), "{}", e),
+            _                     => panic!("Expected too few shares")
+        }
+
+        // Another passphrase gives another secret
+        assert_ne!(combine_mnemonics(&[mnemonic(0, 0), mnemonic(0, 1), mnemonic(2, 0)], "other").unwrap(), secret);
+    }
+
+    #[test]
+    fn test_abbreviations_and_typos() {
+        let m = "duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard";
+        let abbreviated: Vec<String> = m.split(' ').map(|w| w[..4].to_uppercase()).collect();
+        assert_eq!(Share::from_mnemonic(&abbreviated.join(" ")).unwrap(), Share::from_mnemonic(m).unwrap());
+
+        match Share::from_mnemonic(&m.replace("fridge", "friar")) {
+            Err(Error::Shares(e)) => assert!(e.contains("Checksum"), "{}", e),
+            _                     => panic!("Expected a checksum mismatch")
+        }
+        assert!(Share::from_mnemonic(&m.replace("fridge", "fridges")).is_err());
+        assert!(Share::from_mnemonic(&m.replace("fridge", "zzzz")).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use bech32::{Bech32, ToBase32};
+use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+use crate::encoding::{bech32_split, decode_base58check, decode_bech32};
+use crate::error::{Error, Result};
+use crate::paper::{default_address_bytes, pubkey_hash, CoinParams, ToBase58Check};
+
+/// Length of a Sapling address: an 11 byte diversifier and the 32 byte pk_d
+const ZADDR_LENGTH: usize = 43;
+
+/// Length of a serialized Sapling extended spending or full viewing key
+const EXTENDED_KEY_LENGTH: usize = 169;
+
+/// What a key or address string decoded as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyKind {
+    SaplingAddress,
+    TransparentAddress,
+    /// A Sapling extended spending key, as printed by z_exportkey
+    SpendingKey,
+    /// A Sapling extended full viewing key, as printed by z_exportviewingkey
+    ViewingKey,
+    /// A WIF encoded t-address private key, as printed by dumpprivkey
+    Wif,
+}
+
+impl KeyKind {
+    pub fn description(&self) -> &'static str {
+        match self {
+            KeyKind::SaplingAddress     => "Sapling z-address",
+            KeyKind::TransparentAddress => "t-address",
+            KeyKind::SpendingKey        => "Sapling extended spending key",
+            KeyKind::ViewingKey         => "Sapling extended full viewing key",
+            KeyKind::Wif                => "t-address private key (WIF)",
+        }
+    }
+
+    pub fn is_address(&self) -> bool {
+        *self == KeyKind::SaplingAddress || *self == KeyKind::TransparentAddress
+    }
+}
+
+/// A successfully decoded key or address
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decoded {
+    pub kind    : KeyKind,
+    /// For addresses, the address itself. For keys, the address the key spends or views. For z-addresses
+    /// that's the default address of the key.
+    pub address : String,
+}
+
+/// Decode any address or key of the given coin, checking its checksum, its prefix or version byte and its length.
+pub fn decode(s: &str, coin: &CoinParams) -> Result<Decoded> {
+    let s = s.trim();
+    let lower = s.to_ascii_lowercase();
+
+    let has_prefix = |hrp: &str| lower.starts_with(&format!("{}1", hrp));
+
+    if has_prefix(&coin.zaddress_prefix) {
+        let data = decode_bech32_with_hrp(s, &coin.zaddress_prefix)?;
+        if data.len() != ZADDR_LENGTH {
+            return Err(Error::InvalidKey(format!("A z-address has {} bytes, got {}", ZADDR_LENGTH, data.len())));
+        }
+        return Ok(Decoded { kind: KeyKind::SaplingAddress, address: lower });
+    }
+
+    if has_prefix(&coin.zsecret_prefix) {
+        let spk = decode_spending_key(s, coin)?;
+        let address = encode_zaddress(&default_address_bytes(&ExtendedFullViewingKey::from(&spk))?, coin)?;
+        return Ok(Decoded { kind: KeyKind::SpendingKey, address });
+    }
+
+    if has_prefix(&coin.zviewkey_prefix) {
+        let data = decode_bech32_with_hrp(s, &coin.zviewkey_prefix)?;
+        let fvk = read_extended_key(&data, |d| ExtendedFullViewingKey::read(d))?;
+        let address = encode_zaddress(&default_address_bytes(&fvk)?, coin)?;
+        return Ok(Decoded { kind: KeyKind::ViewingKey, address });
+    }
+
+    // Anything else that looks like bech32 is for some other coin or network
+    if let Ok((hrp, _)) = bech32_split(s) {
+        return Err(Error::InvalidKey(format!("Unknown prefix '{}'. This key or address is for a different coin or network.", hrp)));
+    }
+
+    let payload = decode_base58check(s)?;
+    if payload[..1] == coin.taddress_version {
+        if payload.len() != 21 {
+            return Err(Error::InvalidKey(format!("A t-address has 20 bytes, got {}", payload.len() - 1)));
+        }
+        return Ok(Decoded { kind: KeyKind::TransparentAddress, address: s.to_string() });
+    }
+
+    if payload[..1] == coin.tsecret_prefix {
+        let (sk, compressed) = decode_wif(s, coin)?;
+        let address = pubkey_hash(&sk, compressed).to_base58check(&coin.taddress_version, &[]);
+        return Ok(Decoded { kind: KeyKind::Wif, address });
+    }
+
+    return Err(Error::InvalidKey(format!("Unknown version byte {:02x}. This key or address is for a different coin or network.", payload[0])));
+}
+
+/// Check that `key` (a spending key, viewing key or WIF) is the key for `address`.
+///
+/// For z-addresses only the default address of the key is checked, which is the address hushd and the paper
+/// wallet generator give out for each key. Other diversified addresses of the same key are reported as a mismatch.
+pub fn verify_key_for_address(key: &str, address: &str, coin: &CoinParams) -> Result<Decoded> {
+    let k = decode(key, coin)?;
+    if k.kind.is_address() {
+        return Err(Error::InvalidKey(format!("Expected a key, but got a {}", k.kind.description())));
+    }
+
+    let a = decode(address, coin)?;
+    if !a.kind.is_address() {
+        return Err(Error::InvalidKey(format!("Expected an address, but got a {}", a.kind.description())));
+    }
+
+    if k.address != a.address {
+        return Err(Error::InvalidKey(format!("The key is for {}, not {}", k.address, a.address)));
+    }
+
+    return Ok(k);
+}
+
+/// Decode and parse a Sapling extended spending key
+pub(crate) fn decode_spending_key(s: &str, coin: &CoinParams) -> Result<ExtendedSpendingKey> {
+    let data = decode_bech32_with_hrp(s, &coin.zsecret_prefix)?;
+    read_extended_key(&data, |d| ExtendedSpendingKey::read(d))
+}
+
+/// Decode a WIF private key, returning the secret key and whether its public key is compressed
+pub(crate) fn decode_wif(s: &str, coin: &CoinParams) -> Result<(secp256k1::SecretKey, bool)> {
+    let payload = decode_base58check(s)?;
+
+    if payload[..1] != coin.tsecret_prefix {
+        return Err(Error::InvalidKey(format!("Expected a private key with version {:02x}, got {:02x}", coin.tsecret_prefix[0], payload[0])));
+    }
+
+    // A compressed key has a 0x01 byte after the 32 key bytes
+    let compressed = match payload.len() {
+        33                        => false,
+        34 if payload[33] == 0x01 => true,
+        _                         => return Err(Error::InvalidKey(format!("A private key has 32 bytes, got {}", payload.len() - 1)))
+    };
+
+    let mut sk_bytes = [0u8; 32];
+    sk_bytes.copy_from_slice(&payload[1..33]);
+    let sk = secp256k1::SecretKey::parse(&sk_bytes).map_err(|_| Error::InvalidKey("Not a valid secp256k1 private key".to_string()))?;
+
+    return Ok((sk, compressed));
+}
+
+fn decode_bech32_with_hrp(s: &str, expected_hrp: &str) -> Result<Vec<u8>> {
+    let (hrp, data) = decode_bech32(s)?;
+    if hrp != expected_hrp {
+        return Err(Error::InvalidKey(format!("Expected a key starting with '{}', got '{}'", expected_hrp, hrp)));
+    }
+
+    return Ok(data);
+}
+
+fn read_extended_key<T, F>(data: &[u8], read: F) -> Result<T>
+    where F: FnOnce(&[u8]) -> std::io::Result<T>
+{
+    if data.len() != EXTENDED_KEY_LENGTH {
+        return Err(Error::InvalidKey(format!("An extended key has {} bytes, got {}", EXTENDED_KEY_LENGTH, data.len())));
+    }
+
+    read(data).map_err(|e| Error::InvalidKey(e.to_string()))
+}
+
+fn encode_zaddress(data: &[u8], coin: &CoinParams) -> Result<String> {
+    let encoded = Bech32::new(coin.zaddress_prefix.clone(), data.to_base32()).map_err(|e| Error::KeyEncoding(e.to_string()))?;
+    return Ok(encoded.to_string());
+}
@@ -0,0 +1,459 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use hex;
+use json::object;
+use sha2::{Sha256, Digest};
+use zcash_primitives::zip32::ChildIndex;
+
+use crate::airgap::AirgapStatus;
+use crate::encryption;
+use crate::encryption::{DecryptionKey, EncryptionKind};
+use crate::error::{Error, Result};
+use crate::export;
+use crate::paper::{params, CoinParams, MAX_SEED_LENGTH, MIN_SEED_LENGTH};
+
+/// The version of the JSON wallet file format written by `WalletBatch::to_json`. Files without a version
+/// are the bare list of wallets written by older versions, which can still be read.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Up to this version, the checksum only covered the list of wallets, and not the metadata around it
+const WALLETS_CHECKSUM_VERSION: u32 = 1;
+
+/// The "format" field of the JSON wallet file, to tell it apart from other JSON files
+const FORMAT_NAME: &str = "silentdragonpaper-wallet";
+
+/// The kind of address in a wallet entry
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressKind {
+    /// A shielded Sapling z-address
+    Sapling,
+    /// A transparent t-address
+    Transparent,
+}
+
+impl AddressKind {
+    /// The name used for the "type" field in the JSON form
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AddressKind::Sapling     => "zaddr",
+            AddressKind::Transparent => "taddr",
+        }
+    }
+}
+
+impl FromStr for AddressKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AddressKind> {
+        match s {
+            "zaddr" => Ok(AddressKind::Sapling),
+            "taddr" => Ok(AddressKind::Transparent),
+            _       => Err(Error::InvalidWallet(format!("Unknown address type '{}'", s)))
+        }
+    }
+}
+
+/// The encoded keys for an address
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyMaterial {
+    /// The spending key, as a bech32 extended spending key for z-addresses or a WIF for t-addresses
+    pub private_key : String,
+    /// The bech32 extended full viewing key. Only z-addresses have one.
+    pub viewing_key : Option<String>,
+}
+
+/// How an HD address was derived, so it can be re-derived from the seed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Derivation {
+    pub hd_seed    : Vec<u8>,
+    /// The ZIP-32 path, like m/32'/133'/0'
+    pub path       : String,
+    /// The BIP39 mnemonic the seed was derived from, if it was
+    pub mnemonic   : Option<String>,
+    /// Whether a BIP39 passphrase was needed along with the mnemonic. The passphrase itself isn't stored.
+    pub passphrase : bool,
+}
+
+/// A single address with its keys
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalletEntry {
+    /// The position of this entry among the addresses of the same kind in the batch
+    pub num        : u32,
+    pub kind       : AddressKind,
+    pub address    : String,
+    pub keys       : KeyMaterial,
+    pub derivation : Option<Derivation>,
+}
+
+/// How the addresses in a batch were derived
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DerivationProfile {
+    /// All z-addresses derived from one HD seed. The path of every z-address is recorded in its entry, and is
+    /// m/32'/cointype'/index' unless a path, an account or a start index was given.
+    Zip32Hd,
+    /// A new HD seed for every z-address, each at the start of the path recorded in its entry
+    Zip32NoHd,
+    /// A vanity address, whose diversifier key was found by brute force and can't be re-derived
+    Vanity,
+    /// Existing keys exported from a wallet, like hushd's z_exportkey, dumpprivkey or z_exportwallet
+    Imported,
+}
+
+impl DerivationProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DerivationProfile::Zip32Hd   => "zip32-hd",
+            DerivationProfile::Zip32NoHd => "zip32-nohd",
+            DerivationProfile::Vanity    => "vanity",
+            DerivationProfile::Imported  => "imported",
+        }
+    }
+}
+
+impl FromStr for DerivationProfile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DerivationProfile> {
+        match s {
+            "zip32-hd"   => Ok(DerivationProfile::Zip32Hd),
+            "zip32-nohd" => Ok(DerivationProfile::Zip32NoHd),
+            "vanity"     => Ok(DerivationProfile::Vanity),
+            "imported"   => Ok(DerivationProfile::Imported),
+            _            => Err(Error::InvalidWallet(format!("Unknown derivation profile '{}'", s)))
+        }
+    }
+}
+
+/// Information about how and where a batch was created
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchMetadata {
+    pub coin               : CoinParams,
+    pub derivation_profile : DerivationProfile,
+    pub tool_version       : String,
+    /// Seconds since the unix epoch
+    pub created            : u64,
+    /// Where the randomness for the keys came from
+    pub entropy_source     : String,
+    /// The air-gap check, if one was done before generating the keys
+    pub airgap             : Option<AirgapStatus>,
+}
+
+impl BatchMetadata {
+    /// Metadata for a batch created now with the current coin parameters
+    pub fn new(derivation_profile: DerivationProfile, entropy_source: String) -> BatchMetadata {
+        BatchMetadata {
+            coin            : params(),
+            derivation_profile,
+            tool_version    : format!("silentdragonpaper {}", env!("CARGO_PKG_VERSION")),
+            created         : SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            entropy_source,
+            airgap          : None,
+        }
+    }
+}
+
+/// A batch of addresses generated (or printed) together
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WalletBatch {
+    pub entries  : Vec<WalletEntry>,
+    /// Files written by older versions don't have any metadata
+    pub metadata : Option<BatchMetadata>,
+}
+
+impl Derivation {
+    /// The child indices of the path. Hardened indices are marked with a ', like m/32'/133'/0'
+    pub fn child_indices(&self) -> Result<Vec<ChildIndex>> {
+        let invalid = || Error::InvalidWallet(format!("Invalid derivation path '{}'", self.path));
+
+        let mut parts = self.path.split('/');
+        if parts.next() != Some("m") {
+            return Err(invalid());
+        }
+
+        parts.map(|p| {
+            if p.ends_with('\'') {
+                let i = p[..p.len()-1].parse::<u32>().map_err(|_| invalid())?;
+                if i >= (1 << 31) { Err(invalid()) } else { Ok(ChildIndex::Hardened(i)) }
+            } else {
+                let i = p.parse::<u32>().map_err(|_| invalid())?;
+                if i >= (1 << 31) { Err(invalid()) } else { Ok(ChildIndex::NonHardened(i)) }
+            }
+        }).collect()
+    }
+}
+
+impl WalletEntry {
+    pub fn to_json(&self) -> json::JsonValue {
+        let mut entry = object!{
+            "num"           => self.num,
+            "address"       => self.address.clone(),
+            "private_key"   => self.keys.private_key.clone(),
+            "type"          => self.kind.as_str()
+        };
+
+        if let Some(vk) = &self.keys.viewing_key {
+            entry["viewing_key"] = vk.clone().into();
+        }
+
+        if let Some(d) = &self.derivation {
+            entry["seed"] = object!{
+                "HDSeed"    => hex::encode(&d.hd_seed),
+                "path"      => d.path.clone()
+            };
+            if let Some(m) = &d.mnemonic {
+                entry["seed"]["mnemonic"]   = m.clone().into();
+                entry["seed"]["passphrase"] = d.passphrase.into();
+            }
+        }
+
+        return entry;
+    }
+
+    pub fn from_json(kv: &json::JsonValue) -> Result<WalletEntry> {
+        let num = kv["num"].as_u32().ok_or_else(|| missing_field("num"))?;
+        let kind = get_str(kv, "type")?.parse::<AddressKind>()?;
+
+        let keys = KeyMaterial {
+            private_key : get_str(kv, "private_key")?.to_string(),
+            viewing_key : kv["viewing_key"].as_str().map(|s| s.to_string()),
+        };
+
+        let derivation = if kv.has_key("seed") {
+            let hd_seed = hex::decode(get_str(&kv["seed"], "HDSeed")?)
+                            .map_err(|e| Error::InvalidWallet(format!("Invalid HDSeed: {}", e)))?;
+            if hd_seed.len() < MIN_SEED_LENGTH || hd_seed.len() > MAX_SEED_LENGTH {
+                return Err(Error::InvalidWallet(format!("Invalid HDSeed: a seed has {} to {} bytes, got {}", MIN_SEED_LENGTH, MAX_SEED_LENGTH, hd_seed.len())));
+            }
+
+            Some(Derivation {
+                hd_seed,
+                path       : get_str(&kv["seed"], "path")?.to_string(),
+                mnemonic   : kv["seed"]["mnemonic"].as_str().map(|s| s.to_string()),
+                passphrase : kv["seed"]["passphrase"].as_bool().unwrap_or(false),
+            })
+        } else {
+            None
+        };
+
+        return Ok(WalletEntry { num, kind, address: get_str(kv, "address")?.to_string(), keys, derivation });
+    }
+}
+
+impl WalletBatch {
+    /// The JSON form of the batch. If there is metadata, this is a versioned envelope with the metadata
+    /// around the list of wallets, and a checksum of the whole envelope. Otherwise it's just the list.
+    pub fn to_json(&self) -> json::JsonValue {
+        let mut envelope = match &self.metadata {
+            Some(m) => self.envelope_json(m),
+            None    => return self.wallets_json()
+        };
+
+        envelope["checksum"] = checksum(&envelope).into();
+        return envelope;
+    }
+
+    /// The envelope with the checksum left empty, which is what the checksum is computed over
+    fn envelope_json(&self, m: &BatchMetadata) -> json::JsonValue {
+        let mut envelope = object!{
+            "format"             => FORMAT_NAME,
+            "version"            => FORMAT_VERSION,
+            "coin"               => m.coin.to_json(),
+            "derivation_profile" => m.derivation_profile.as_str(),
+            "tool_version"       => m.tool_version.clone(),
+            "created"            => m.created,
+            "entropy_source"     => m.entropy_source.clone()
+        };
+        if let Some(a) = &m.airgap {
+            envelope["airgap"] = a.to_json();
+        }
+        envelope["checksum"] = "".into();
+        envelope["wallets"]  = self.wallets_json();
+
+        return envelope;
+    }
+
+    fn wallets_json(&self) -> json::JsonValue {
+        json::JsonValue::Array(self.entries.iter().map(|e| e.to_json()).collect())
+    }
+
+    /// The pretty printed JSON form of the batch
+    pub fn to_json_string(&self) -> String {
+        json::stringify_pretty(self.to_json(), 2)
+    }
+
+    /// Read a batch from either the versioned envelope or the bare list of wallets written by older versions
+    pub fn from_json(value: &json::JsonValue) -> Result<WalletBatch> {
+        if value.is_array() {
+            return Ok(WalletBatch { entries: entries_from_json(value)?, metadata: None });
+        }
+
+        if value["format"].as_str() != Some(FORMAT_NAME) {
+            return Err(Error::InvalidWallet("Not a SilentDragonPaper wallet file".to_string()));
+        }
+
+        let version = value["version"].as_u32().ok_or_else(|| missing_field("version"))?;
+        if version > FORMAT_VERSION {
+            return Err(Error::InvalidWallet(format!("Wallet file format version {} is newer than the supported version {}. Please upgrade.", version, FORMAT_VERSION)));
+        }
+
+        let metadata = BatchMetadata {
+            coin               : CoinParams::from_json(&value["coin"])?,
+            derivation_profile : get_str(value, "derivation_profile")?.parse()?,
+            tool_version       : get_str(value, "tool_version")?.to_string(),
+            created            : value["created"].as_u64().ok_or_else(|| missing_field("created"))?,
+            entropy_source     : get_str(value, "entropy_source")?.to_string(),
+            airgap             : if value.has_key("airgap") { Some(AirgapStatus::from_json(&value["airgap"])) } else { None },
+        };
+
+        let batch = WalletBatch { entries: entries_from_json(&value["wallets"])?, metadata: Some(metadata) };
+
+        // The checksum is over our own serialization of the envelope, so it doesn't depend on how the file was
+        // formatted. Version 1 files only have a checksum of the wallets.
+        let expected = match &batch.metadata {
+            Some(m) if version > WALLETS_CHECKSUM_VERSION => checksum(&batch.envelope_json(m)),
+            _                                             => checksum(&batch.wallets_json())
+        };
+        if expected != get_str(value, "checksum")? {
+            return Err(Error::InvalidWallet("Checksum mismatch. The wallet file is corrupted.".to_string()));
+        }
+
+        return Ok(batch);
+    }
+
+    pub fn from_json_str(s: &str) -> Result<WalletBatch> {
+        let value = json::parse(s).map_err(|e| Error::InvalidWallet(e.to_string()))?;
+        WalletBatch::from_json(&value)
+    }
+
+    /// Read a plaintext wallet JSON file, in any of the formats `from_json` accepts
+    pub fn from_file(filename: &str) -> Result<WalletBatch> {
+        WalletBatch::from_file_with_key(filename, |_| Err(Error::InvalidWallet("The wallet file is encrypted".to_string())))
+    }
+
+    /// Read a wallet JSON file that may have been encrypted with the `encryption` module. If it is, `key` is
+    /// called with the kind of encryption, and has to return the passphrase or identity to decrypt it with.
+    /// Any attachment is ignored. Files named *.csv or *.jsonl are read as exports of the `export` module.
+    pub fn from_file_with_key<F>(filename: &str, key: F) -> Result<WalletBatch>
+        where F: FnOnce(EncryptionKind) -> Result<DecryptionKey>
+    {
+        let contents = fs::read_to_string(filename).map_err(|e| Error::Io(filename.to_string(), e))?;
+
+        let extension = Path::new(filename).extension().map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_ref().map(|e| e.as_str()) {
+            Some("csv")   => return export::read_csv(&contents),
+            Some("jsonl") => return export::read_json_lines(&contents),
+            _             => {}
+        };
+
+        let kind = match encryption::encryption_kind(&contents) {
+            Some(k) => k,
+            None    => return WalletBatch::from_json_str(&contents)
+        };
+
+        let decrypted = encryption::decrypt(&contents, &key(kind)?)?;
+        let plaintext = String::from_utf8(decrypted.plaintext).map_err(|e| Error::InvalidWallet(e.to_string()))?;
+        WalletBatch::from_json_str(&plaintext)
+    }
+}
+
+fn entries_from_json(value: &json::JsonValue) -> Result<Vec<WalletEntry>> {
+    if !value.is_array() {
+        return Err(Error::InvalidWallet("Expected a list of wallets".to_string()));
+    }
+
+    value.members().map(WalletEntry::from_json).collect()
+}
+
+/// Hex encoded SHA256 of the compact JSON form of the envelope, or of the wallets in version 1
+fn checksum(value: &json::JsonValue) -> String {
+    hex::encode(Sha256::digest(json::stringify(value.clone()).as_bytes()))
+}
+
+fn missing_field(field: &str) -> Error {
+    Error::InvalidWallet(format!("Missing or invalid field '{}'", field))
+}
+
+/// Get a string field from a wallet entry, or an error saying which field is missing
+fn get_str<'a>(kv: &'a json::JsonValue, field: &str) -> Result<&'a str> {
+    kv[field].as_str().ok_or_else(|| missing_field(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::{generate_wallet, DerivationPath, DEFAULT_SEED_LENGTH};
+
+    #[test]
+    fn test_json_round_trip() {
+        let batch = generate_wallet(false, 2, 2, &DerivationPath::default(), DEFAULT_SEED_LENGTH, 2, b"").unwrap();
+
+        let read = WalletBatch::from_json_str(&batch.to_json_string()).unwrap();
+        assert_eq!(read, batch);
+
+        // The bare list written by older versions
+        let legacy = WalletBatch { entries: batch.entries.clone(), metadata: None };
+        let read = WalletBatch::from_json_str(&json::stringify(legacy.to_json())).unwrap();
+        assert_eq!(read, legacy);
+    }
+
+    #[test]
+    fn test_json_checksum() {
+        let batch = generate_wallet(false, 1, 0, &DerivationPath::default(), DEFAULT_SEED_LENGTH, 2, b"").unwrap();
+        let mut value = batch.to_json();
+
+        value["wallets"][0]["address"] = "zs1tampered".into();
+        match WalletBatch::from_json(&value) {
+            Err(Error::InvalidWallet(e)) => assert!(e.contains("Checksum")),
+            _                            => panic!("Expected a checksum mismatch")
+        }
+
+        // The metadata is covered too
+        let mut value = batch.to_json();
+        value["coin"]["zaddress_prefix"] = "zt".into();
+        assert!(WalletBatch::from_json(&value).is_err());
+
+        let mut value = batch.to_json();
+        value["created"] = 0.into();
+        assert!(WalletBatch::from_json(&value).is_err());
+
+        let mut value = batch.to_json();
+        value["version"] = (FORMAT_VERSION + 1).into();
+        assert!(WalletBatch::from_json(&value).is_err());
+
+        assert!(WalletBatch::from_json_str("{\"format\": \"something else\"}").is_err());
+        assert!(WalletBatch::from_json_str("not json").is_err());
+    }
+
+    #[test]
+    fn test_json_wallets_checksum() {
+        // Version 1 files only have a checksum of the wallets, and can still be read
+        let batch = generate_wallet(false, 1, 1, &DerivationPath::default(), DEFAULT_SEED_LENGTH, 2, b"").unwrap();
+        let mut value = batch.to_json();
+        value["version"]  = WALLETS_CHECKSUM_VERSION.into();
+        value["checksum"] = checksum(&batch.wallets_json()).into();
+        assert_eq!(WalletBatch::from_json(&value).unwrap(), batch);
+
+        // But not with a checksum of the whole envelope, or as version 2 with the old checksum
+        let mut envelope = batch.to_json();
+        envelope["version"] = WALLETS_CHECKSUM_VERSION.into();
+        assert!(WalletBatch::from_json(&envelope).is_err());
+
+        value["version"] = FORMAT_VERSION.into();
+        assert!(WalletBatch::from_json(&value).is_err());
+    }
+
+    #[test]
+    fn test_child_indices() {
+        let d = |path: &str| Derivation { hd_seed: vec![], path: path.to_string(), mnemonic: None, passphrase: false };
+
+        assert_eq!(d("m").child_indices().unwrap(), vec![]);
+        assert_eq!(d("m/32'/133'/7").child_indices().unwrap(),
+                   vec![ChildIndex::Hardened(32), ChildIndex::Hardened(133), ChildIndex::NonHardened(7)]);
+
+        for bad in &["", "32'/133'", "m/", "m/x'", "m/2147483648'", "m/-1", "m//1"] {
+            assert!(d(bad).child_indices().is_err(), "{} should be invalid", bad);
+        }
+    }
+}
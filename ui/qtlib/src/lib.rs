@@ -1,26 +1,48 @@
 use libc::{c_char};
 use std::ffi::{CStr, CString};
-use silentdragonpaperlib::{pdf, paper};
+use silentdragonpaper::{pdf, paper, selftest};
+use silentdragonpaper::pdf::PdfOptions;
+use silentdragonpaper::wallet::WalletBatch;
 
 /**
- * Call into rust to generate a paper wallet. Returns the paper wallet in JSON form. 
+ * Call into rust to generate a paper wallet. Returns the paper wallet in JSON form, or NULL if the wallet
+ * couldn't be generated. Only mainnet is supported, so `is_testnet` is ignored.
  * NOTE: the returned string is owned by rust, so the caller needs to call rust_free_string with it
  * after using it to free it properly
  */ 
 #[no_mangle]
-pub extern fn rust_generate_wallet(is_testnet: bool, zcount: u32, tcount: u32, entropy: *const c_char) -> *mut c_char {
+pub extern fn rust_generate_wallet(_is_testnet: bool, zcount: u32, tcount: u32, entropy: *const c_char) -> *mut c_char {
     let entropy_str = unsafe {
         assert!(!entropy.is_null());
 
         CStr::from_ptr(entropy)
     };
 
-    let c_str = CString::new(paper::generate_wallet(is_testnet, false, zcount, tcount, entropy_str.to_bytes())).unwrap();
-    return c_str.into_raw();
+    if let Err(e) = selftest::known_answer_tests() {
+        eprintln!("{}", e);
+        return std::ptr::null_mut();
+    }
+
+    let wallet = match paper::generate_wallet(false, zcount, tcount, &paper::DerivationPath::default(), paper::DEFAULT_SEED_LENGTH, 1, entropy_str.to_bytes()) {
+        Ok(w)  => w,
+        Err(e) => {
+            eprintln!("{}", e);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match CString::new(wallet.to_json_string()) {
+        Ok(c_str) => return c_str.into_raw(),
+        Err(_)    => return std::ptr::null_mut()
+    }
 }
 
+/**
+ * Save the paper wallet JSON as a PDF. The save dialog has already asked before replacing an existing file,
+ * so the file is overwritten. Returns false if the PDF couldn't be saved.
+ */
 #[no_mangle]
-pub extern fn rust_save_as_pdf(is_testnet: bool, json: *const c_char, file: *const c_char)-> bool {
+pub extern fn rust_save_as_pdf(_is_testnet: bool, json: *const c_char, file: *const c_char)-> bool {
     let json_str = unsafe {
         assert!(!json.is_null());
 
@@ -33,7 +55,20 @@ pub extern fn rust_save_as_pdf(is_testnet: bool, json: *const c_char, file: *con
         CStr::from_ptr(file)
     };
 
-    match pdf::save_to_pdf(is_testnet, json_str.to_str().unwrap(), file_str.to_str().unwrap()) {
+    let (json_str, file_str) = match (json_str.to_str(), file_str.to_str()) {
+        (Ok(j), Ok(f)) => (j, f),
+        _              => return false
+    };
+
+    let wallet = match WalletBatch::from_json_str(json_str) {
+        Ok(w)  => w,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+
+    match pdf::save_to_pdf(&wallet, file_str, &PdfOptions::default(), true) {
         Ok(_)   => return true,
         Err(e)  => {
             eprintln!("{}", e);
@@ -58,6 +58,9 @@ void AddWallet(QString address, QString pk, QWidget* scroll) {
 QString Generate(int zaddrs, int taddrs, QString entropy) {
     // Call into rust to get the addresses
     char* wallet = rust_generate_wallet(false, zaddrs, taddrs, entropy.toStdString().c_str());
+    if (wallet == nullptr)
+        return QString();
+
     QString walletJson(wallet);
     
     // We'll overwrite the privatekeys for safety before sending it back to rust
@@ -82,11 +85,12 @@ void MainWindow::populateWallets() {
         delete children[i];
     }
 
-    // Then add the new wallets
+    // Then add the new wallets. Newer versions wrap the list of wallets in an object with the batch metadata
     auto json = QJsonDocument::fromJson(currentWallets.toUtf8());
-    for (int i=0; i < json.array().size(); i++) {
-        auto addr = json.array()[i].toObject()["address"].toString();
-        auto pk   = json.array()[i].toObject()["private_key"].toString();
+    auto wallets = json.isArray() ? json.array() : json.object()["wallets"].toArray();
+    for (int i=0; i < wallets.size(); i++) {
+        auto addr = wallets[i].toObject()["address"].toString();
+        auto pk   = wallets[i].toObject()["private_key"].toString();
 
         AddWallet(addr, pk, ui->scroll);
     }    